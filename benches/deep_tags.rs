@@ -0,0 +1,115 @@
+//! Time listing and looking up files through tag paths up to 10 tags deep, e.g.
+//! `#t0/#t1/.../#t9`, and through the same tags in reverse order
+
+use fuser::{FileAttr, FileType};
+use ptfs::{SqliteStore, Store};
+use std::time::{Duration, Instant, SystemTime};
+use tokio::runtime::Runtime;
+
+const FILES: u64 = 20_000;
+const TAGS: usize = 10;
+/// Files looked up per depth, spread evenly over the files listed there
+const LOOKUPS: usize = 100;
+/// Listings per path, the fastest is reported
+const RUNS: usize = 5;
+
+fn attr(kind: FileType) -> FileAttr {
+    let now = SystemTime::now();
+    FileAttr {
+        ino: 1,
+        size: 0,
+        blocks: 0,
+        atime: now,
+        mtime: now,
+        ctime: now,
+        crtime: now,
+        kind,
+        perm: 0o755,
+        nlink: 1,
+        uid: 0,
+        gid: 0,
+        rdev: 0,
+        blksize: 0,
+        flags: 0,
+    }
+}
+
+fn main() {
+    let rt = Runtime::new().unwrap();
+    rt.block_on(async {
+        let store = SqliteStore::memory()
+            .await
+            .map_err(|_| "failed creating database")
+            .unwrap();
+        store
+            .migrate()
+            .await
+            .map_err(|_| "failed migrating")
+            .unwrap();
+
+        let mut tids = Vec::with_capacity(TAGS);
+        for t in 0..TAGS {
+            tids.push(
+                store
+                    .create_tag(&format!("#t{t}"))
+                    .await
+                    .map_err(|_| "failed creating tag")
+                    .unwrap(),
+            );
+        }
+        // file i is tagged with the first i % TAGS + 1 tags, so deeper paths list fewer files
+        for i in 0..FILES {
+            let ino = store
+                .insert_attr(&attr(FileType::RegularFile))
+                .await
+                .map_err(|_| "failed creating file")
+                .unwrap();
+            store
+                .insert_name(ino, &format!("file-{i}"))
+                .await
+                .map_err(|_| "failed naming")
+                .unwrap();
+            for tid in &tids[..=i as usize % TAGS] {
+                store
+                    .tag(*tid, ino)
+                    .await
+                    .map_err(|_| "failed tagging")
+                    .unwrap();
+            }
+        }
+
+        let reversed: Vec<u64> = tids.iter().rev().copied().collect();
+        let paths = [1, 2, 5, TAGS]
+            .map(|depth| (format!("{depth} tags deep"), &tids[..depth]))
+            .into_iter()
+            .chain([("reversed".to_string(), &reversed[..])]);
+        for (path, tags) in paths {
+            let mut listed = Duration::MAX;
+            let mut entries = Vec::new();
+            for _ in 0..RUNS {
+                let start = Instant::now();
+                entries = store
+                    .list_entries(0, tags, "#", 0, FILES)
+                    .await
+                    .map_err(|_| "failed listing")
+                    .unwrap();
+                listed = listed.min(start.elapsed());
+            }
+
+            let start = Instant::now();
+            for entry in entries.iter().step_by(entries.len() / LOOKUPS) {
+                store
+                    .lookup_entry(0, tags, &entry.name)
+                    .await
+                    .map_err(|_| "failed looking up")
+                    .unwrap();
+            }
+            let lookups = entries.iter().step_by(entries.len() / LOOKUPS).count() as u32;
+            println!(
+                "{path}: readdir of {} files {listed:?}, lookup {:?} per file",
+                entries.len(),
+                start.elapsed() / lookups
+            );
+        }
+    });
+}
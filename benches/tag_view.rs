@@ -0,0 +1,125 @@
+//! Time `ls -l` on a tag directory listing 100k files: reading the directory in batches the size
+//! of a readdir reply, then looking files up by name
+
+use fuser::{FileAttr, FileType};
+use ptfs::{SqliteStore, Store};
+use std::time::{Instant, SystemTime};
+use tokio::runtime::Runtime;
+
+const FILES: u64 = 100_000;
+/// Entries of a 32KiB readdir reply, the buffer `ls` reads directories with, with short names
+const READDIR_BATCH: u64 = 800;
+/// Files looked up, spread evenly over the directory
+const LOOKUPS: u64 = 1_000;
+
+fn attr(kind: FileType) -> FileAttr {
+    let now = SystemTime::now();
+    FileAttr {
+        ino: 1,
+        size: 0,
+        blocks: 0,
+        atime: now,
+        mtime: now,
+        ctime: now,
+        crtime: now,
+        kind,
+        perm: 0o755,
+        nlink: 1,
+        uid: 0,
+        gid: 0,
+        rdev: 0,
+        blksize: 0,
+        flags: 0,
+    }
+}
+
+fn main() {
+    let rt = Runtime::new().unwrap();
+    rt.block_on(async {
+        let store = SqliteStore::memory()
+            .await
+            .map_err(|_| "failed creating database")
+            .unwrap();
+        store
+            .migrate()
+            .await
+            .map_err(|_| "failed migrating")
+            .unwrap();
+
+        store
+            .insert_attr_if_missing(&attr(FileType::Directory))
+            .await
+            .map_err(|_| "failed creating root")
+            .unwrap();
+        let dir = store
+            .insert_attr(&attr(FileType::Directory))
+            .await
+            .map_err(|_| "failed creating tag directory")
+            .unwrap();
+        store
+            .insert_name(dir, "#tag")
+            .await
+            .map_err(|_| "failed naming")
+            .unwrap();
+        store
+            .add_to_dir(1, dir)
+            .await
+            .map_err(|_| "failed adding")
+            .unwrap();
+        let tid = store
+            .create_tag("#tag")
+            .await
+            .map_err(|_| "failed creating tag")
+            .unwrap();
+        store
+            .tag(tid, dir)
+            .await
+            .map_err(|_| "failed tagging")
+            .unwrap();
+
+        for i in 0..FILES {
+            let ino = store
+                .insert_attr(&attr(FileType::RegularFile))
+                .await
+                .map_err(|_| "failed creating file")
+                .unwrap();
+            store
+                .insert_name(ino, &format!("file-{i}"))
+                .await
+                .map_err(|_| "failed naming")
+                .unwrap();
+            store
+                .tag(tid, ino)
+                .await
+                .map_err(|_| "failed tagging")
+                .unwrap();
+        }
+
+        let start = Instant::now();
+        let (mut after, mut listed) = (0, 0);
+        loop {
+            let entries = store
+                .list_entries(dir, &[tid], "#", after, READDIR_BATCH)
+                .await
+                .map_err(|_| "failed listing")
+                .unwrap();
+            let Some(last) = entries.last() else {
+                break;
+            };
+            after = last.attr.ino;
+            listed += entries.len() as u64;
+        }
+        assert_eq!(listed, FILES);
+        println!("readdir of {FILES} files: {:?}", start.elapsed());
+
+        let start = Instant::now();
+        for i in (0..FILES).step_by((FILES / LOOKUPS) as usize) {
+            store
+                .lookup_entry(dir, &[tid], &format!("file-{i}"))
+                .await
+                .map_err(|_| "failed looking up")
+                .unwrap();
+        }
+        println!("lookup: {:?} per file", start.elapsed() / LOOKUPS as u32);
+    });
+}
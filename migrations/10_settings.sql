@@ -0,0 +1,9 @@
+CREATE TABLE IF NOT EXISTS settings (
+  key TEXT PRIMARY KEY,
+  value TEXT
+);
+
+-- codec of bytes, see `Compression`, and their length once decompressed
+ALTER TABLE chunks ADD COLUMN codec INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE chunks ADD COLUMN len INTEGER;
+UPDATE chunks SET len = LENGTH(bytes);
@@ -0,0 +1,4 @@
+-- count blocks of stored pages, holes aren't stored
+UPDATE file_attrs SET blocks = (
+  SELECT COUNT(*) FROM file_contents WHERE file_contents.ino = file_attrs.ino
+) * (SELECT page_size FROM pragma_page_size()) / 512;
@@ -0,0 +1,2 @@
+-- pages used to be sized by the database's page size, keep it for existing databases
+INSERT OR IGNORE INTO settings (key, value) SELECT 'chunk_size', page_size FROM pragma_page_size();
@@ -0,0 +1,2 @@
+-- whether bytes are stored in the blob directory instead of the database, named by hash
+ALTER TABLE chunks ADD COLUMN blob INTEGER NOT NULL DEFAULT 0;
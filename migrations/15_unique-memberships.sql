@@ -0,0 +1,37 @@
+-- drop duplicate tag associations and directory memberships, and keep them from being inserted
+-- again, tables are recreated since SQLite can't add primary keys to them
+CREATE TABLE associated_tags_unique (
+  tid INTEGER NOT NULL,
+  ino INTEGER NOT NULL,
+  PRIMARY KEY (tid, ino),
+  FOREIGN KEY (tid) REFERENCES tags(tid)
+    ON DELETE CASCADE
+    ON UPDATE CASCADE,
+  FOREIGN KEY (ino) REFERENCES file_attrs(ino)
+    ON DELETE CASCADE
+    ON UPDATE CASCADE
+);
+INSERT OR IGNORE INTO associated_tags_unique (tid, ino)
+SELECT tid, ino FROM associated_tags WHERE tid IS NOT NULL AND ino IS NOT NULL;
+DROP TABLE associated_tags;
+ALTER TABLE associated_tags_unique RENAME TO associated_tags;
+-- (tid, ino) is covered by the primary key
+CREATE INDEX IF NOT EXISTS associated_tags_ino ON associated_tags (ino);
+
+CREATE TABLE dir_contents_unique (
+  dir_ino INTEGER NOT NULL,
+  cnt_ino INTEGER NOT NULL,
+  PRIMARY KEY (dir_ino, cnt_ino),
+  FOREIGN KEY (dir_ino) REFERENCES file_attrs(ino)
+    ON UPDATE CASCADE
+    ON DELETE CASCADE,
+  FOREIGN KEY (cnt_ino) REFERENCES file_attrs(ino)
+    ON UPDATE CASCADE
+    ON DELETE CASCADE
+);
+INSERT OR IGNORE INTO dir_contents_unique (dir_ino, cnt_ino)
+SELECT dir_ino, cnt_ino FROM dir_contents WHERE dir_ino IS NOT NULL AND cnt_ino IS NOT NULL;
+DROP TABLE dir_contents;
+ALTER TABLE dir_contents_unique RENAME TO dir_contents;
+-- (dir_ino, cnt_ino) is covered by the primary key
+CREATE INDEX IF NOT EXISTS dir_contents_cnt_ino ON dir_contents (cnt_ino);
@@ -0,0 +1,3 @@
+-- chunks are garbage collected after every change of file contents, which only has to find the
+-- few chunks left without references
+CREATE INDEX IF NOT EXISTS chunks_unreferenced ON chunks (refs) WHERE refs <= 0;
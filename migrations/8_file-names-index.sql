@@ -0,0 +1 @@
+CREATE INDEX IF NOT EXISTS file_names_name ON file_names (name COLLATE NOCASE);
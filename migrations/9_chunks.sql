@@ -0,0 +1,27 @@
+CREATE TABLE IF NOT EXISTS chunks (
+  hash BLOB PRIMARY KEY,
+  bytes BLOB,
+  refs INTEGER NOT NULL DEFAULT 0
+);
+
+-- pages reference chunks by hash, bytes is only kept for pages written before chunks existed,
+-- which are moved into chunks on mount
+ALTER TABLE file_contents ADD COLUMN hash BLOB REFERENCES chunks(hash);
+
+CREATE TRIGGER IF NOT EXISTS file_contents_ref AFTER INSERT ON file_contents
+WHEN NEW.hash IS NOT NULL
+BEGIN
+  UPDATE chunks SET refs = refs + 1 WHERE hash = NEW.hash;
+END;
+
+CREATE TRIGGER IF NOT EXISTS file_contents_reref AFTER UPDATE OF hash ON file_contents
+BEGIN
+  UPDATE chunks SET refs = refs - 1 WHERE hash = OLD.hash;
+  UPDATE chunks SET refs = refs + 1 WHERE hash = NEW.hash;
+END;
+
+CREATE TRIGGER IF NOT EXISTS file_contents_unref AFTER DELETE ON file_contents
+WHEN OLD.hash IS NOT NULL
+BEGIN
+  UPDATE chunks SET refs = refs - 1 WHERE hash = OLD.hash;
+END;
@@ -0,0 +1,100 @@
+-- schema of the SQLite migrations as of 13_blobs, for databases in a PostgreSQL server
+CREATE TABLE IF NOT EXISTS file_attrs (
+  ino BIGINT GENERATED BY DEFAULT AS IDENTITY PRIMARY KEY,
+  size BIGINT,
+  blocks BIGINT,
+  atime BIGINT,
+  mtime BIGINT,
+  ctime BIGINT,
+  crtime BIGINT,
+  kind BIGINT,
+  perm BIGINT,
+  nlink BIGINT,
+  uid BIGINT,
+  gid BIGINT,
+  rdev BIGINT,
+  blksize BIGINT,
+  flags BIGINT
+);
+
+CREATE TABLE IF NOT EXISTS file_names (
+  ino BIGINT PRIMARY KEY,
+  name TEXT,
+  FOREIGN KEY (ino) REFERENCES file_attrs(ino)
+    ON UPDATE CASCADE
+    ON DELETE CASCADE
+);
+CREATE INDEX IF NOT EXISTS file_names_name ON file_names (name);
+
+CREATE OR REPLACE VIEW readdir_rows AS
+SELECT file_attrs.*, file_names.name FROM file_attrs
+INNER JOIN file_names ON file_attrs.ino = file_names.ino;
+
+CREATE TABLE IF NOT EXISTS tags (
+  tid BIGINT GENERATED BY DEFAULT AS IDENTITY PRIMARY KEY,
+  name TEXT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS associated_tags (
+  tid BIGINT,
+  ino BIGINT,
+  FOREIGN KEY (tid) REFERENCES tags(tid)
+    ON DELETE CASCADE
+    ON UPDATE CASCADE,
+  FOREIGN KEY (ino) REFERENCES file_attrs(ino)
+    ON DELETE CASCADE
+    ON UPDATE CASCADE
+);
+
+CREATE TABLE IF NOT EXISTS dir_contents (
+  dir_ino BIGINT,
+  cnt_ino BIGINT,
+  FOREIGN KEY (dir_ino) REFERENCES file_attrs(ino)
+    ON UPDATE CASCADE
+    ON DELETE CASCADE,
+  FOREIGN KEY (cnt_ino) REFERENCES file_attrs(ino)
+    ON UPDATE CASCADE
+    ON DELETE CASCADE
+);
+
+-- codec of bytes, see `Compression`, their length once decompressed, and whether they're stored in
+-- the blob directory instead
+CREATE TABLE IF NOT EXISTS chunks (
+  hash BYTEA PRIMARY KEY,
+  bytes BYTEA,
+  refs BIGINT NOT NULL DEFAULT 0,
+  codec SMALLINT NOT NULL DEFAULT 0,
+  len BIGINT,
+  blob BOOLEAN NOT NULL DEFAULT FALSE
+);
+
+-- there were never pages stored before chunks, so pages only reference them
+CREATE TABLE IF NOT EXISTS file_contents (
+  ino BIGINT,
+  page BIGINT,
+  hash BYTEA REFERENCES chunks(hash),
+  PRIMARY KEY (ino, page),
+  FOREIGN KEY (ino) REFERENCES file_attrs(ino)
+    ON UPDATE CASCADE
+    ON DELETE CASCADE
+);
+
+CREATE OR REPLACE FUNCTION file_contents_refs() RETURNS TRIGGER AS $$
+BEGIN
+  IF TG_OP IN ('UPDATE', 'DELETE') THEN
+    UPDATE chunks SET refs = refs - 1 WHERE hash = OLD.hash;
+  END IF;
+  IF TG_OP IN ('INSERT', 'UPDATE') THEN
+    UPDATE chunks SET refs = refs + 1 WHERE hash = NEW.hash;
+  END IF;
+  RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE OR REPLACE TRIGGER file_contents_refs AFTER INSERT OR DELETE OR UPDATE OF hash
+ON file_contents FOR EACH ROW EXECUTE FUNCTION file_contents_refs();
+
+CREATE TABLE IF NOT EXISTS settings (
+  key TEXT PRIMARY KEY,
+  value TEXT
+);
@@ -0,0 +1,11 @@
+-- indexes of the SQLite migration 14_indexes, file_names(name) is already indexed
+UPDATE associated_tags SET tid = (
+  SELECT MIN(tid) FROM tags WHERE name = (SELECT name FROM tags WHERE tid = associated_tags.tid)
+);
+DELETE FROM tags WHERE tid NOT IN (SELECT MIN(tid) FROM tags GROUP BY name);
+CREATE UNIQUE INDEX IF NOT EXISTS tags_name ON tags (name);
+
+CREATE INDEX IF NOT EXISTS associated_tags_tid_ino ON associated_tags (tid, ino);
+CREATE INDEX IF NOT EXISTS associated_tags_ino ON associated_tags (ino);
+CREATE INDEX IF NOT EXISTS dir_contents_dir_ino_cnt_ino ON dir_contents (dir_ino, cnt_ino);
+CREATE INDEX IF NOT EXISTS dir_contents_cnt_ino ON dir_contents (cnt_ino);
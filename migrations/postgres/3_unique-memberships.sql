@@ -0,0 +1,13 @@
+-- drop duplicate tag associations and directory memberships, and keep them from being inserted
+-- again, as in the SQLite migration 15_unique-memberships
+DELETE FROM associated_tags WHERE tid IS NULL OR ino IS NULL;
+DELETE FROM associated_tags AS a USING associated_tags AS b
+WHERE a.ctid > b.ctid AND a.tid = b.tid AND a.ino = b.ino;
+ALTER TABLE associated_tags ADD PRIMARY KEY (tid, ino);
+DROP INDEX IF EXISTS associated_tags_tid_ino;
+
+DELETE FROM dir_contents WHERE dir_ino IS NULL OR cnt_ino IS NULL;
+DELETE FROM dir_contents AS a USING dir_contents AS b
+WHERE a.ctid > b.ctid AND a.dir_ino = b.dir_ino AND a.cnt_ino = b.cnt_ino;
+ALTER TABLE dir_contents ADD PRIMARY KEY (dir_ino, cnt_ino);
+DROP INDEX IF EXISTS dir_contents_dir_ino_cnt_ino;
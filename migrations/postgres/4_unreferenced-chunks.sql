@@ -0,0 +1,2 @@
+-- index of the SQLite migration 16_unreferenced-chunks
+CREATE INDEX IF NOT EXISTS chunks_unreferenced ON chunks (refs) WHERE refs <= 0;
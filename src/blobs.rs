@@ -0,0 +1,142 @@
+use crate::{PTFS, db_helpers::types::DBError, store::Store};
+use std::{
+    collections::HashSet,
+    fs::{self, File, TryLockError},
+    io::{ErrorKind as IoErrorKind, Write},
+    path::PathBuf,
+};
+
+/// File in the blob directory that mounts lock shared and repairs exclusively, see
+/// [`PTFS::lock_blobs`]
+const LOCK_NAME: &str = "lock";
+
+/// Inconsistencies between chunks and the blob directory found by [`PTFS::fsck`]
+#[derive(Debug, Default)]
+pub struct FsckReport {
+    /// Hex hashes of chunks whose blob file is missing
+    pub missing_blobs: Vec<String>,
+    /// Blob files of no chunk, removed when repairing
+    pub orphan_blobs: Vec<PathBuf>,
+}
+
+impl<S: Store> PTFS<S> {
+    /// Path of the blob file of chunk `hash`, spread into directories by the first hash byte
+    pub(crate) fn blob_path(&self, hash: &[u8]) -> PathBuf {
+        let name = hex::encode(hash);
+        self.blob_dir.join(&name[..2]).join(name)
+    }
+
+    /// Lock the blob directory, shared by mounts writing blobs or exclusively to remove blobs of
+    /// no chunk, failing with `EWOULDBLOCK` if it's locked the other way
+    ///
+    /// Mounts write blobs before committing their chunks, which a repair would otherwise remove.
+    /// The lock is held until the returned file is dropped, `None` if there are no blobs to lock.
+    pub fn lock_blobs(&self, exclusive: bool) -> Result<Option<File>, DBError> {
+        if self.blob_dir.as_os_str().is_empty()
+            || (self.settings.blob_threshold.is_none() && !self.blob_dir.is_dir())
+        {
+            return Ok(None);
+        }
+        fs::create_dir_all(&self.blob_dir)?;
+        let file = File::options()
+            .create(true)
+            .write(true)
+            .truncate(false)
+            .open(self.blob_dir.join(LOCK_NAME))?;
+        let locked = match exclusive {
+            true => file.try_lock(),
+            false => file.try_lock_shared(),
+        };
+        match locked {
+            Ok(()) => Ok(Some(file)),
+            Err(TryLockError::WouldBlock) => {
+                Err(std::io::Error::from_raw_os_error(libc::EWOULDBLOCK).into())
+            }
+            Err(TryLockError::Error(e)) => Err(e.into()),
+        }
+    }
+
+    /// Write the blob file of chunk `hash`, which is synced to disk before its chunk is committed
+    pub(crate) fn write_blob(&self, hash: &[u8], bytes: &[u8]) -> Result<(), DBError> {
+        let path = self.blob_path(hash);
+        let dir = path.parent().unwrap_or(&self.blob_dir);
+        let new_dir = !dir.is_dir();
+        if new_dir {
+            fs::create_dir_all(dir)?;
+        }
+        let tmp_path = path.with_extension("tmp");
+        // a partially written blob is never named by its hash
+        let mut file = File::create(&tmp_path)?;
+        file.write_all(bytes)?;
+        file.sync_all()?;
+        fs::rename(tmp_path, &path)?;
+        // the rename, and the directory it's in if it's new, only persist once their parents are
+        File::open(dir)?.sync_all()?;
+        if new_dir {
+            File::open(&self.blob_dir)?.sync_all()?;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn read_blob(&self, hash: &[u8]) -> Result<Vec<u8>, DBError> {
+        Ok(fs::read(self.blob_path(hash))?)
+    }
+
+    pub(crate) fn remove_blob(&self, hash: &[u8]) -> Result<(), DBError> {
+        match fs::remove_file(self.blob_path(hash)) {
+            Err(e) if e.kind() != IoErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+
+    /// Check that every chunk stored as a blob has its blob file and that every blob file
+    /// belongs to a chunk
+    ///
+    /// Repairing collects unreferenced chunks and removes blob files of no chunk, e.g. left by a
+    /// crash between writing a blob and committing its chunk. Missing blobs can't be repaired.
+    /// Repairs fail while the database is mounted, see [`PTFS::lock_blobs`].
+    pub async fn fsck(&self, repair: bool) -> Result<FsckReport, DBError> {
+        let _lock = match repair {
+            true => self.lock_blobs(true)?,
+            false => None,
+        };
+        if repair {
+            self.gc_chunks().await?;
+        }
+        let mut report = FsckReport::default();
+
+        let mut blob_paths = HashSet::new();
+        for hash in self.store.get_blob_hashes().await? {
+            let path = self.blob_path(&hash);
+            if !path.is_file() {
+                report.missing_blobs.push(hex::encode(&hash));
+            }
+            blob_paths.insert(path);
+        }
+
+        let entries = match fs::read_dir(&self.blob_dir) {
+            Err(e) if e.kind() == IoErrorKind::NotFound => return Ok(report),
+            entries => entries?,
+        };
+        for entry in entries {
+            let dir = entry?.path();
+            let paths = match dir.is_dir() {
+                true => fs::read_dir(&dir)?
+                    .map(|entry| entry.map(|entry| entry.path()))
+                    .collect::<Result<Vec<_>, _>>()?,
+                false if dir == self.blob_dir.join(LOCK_NAME) => vec![],
+                false => vec![dir],
+            };
+            for path in paths {
+                if blob_paths.contains(&path) {
+                    continue;
+                }
+                if repair {
+                    fs::remove_file(&path)?;
+                }
+                report.orphan_blobs.push(path);
+            }
+        }
+        Ok(report)
+    }
+}
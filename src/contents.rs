@@ -0,0 +1,320 @@
+use crate::{
+    PTFS,
+    db_helpers::types::DBError,
+    settings::Compression,
+    store::{Chunk, Store},
+};
+use std::cmp::{max, min};
+
+/// Most bytes copied by a single [`PTFS::copy_data`], which keeps the amount within a `u32`
+pub const COPY_MAX: u64 = 1 << 30;
+/// Pages read into memory at once when copying bytes that can't be copied as whole pages
+const COPY_BATCH_PAGES: u64 = 256;
+/// Pages stored before chunks existed that are converted at once, see
+/// [`PTFS::convert_legacy_pages`]
+const LEGACY_BATCH_PAGES: u64 = 256;
+
+impl<S: Store> PTFS<S> {
+    /// Hash identifying a chunk by its content, or a random one on encrypted databases that don't
+    /// deduplicate pages, see [`crate::Encryption::dedup`]
+    fn chunk_hash(&self, bytes: &[u8]) -> Vec<u8> {
+        let dedup = self.settings.encryption.as_ref().is_none_or(|e| e.dedup);
+        match &self.cipher {
+            Some(cipher) if dedup => cipher.chunk_hash(bytes),
+            Some(_) => rand::random::<[u8; 32]>().to_vec(),
+            None => blake3::hash(bytes).as_bytes().to_vec(),
+        }
+    }
+
+    /// Get the bytes of page `page` of `ino`, `None` if the page isn't stored
+    pub(crate) async fn get_page(&self, ino: u64, page: u64) -> Result<Option<Vec<u8>>, DBError> {
+        let Some(chunk) = self.store.get_chunk(ino, page).await? else {
+            return Ok(None);
+        };
+        let mut bytes = match chunk.blob {
+            true => self.read_blob(&chunk.hash)?,
+            false => chunk.bytes.ok_or(sqlx::Error::RowNotFound)?,
+        };
+        if let Some(cipher) = &self.cipher {
+            bytes = cipher.decrypt_chunk(&chunk.hash, &bytes)?;
+        }
+        Ok(Some(
+            Compression::from_codec(chunk.codec)?.decompress(bytes, chunk.len.try_into()?)?,
+        ))
+    }
+
+    /// Hash `bytes`, along with a new chunk of them if no page has the same content, see
+    /// [`PTFS::chunk_hash`]
+    ///
+    /// New chunks are compressed with the database's codec, unless that doesn't make them smaller,
+    /// and then encrypted on encrypted databases. Chunks larger than the blob threshold are
+    /// stored in the blob directory.
+    async fn new_chunk(&self, bytes: &[u8]) -> Result<(Vec<u8>, Option<Chunk>), DBError> {
+        let hash = self.chunk_hash(bytes);
+        if self.store.has_chunk(&hash).await? {
+            return Ok((hash, None));
+        }
+        let compression = self.settings.compression;
+        let (codec, mut stored) = match compression.compress(bytes) {
+            Some(compressed) => (compression, compressed),
+            None => (Compression::None, bytes.to_vec()),
+        };
+        if let Some(cipher) = &self.cipher {
+            stored = cipher.encrypt_chunk(&hash, &stored);
+        }
+        let blob = self
+            .settings
+            .blob_threshold
+            .is_some_and(|threshold| stored.len() as u64 > threshold);
+        if blob {
+            self.write_blob(&hash, &stored)?;
+        }
+        let chunk = Chunk {
+            hash: hash.clone(),
+            bytes: (!blob).then_some(stored),
+            codec: codec as u8,
+            len: bytes.len().try_into()?,
+            blob,
+        };
+        Ok((hash, Some(chunk)))
+    }
+
+    /// Set page `page` of `ino` to `bytes`, see [`PTFS::new_chunk`]
+    ///
+    /// The chunk previously referenced by the page is left for [`PTFS::gc_chunks`].
+    pub(crate) async fn put_page(&self, ino: u64, page: u64, bytes: &[u8]) -> Result<(), DBError> {
+        let (hash, new_chunk) = self.new_chunk(bytes).await?;
+        self.store.put_page(ino, page, &hash, new_chunk).await
+    }
+
+    /// Get the size of `ino` in bytes
+    pub(crate) async fn get_file_size(&self, ino: u64) -> Result<u64, DBError> {
+        Ok(self.store.get_attr(ino).await?.size)
+    }
+
+    /// Read up to `size` bytes of `ino` starting at `offset`, stopping at the end of the file
+    ///
+    /// Pages that aren't stored read as zeros.
+    pub(crate) async fn read_data(
+        &self,
+        ino: u64,
+        offset: u64,
+        size: u64,
+    ) -> Result<Vec<u8>, DBError> {
+        let end = min(offset.saturating_add(size), self.get_file_size(ino).await?);
+        if end <= offset {
+            return Ok(vec![]);
+        }
+        let page_size = self.settings.chunk_size;
+
+        let mut data = Vec::with_capacity((end - offset).try_into()?);
+        for page in offset / page_size..=(end - 1) / page_size {
+            let page_start = page * page_size;
+            let from: usize = (max(offset, page_start) - page_start).try_into()?;
+            let to: usize = (min(end, page_start + page_size) - page_start).try_into()?;
+            let mut bytes = self.get_page(ino, page).await?.unwrap_or_default();
+            bytes.resize(max(bytes.len(), to), 0);
+            data.extend_from_slice(&bytes[from..to]);
+        }
+        Ok(data)
+    }
+
+    /// Write `data` to `ino` at `offset`, growing the file if it's written past its end
+    ///
+    /// Only the pages `data` is written to are stored, so writing past the end of the file leaves
+    /// a hole instead of padding it with zeros.
+    pub(crate) async fn write_data(
+        &self,
+        ino: u64,
+        offset: u64,
+        data: &[u8],
+    ) -> Result<(), DBError> {
+        if data.is_empty() {
+            return Ok(());
+        }
+        let page_size = self.settings.chunk_size;
+        let usize_page_size: usize = page_size.try_into()?;
+        let end = offset + u64::try_from(data.len())?;
+
+        for page in offset / page_size..=(end - 1) / page_size {
+            let page_start = page * page_size;
+            let from = max(offset, page_start);
+            let to = min(end, page_start + page_size);
+            let slice = &data[(from - offset).try_into()?..(to - offset).try_into()?];
+            let paged_offset: usize = (from - page_start).try_into()?;
+
+            let mut bytes = match slice.len() == usize_page_size {
+                true => vec![],
+                false => self.get_page(ino, page).await?.unwrap_or_default(),
+            };
+            bytes.resize(max(bytes.len(), paged_offset + slice.len()), 0);
+            bytes[paged_offset..paged_offset + slice.len()].copy_from_slice(slice);
+            self.put_page(ino, page, &bytes).await?;
+        }
+
+        self.store.grow_size(ino, end).await?;
+        self.update_blocks(ino).await?;
+        self.gc_chunks().await
+    }
+
+    /// Copy up to `len` bytes of `ino_in` at `offset_in` into `ino_out` at `offset_out`,
+    /// stopping at the end of `ino_in` or after [`COPY_MAX`] bytes, and return the amount of
+    /// bytes copied
+    ///
+    /// When both offsets are page aligned, whole pages are copied by referencing their chunks,
+    /// otherwise the bytes are read and written.
+    pub(crate) async fn copy_data(
+        &self,
+        ino_in: u64,
+        offset_in: u64,
+        ino_out: u64,
+        offset_out: u64,
+        len: u64,
+    ) -> Result<u64, DBError> {
+        let in_size = self.get_file_size(ino_in).await?;
+        let len = min(min(len, COPY_MAX), in_size.saturating_sub(offset_in));
+        if len == 0 {
+            return Ok(0);
+        }
+        let page_size = self.settings.chunk_size;
+
+        let mut copied = 0;
+        let pages = len / page_size;
+        if offset_in.is_multiple_of(page_size) && offset_out.is_multiple_of(page_size) && pages > 0
+        {
+            self.store
+                .copy_pages(
+                    ino_in,
+                    offset_in / page_size,
+                    ino_out,
+                    offset_out / page_size,
+                    pages,
+                )
+                .await?;
+            copied = pages * page_size;
+        }
+
+        while copied < len {
+            let data = self
+                .read_data(
+                    ino_in,
+                    offset_in + copied,
+                    min(len - copied, COPY_BATCH_PAGES * page_size),
+                )
+                .await?;
+            self.write_data(ino_out, offset_out + copied, &data).await?;
+            copied += u64::try_from(data.len())?;
+        }
+
+        self.store.grow_size(ino_out, offset_out + copied).await?;
+        self.update_blocks(ino_out).await?;
+        self.gc_chunks().await?;
+        Ok(copied)
+    }
+
+    /// Set the blocks of `ino` to the 512 byte blocks taken by its stored pages, holes aren't
+    /// counted
+    pub(crate) async fn update_blocks(&self, ino: u64) -> Result<(), DBError> {
+        let pages = self.store.count_pages(ino).await?;
+        self.store
+            .set_blocks(ino, pages * self.settings.chunk_size / 512)
+            .await
+    }
+
+    /// Zero `len` bytes of `ino` from `offset` without changing its size, dropping the pages
+    /// that are entirely zeroed
+    pub(crate) async fn zero_range(&self, ino: u64, offset: u64, len: u64) -> Result<(), DBError> {
+        // bytes past the end of the file aren't stored
+        let end = min(offset.saturating_add(len), self.get_file_size(ino).await?);
+        if end <= offset {
+            return Ok(());
+        }
+        let page_size = self.settings.chunk_size;
+
+        self.store
+            .delete_pages(ino, offset.div_ceil(page_size), Some(end / page_size))
+            .await?;
+
+        // zero the partially covered first and last pages
+        let mut partial_pages = vec![offset / page_size, (end - 1) / page_size];
+        partial_pages.dedup();
+        for page in partial_pages {
+            let page_start = page * page_size;
+            let Some(mut bytes) = self.get_page(ino, page).await? else {
+                continue;
+            };
+            let from: usize = (max(offset, page_start) - page_start).try_into()?;
+            let to: usize = (min(end, page_start + page_size) - page_start).try_into()?;
+            if from < bytes.len() {
+                let to = min(to, bytes.len());
+                bytes[from..to].fill(0);
+                self.put_page(ino, page, &bytes).await?;
+            }
+        }
+
+        self.update_blocks(ino).await?;
+        self.gc_chunks().await
+    }
+
+    /// Find the offset of the next data (`data`) or hole (`!data`) of `ino` from `offset`, `None`
+    /// if there's none before the end of the file
+    ///
+    /// Holes are pages that aren't stored, plus the implicit hole at the end of the file.
+    pub(crate) async fn seek_data(
+        &self,
+        ino: u64,
+        offset: u64,
+        data: bool,
+    ) -> Result<Option<u64>, DBError> {
+        let size = self.get_file_size(ino).await?;
+        if offset >= size {
+            return Ok(None);
+        }
+        let page_size = self.settings.chunk_size;
+        let page = offset / page_size;
+
+        if data {
+            let data_page = self.store.next_page(ino, page).await?;
+            return Ok(data_page.map(|data_page| max(offset, data_page * page_size)));
+        }
+
+        Ok(Some(match self.store.end_of_pages(ino, page).await? {
+            Some(hole_page) => min(hole_page * page_size, size),
+            None => offset,
+        }))
+    }
+
+    /// Delete chunks that are no longer referenced by any page, along with their blobs
+    pub(crate) async fn gc_chunks(&self) -> Result<(), DBError> {
+        let deleted = self.store.delete_unreferenced_chunks().await?;
+        for (hash, _) in deleted.iter().filter(|(_, blob)| *blob) {
+            self.remove_blob(hash)?;
+        }
+        Ok(())
+    }
+
+    /// Move pages stored before the chunk store into chunks
+    ///
+    /// Pages are converted in batches of [`LEGACY_BATCH_PAGES`], each committed on its own, so
+    /// only one batch is in memory and an interrupted conversion resumes on the next mount.
+    pub(crate) async fn convert_legacy_pages(&self) -> Result<(), DBError> {
+        let mut after = (0, 0);
+        loop {
+            let pages = self
+                .store
+                .get_legacy_pages(after, LEGACY_BATCH_PAGES)
+                .await?;
+            let Some((ino, page, _)) = pages.last() else {
+                return Ok(());
+            };
+            after = (*ino, *page);
+
+            let mut converted = Vec::with_capacity(pages.len());
+            for (ino, page, bytes) in pages {
+                let (hash, new_chunk) = self.new_chunk(&bytes).await?;
+                converted.push((ino, page, hash, new_chunk));
+            }
+            self.store.put_legacy_pages(converted).await?;
+        }
+    }
+}
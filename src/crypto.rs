@@ -0,0 +1,120 @@
+use crate::db_helpers::types::ConvError;
+use chacha20poly1305::{
+    XChaCha20Poly1305, XNonce,
+    aead::{Aead, KeyInit},
+};
+
+pub const KEY_LEN: usize = 32;
+/// Length of the salt keys are derived from passphrases with
+pub const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 24;
+
+/// Plaintext encrypted into `key_check`, telling whether a key is the database's
+const KEY_CHECK: &str = "ptfs";
+
+/// Keys encrypting names and file contents with XChaCha20-Poly1305
+///
+/// Names are encrypted deterministically, their nonces are derived from a keyed hash of the
+/// plaintext, so that they can be looked up without decrypting the database. As a result the
+/// database reveals which names are equal, but not what they are. Chunks are encrypted with their
+/// hash as the nonce, which is random unless the database deduplicates pages, see
+/// [`crate::Encryption::dedup`].
+pub struct Cipher {
+    name_nonce_key: [u8; KEY_LEN],
+    name_cipher: XChaCha20Poly1305,
+    chunk_hash_key: [u8; KEY_LEN],
+    chunk_cipher: XChaCha20Poly1305,
+}
+
+impl std::fmt::Debug for Cipher {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Cipher")
+    }
+}
+
+/// Make a nonce of the first bytes of `bytes`, which has to be at least [`NONCE_LEN`] long
+fn to_nonce(bytes: &[u8]) -> XNonce {
+    let mut nonce = [0u8; NONCE_LEN];
+    nonce.copy_from_slice(&bytes[..NONCE_LEN]);
+    nonce.into()
+}
+
+impl Cipher {
+    pub fn from_key(key: &[u8; KEY_LEN]) -> Cipher {
+        let subkey = |context| blake3::derive_key(context, key);
+        Cipher {
+            name_nonce_key: subkey("ptfs name nonces"),
+            name_cipher: XChaCha20Poly1305::new(&subkey("ptfs names").into()),
+            chunk_hash_key: subkey("ptfs chunk hashes"),
+            chunk_cipher: XChaCha20Poly1305::new(&subkey("ptfs chunks").into()),
+        }
+    }
+
+    /// Derive the key from a passphrase with Argon2id
+    pub fn from_passphrase(passphrase: &[u8], salt: &[u8]) -> Result<Cipher, ConvError> {
+        let mut key = [0u8; KEY_LEN];
+        argon2::Argon2::default()
+            .hash_password_into(passphrase, salt, &mut key)
+            .map_err(|_| ConvError::Crypto)?;
+        Ok(Cipher::from_key(&key))
+    }
+
+    /// Derive the key from the contents of a key file
+    pub fn from_key_file(contents: &[u8]) -> Cipher {
+        Cipher::from_key(&blake3::derive_key("ptfs key file", contents))
+    }
+
+    /// Value stored in the database to check keys against
+    pub fn key_check(&self) -> String {
+        self.encrypt_name(KEY_CHECK)
+    }
+
+    /// Encrypt `name` into a hex string, equal names are encrypted equally
+    pub fn encrypt_name(&self, name: &str) -> String {
+        let nonce_hash = blake3::keyed_hash(&self.name_nonce_key, name.as_bytes());
+        let nonce = to_nonce(nonce_hash.as_bytes());
+        let mut sealed = nonce.to_vec();
+        sealed.extend(
+            self.name_cipher
+                .encrypt(&nonce, name.as_bytes())
+                .expect("encrypting in memory can't fail"),
+        );
+        hex::encode(sealed)
+    }
+
+    pub fn decrypt_name(&self, encrypted: &str) -> Result<String, ConvError> {
+        let sealed = hex::decode(encrypted).map_err(|_| ConvError::Crypto)?;
+        if sealed.len() < NONCE_LEN {
+            return Err(ConvError::Crypto);
+        }
+        let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+        let name = self
+            .name_cipher
+            .decrypt(&to_nonce(nonce), ciphertext)
+            .map_err(|_| ConvError::Crypto)?;
+        String::from_utf8(name).map_err(|_| ConvError::Crypto)
+    }
+
+    /// Hash identifying a chunk by its content, keyed so it doesn't reveal the content
+    pub fn chunk_hash(&self, bytes: &[u8]) -> Vec<u8> {
+        blake3::keyed_hash(&self.chunk_hash_key, bytes)
+            .as_bytes()
+            .to_vec()
+    }
+
+    /// Encrypt a chunk, using its hash as the nonce
+    pub fn encrypt_chunk(&self, hash: &[u8], bytes: &[u8]) -> Vec<u8> {
+        self.chunk_cipher
+            .encrypt(&to_nonce(hash), bytes)
+            .expect("encrypting in memory can't fail")
+    }
+
+    pub fn decrypt_chunk(&self, hash: &[u8], bytes: &[u8]) -> Result<Vec<u8>, ConvError> {
+        if hash.len() < NONCE_LEN {
+            return Err(ConvError::Crypto);
+        }
+        self.chunk_cipher
+            .decrypt(&to_nonce(hash), bytes)
+            .map_err(|_| ConvError::Crypto)
+    }
+}
@@ -1,7 +1,8 @@
+pub mod queries;
 pub mod types;
 
 use fuser::FileAttr;
-use sqlx::{QueryBuilder, Sqlite};
+use sqlx::{Database, Encode, QueryBuilder, Sqlite, Type};
 use types::{Bindable, ConvError, DBError, from_filetype, from_systime};
 
 pub fn try_bind_attrs<'q, Q, B>(b: B, a: &FileAttr) -> Result<Q, ConvError>
@@ -29,17 +30,93 @@ where
 }
 
 /// Chain `qb` with a `SELECT` query of inodes that has been tagged with all of `tags`
-pub fn chain_tagged_inos(qb: &mut QueryBuilder<Sqlite>, tags: &Vec<u64>) -> Result<(), DBError> {
-    for (i, t) in tags.iter().enumerate() {
-        qb.push("SELECT ino FROM associated_tags WHERE tid = ")
-            .push_bind(i64::try_from(*t)?);
-        if i != tags.len() - 1 {
-            qb.push(" AND ino IN (");
+///
+/// Inodes are intersected by counting their associations with `tags`, so the query doesn't get
+/// deeper with each tag.
+pub fn chain_tagged_inos<'q, DB>(qb: &mut QueryBuilder<'q, DB>, tags: &[u64]) -> Result<(), DBError>
+where
+    DB: Database,
+    i64: Encode<'q, DB> + Type<DB>,
+{
+    let mut distinct = tags.to_vec();
+    distinct.sort_unstable();
+    distinct.dedup();
+    match distinct[..] {
+        [] => {
+            qb.push("SELECT ino FROM associated_tags WHERE FALSE");
+            return Ok(());
+        }
+        // a single tag needs no intersection
+        [tid] => {
+            qb.push("SELECT ino FROM associated_tags WHERE tid = ")
+                .push_bind(i64::try_from(tid)?);
+            return Ok(());
         }
+        _ => {}
     }
-    for _ in tags.iter().skip(1) {
-        qb.push(")");
+
+    qb.push("SELECT ino FROM associated_tags WHERE tid IN (");
+    let mut separated = qb.separated(", ");
+    for t in &distinct {
+        separated.push_bind(i64::try_from(*t)?);
     }
+    qb.push(") GROUP BY ino HAVING COUNT(DISTINCT tid) = ")
+        .push_bind(i64::try_from(distinct.len())?);
 
     Ok(())
 }
+
+/// Chain `qb` with a condition of `ino_column` being tagged with all of `tags`, which is false
+/// without tags like [`chain_tagged_inos`]
+///
+/// Tags are counted for each row instead of selecting every tagged inode first, which is faster
+/// on rows that are already narrowed down, e.g. by name.
+pub fn chain_has_tags<'q, DB>(
+    qb: &mut QueryBuilder<'q, DB>,
+    ino_column: &str,
+    tags: &[u64],
+) -> Result<(), DBError>
+where
+    DB: Database,
+    i64: Encode<'q, DB> + Type<DB>,
+{
+    let mut distinct = tags.to_vec();
+    distinct.sort_unstable();
+    distinct.dedup();
+    if distinct.is_empty() {
+        qb.push("FALSE");
+        return Ok(());
+    }
+
+    qb.push(format!(
+        "(SELECT COUNT(DISTINCT tid) FROM associated_tags WHERE ino = {ino_column} AND tid IN ("
+    ));
+    let mut separated = qb.separated(", ");
+    for t in &distinct {
+        separated.push_bind(i64::try_from(*t)?);
+    }
+    qb.push(")) = ").push_bind(i64::try_from(distinct.len())?);
+
+    Ok(())
+}
+
+/// Translate a shell glob into a `LIKE` pattern escaped with `\`
+///
+/// `*` and `?` become `%` and `_`, while `%`, `_` and `\` are matched literally.
+// only Postgres matches globs with LIKE
+#[cfg(any(feature = "postgres", test))]
+pub fn glob_to_like(glob: &str) -> String {
+    let mut pattern = String::with_capacity(glob.len());
+    for c in glob.chars() {
+        match c {
+            '*' => pattern.push('%'),
+            '?' => pattern.push('_'),
+            '%' | '_' | '\\' => {
+                pattern.push('\\');
+                pattern.push(c);
+            }
+            _ => pattern.push(c),
+        }
+    }
+    pattern
+}
@@ -0,0 +1,292 @@
+use super::{
+    chain_has_tags, chain_tagged_inos,
+    types::{DBError, from_filetype},
+};
+use crate::{
+    store::FileFilter,
+    vdirs::{RecentWindow, TimeField},
+};
+use fuser::FileType;
+use sqlx::{Database, Encode, QueryBuilder, Type};
+
+/// SQL that differs between the databases of stores, for the queries built here
+pub trait Dialect: Database {
+    /// Chain `qb` with a condition of `column` starting with `prefix`
+    fn push_prefixed(qb: &mut QueryBuilder<'_, Self>, column: &str, prefix: &str);
+    /// Chain `qb` with a condition of `column` matching the shell glob `glob`
+    fn push_glob(qb: &mut QueryBuilder<'_, Self>, column: &str, glob: &str);
+    /// Epoch seconds at the start of `window`
+    fn window_start(window: RecentWindow) -> String;
+    /// Local year of the epoch seconds in `column`, as an integer
+    fn year(column: &str) -> String;
+    /// Local month of the epoch seconds in `column`, as an integer from 1 to 12
+    fn month(column: &str) -> String;
+}
+
+/// Chain `qb` with a comma separated list of `inos`
+fn chain_inos<'q, DB>(qb: &mut QueryBuilder<'q, DB>, inos: &[u64]) -> Result<(), DBError>
+where
+    DB: Database,
+    i64: Encode<'q, DB> + Type<DB>,
+{
+    let mut separated = qb.separated(", ");
+    for ino in inos {
+        separated.push_bind(i64::try_from(*ino)?);
+    }
+    Ok(())
+}
+
+/// Create a query selecting `columns` of non-directory files tagged with all of `base_tags` (or
+/// all of them) from `readdir_rows`, to be chained with `AND` conditions
+fn base_files_query<'q, DB>(
+    base_tags: Option<&[u64]>,
+    columns: &str,
+) -> Result<QueryBuilder<'q, DB>, DBError>
+where
+    DB: Dialect,
+    i64: Encode<'q, DB> + Type<DB>,
+{
+    let mut query_builder =
+        QueryBuilder::new(format!("SELECT {columns} FROM readdir_rows WHERE kind != "));
+    query_builder.push_bind(i64::from(from_filetype(FileType::Directory)));
+    if let Some(tags) = base_tags {
+        query_builder.push(" AND ino IN (");
+        chain_tagged_inos(&mut query_builder, tags)?;
+        query_builder.push(")");
+    }
+    Ok(query_builder)
+}
+
+/// Query of [`crate::Store::get_tagged`], selecting inodes
+pub fn tagged_query<'q, DB>(tags: &[u64], tag_prefix: &str) -> Result<QueryBuilder<'q, DB>, DBError>
+where
+    DB: Dialect,
+    i64: Encode<'q, DB> + Type<DB>,
+{
+    let mut query_builder = QueryBuilder::new("SELECT ino FROM file_names WHERE ino IN (");
+    chain_tagged_inos(&mut query_builder, tags)?;
+    query_builder.push(") AND NOT ");
+    DB::push_prefixed(&mut query_builder, "name", tag_prefix);
+    Ok(query_builder)
+}
+
+/// Query of [`crate::Store::count_tag_files`], selecting the count
+pub fn count_tag_files_query<'q, DB>(
+    tid: u64,
+    tag_prefix: &str,
+) -> Result<QueryBuilder<'q, DB>, DBError>
+where
+    DB: Dialect,
+    i64: Encode<'q, DB> + Type<DB>,
+{
+    let mut query_builder = QueryBuilder::new(
+        "SELECT COUNT(*) FROM associated_tags INNER JOIN file_names ON file_names.ino = \
+         associated_tags.ino WHERE tid = ",
+    );
+    query_builder
+        .push_bind(i64::try_from(tid)?)
+        .push(" AND NOT ");
+    DB::push_prefixed(&mut query_builder, "name", tag_prefix);
+    Ok(query_builder)
+}
+
+/// Query of [`crate::Store::get_tag_views`], selecting pairs of inode and directory
+pub fn tag_views_query<'q, DB>(
+    inos: &[u64],
+    tag_prefix: &str,
+) -> Result<QueryBuilder<'q, DB>, DBError>
+where
+    DB: Dialect,
+    i64: Encode<'q, DB> + Type<DB>,
+{
+    // count the tags each prefixed directory shares with each file
+    let mut query_builder = QueryBuilder::new(
+        "SELECT files.ino, dirs.ino FROM associated_tags AS dirs INNER JOIN file_names ON \
+         file_names.ino = dirs.ino INNER JOIN associated_tags AS files ON files.tid = dirs.tid \
+         WHERE ",
+    );
+    DB::push_prefixed(&mut query_builder, "name", tag_prefix);
+    query_builder.push(" AND files.ino IN (");
+    chain_inos(&mut query_builder, inos)?;
+    query_builder.push(
+        ") GROUP BY files.ino, dirs.ino HAVING COUNT(*) = (SELECT COUNT(*) FROM associated_tags \
+         WHERE ino = dirs.ino)",
+    );
+    Ok(query_builder)
+}
+
+/// Query of [`crate::Store::get_names_and_tags`], selecting inodes, names and tids
+pub fn names_and_tags_query<'q, DB>(inos: &[u64]) -> Result<QueryBuilder<'q, DB>, DBError>
+where
+    DB: Dialect,
+    i64: Encode<'q, DB> + Type<DB>,
+{
+    let mut query_builder = QueryBuilder::new(
+        "SELECT file_names.ino, name, tid FROM file_names LEFT JOIN associated_tags ON \
+         associated_tags.ino = file_names.ino WHERE file_names.ino IN (",
+    );
+    chain_inos(&mut query_builder, inos)?;
+    query_builder.push(")");
+    Ok(query_builder)
+}
+
+/// Query of [`crate::Store::lookup_entry`], selecting `readdir_rows`
+pub fn lookup_entry_query<'q, DB>(
+    dir: u64,
+    tags: &[u64],
+    name: &str,
+) -> Result<QueryBuilder<'q, DB>, DBError>
+where
+    DB: Dialect,
+    i64: Encode<'q, DB> + Type<DB>,
+    String: Encode<'q, DB> + Type<DB>,
+{
+    let mut query_builder = QueryBuilder::new("SELECT * FROM readdir_rows WHERE name = ");
+    query_builder.push_bind(name.to_string()).push(" AND (");
+    chain_has_tags(&mut query_builder, "readdir_rows.ino", tags)?;
+    query_builder
+        .push(" OR ino IN (SELECT cnt_ino FROM dir_contents WHERE dir_ino = ")
+        .push_bind(i64::try_from(dir)?)
+        .push("))");
+    Ok(query_builder)
+}
+
+/// Query of [`crate::Store::list_entries`], selecting `readdir_rows`
+pub fn list_entries_query<'q, DB>(
+    dir: u64,
+    tags: &[u64],
+    tag_prefix: &str,
+    after: u64,
+    limit: u64,
+) -> Result<QueryBuilder<'q, DB>, DBError>
+where
+    DB: Dialect,
+    i64: Encode<'q, DB> + Type<DB>,
+{
+    let mut query_builder = QueryBuilder::new("SELECT * FROM readdir_rows WHERE (ino IN (");
+    chain_tagged_inos(&mut query_builder, tags)?;
+    query_builder.push(") AND NOT ");
+    DB::push_prefixed(&mut query_builder, "name", tag_prefix);
+    query_builder
+        .push(" OR ino IN (SELECT cnt_ino FROM dir_contents WHERE dir_ino = ")
+        .push_bind(i64::try_from(dir)?)
+        .push(")) AND ino > ")
+        .push_bind(i64::try_from(after)?)
+        .push(" ORDER BY ino LIMIT ")
+        .push_bind(i64::try_from(limit)?);
+    Ok(query_builder)
+}
+
+/// Query of [`crate::Store::get_members`], selecting inodes
+pub fn members_query<'q, DB>(dir: u64, tags: &[u64]) -> Result<QueryBuilder<'q, DB>, DBError>
+where
+    DB: Dialect,
+    i64: Encode<'q, DB> + Type<DB>,
+{
+    let mut query_builder = QueryBuilder::new("SELECT ino FROM file_attrs WHERE ino IN (");
+    chain_tagged_inos(&mut query_builder, tags)?;
+    query_builder
+        .push(") OR ino IN (SELECT cnt_ino FROM dir_contents WHERE dir_ino = ")
+        .push_bind(i64::try_from(dir)?)
+        .push(")");
+    Ok(query_builder)
+}
+
+/// Query of [`crate::Store::filter_entries`], selecting `readdir_rows`
+pub fn filter_entries_query<'q, DB>(
+    filter: &FileFilter<'_>,
+    name: Option<&str>,
+    after: u64,
+    limit: u64,
+) -> Result<QueryBuilder<'q, DB>, DBError>
+where
+    DB: Dialect,
+    i64: Encode<'q, DB> + Type<DB>,
+    String: Encode<'q, DB> + Type<DB>,
+{
+    let mut query_builder = match filter {
+        FileFilter::Untagged => QueryBuilder::new(
+            "SELECT * FROM readdir_rows WHERE ino NOT IN (SELECT ino FROM associated_tags) AND \
+             ino NOT IN (SELECT cnt_ino FROM dir_contents)",
+        ),
+        FileFilter::Tag { tid, tag_prefix } => {
+            let mut query_builder = QueryBuilder::new(
+                "SELECT * FROM readdir_rows WHERE ino IN (SELECT ino FROM associated_tags WHERE \
+                 tid = ",
+            );
+            query_builder
+                .push_bind(i64::try_from(*tid)?)
+                .push(") AND NOT ");
+            DB::push_prefixed(&mut query_builder, "name", tag_prefix);
+            query_builder
+        }
+        FileFilter::Recent { base_tags, window } => {
+            let mut query_builder = base_files_query(*base_tags, "*")?;
+            query_builder.push(format!(" AND mtime >= {}", DB::window_start(*window)));
+            query_builder
+        }
+        FileFilter::Month {
+            base_tags,
+            field,
+            year,
+            month,
+        } => {
+            let column = field.column();
+            let mut query_builder = base_files_query(*base_tags, "*")?;
+            query_builder
+                .push(format!(" AND {} = ", DB::year(column)))
+                .push_bind(i64::from(*year))
+                .push(format!(" AND {} = ", DB::month(column)))
+                .push_bind(i64::from(*month));
+            query_builder
+        }
+        FileFilter::Glob { base_tags, pattern } => {
+            let mut query_builder = base_files_query(*base_tags, "*")?;
+            query_builder.push(" AND ");
+            DB::push_glob(&mut query_builder, "name", pattern);
+            query_builder
+        }
+    };
+    if let Some(name) = name {
+        query_builder
+            .push(" AND name = ")
+            .push_bind(name.to_string());
+    }
+    query_builder
+        .push(" AND ino > ")
+        .push_bind(i64::try_from(after)?)
+        .push(" ORDER BY ino LIMIT ")
+        .push_bind(i64::try_from(limit)?);
+    Ok(query_builder)
+}
+
+/// Query of [`crate::Store::list_time_parts`], selecting years or months
+pub fn time_parts_query<'q, DB>(
+    base_tags: Option<&[u64]>,
+    field: TimeField,
+    year: Option<u32>,
+    after: u64,
+    limit: u64,
+) -> Result<QueryBuilder<'q, DB>, DBError>
+where
+    DB: Dialect,
+    i64: Encode<'q, DB> + Type<DB>,
+{
+    let column = field.column();
+    let part = match year {
+        None => DB::year(column),
+        Some(_) => DB::month(column),
+    };
+    let mut query_builder = base_files_query(base_tags, &format!("DISTINCT {part} AS n"))?;
+    if let Some(year) = year {
+        query_builder
+            .push(format!(" AND {} = ", DB::year(column)))
+            .push_bind(i64::from(year));
+    }
+    query_builder
+        .push(format!(" AND {part} > "))
+        .push_bind(i64::try_from(after)?)
+        .push(" ORDER BY n LIMIT ")
+        .push_bind(i64::try_from(limit)?);
+    Ok(query_builder)
+}
@@ -72,6 +72,8 @@ where
 pub enum DBError {
     SQLX(sqlx::Error),
     Conv(ConvError),
+    /// Error accessing chunks stored in the blob directory
+    Io(std::io::Error),
 }
 
 /// libc error code map for database errors
@@ -95,6 +97,7 @@ impl DBError {
                 // TODO: impl display for ConvError
                 "Conversion error".to_string(),
             ),
+            DBError::Io(e) => (e.raw_os_error().unwrap_or(EDB), e.to_string()),
         }
     }
 }
@@ -111,6 +114,12 @@ impl From<sqlx::Error> for DBError {
     }
 }
 
+impl From<std::io::Error> for DBError {
+    fn from(value: std::io::Error) -> Self {
+        DBError::Io(value)
+    }
+}
+
 impl From<SystemTimeError> for DBError {
     fn from(value: SystemTimeError) -> Self {
         DBError::Conv(ConvError::SystemTimeToU64(value))
@@ -128,6 +137,14 @@ pub enum ConvError {
     ModeToFiletype,
     SystemTimeToU64(SystemTimeError),
     ToI64(TryFromIntError),
+    /// Setting with an unknown value
+    Setting,
+    /// Chunk that failed decompressing
+    Decompress,
+    /// Name or chunk that failed decrypting, or a key that failed deriving
+    Crypto,
+    /// Tag prefix of an encrypted database that encrypted names could start with
+    TagPrefix,
 }
 
 impl From<SystemTimeError> for ConvError {
@@ -1,52 +1,64 @@
 use crate::{
-    PTFS,
-    db_helpers::{
-        chain_tagged_inos, try_bind_attrs,
-        types::{FileAttrRow, ReadDirRow, mode_to_filetype, to_filetype},
-    },
+    PTFS, Settings,
+    db_helpers::types::{ConvError, DBError, mode_to_filetype},
     handle_db_err, handle_from_int_err,
+    store::Store,
+    vdirs::{BY_CRTIME_NAME, BY_MTIME_NAME, DirEntry, RECENT_NAME, SEARCH_NAME, VirtualDir},
 };
 use fuser::*;
 use libc::c_int;
-use sqlx::{QueryBuilder, Sqlite, migrate, query, query_as, query_scalar};
-use std::{
-    cmp::max,
-    time::{Duration, SystemTime},
-};
+use std::time::{Duration, SystemTime};
+
+/// Entries fetched from the store at a time while filling a readdir reply
+const READDIR_BATCH: u64 = 256;
 
-impl Filesystem for PTFS<Sqlite> {
+impl<S: Store> Filesystem for PTFS<S> {
     #[tracing::instrument]
-    fn init(&mut self, req: &Request<'_>, _config: &mut KernelConfig) -> Result<(), c_int> {
+    fn init(&mut self, req: &Request<'_>, config: &mut KernelConfig) -> Result<(), c_int> {
+        // list attributes along with entries when the kernel expects them to be looked up, e.g.
+        // for `ls -l`, kernels that can't keep using readdir
+        config
+            .add_capabilities(consts::FUSE_DO_READDIRPLUS | consts::FUSE_READDIRPLUS_AUTO)
+            .ok();
+
+        self.settings = self.runtime_handle.block_on(async {
+            handle_db_err(self.migrate().await)?;
+            handle_db_err(Settings::load(&self.store).await)
+        })?;
+        // encrypted databases are only mounted unlocked, see [`PTFS::unlock`]
+        if self.settings.encryption.is_some() {
+            if self.hex_prefix() {
+                return handle_db_err(Err(ConvError::TagPrefix));
+            }
+            if self.cipher.is_none() {
+                return handle_db_err(Err(ConvError::Crypto));
+            }
+        }
+
         self.runtime_handle.block_on(async {
-            migrate!().run(&self.pool).await.unwrap();
+            handle_db_err(self.convert_legacy_pages().await)?;
 
             // create mountpoint attr if not exist
-            let q = handle_db_err(try_bind_attrs(
-                query(
-                    "INSERT OR IGNORE INTO file_attrs VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, \
-                     $10, $11, $12, $13, $14, $15)",
-                ),
-                &FileAttr {
-                    ino: 1,
-                    atime: SystemTime::now(),
-                    mtime: SystemTime::now(),
-                    ctime: SystemTime::now(),
-                    crtime: SystemTime::now(),
-                    kind: FileType::Directory,
-                    uid: req.uid(),
-                    gid: req.gid(),
-                    perm: 0o777, // TODO: permission related, sync with original dir mayhaps?
-                    size: 0,     // TODO: calculate size
-
-                    // unused
-                    nlink: 1,
-                    rdev: 0,
-                    blocks: 0,
-                    blksize: 0,
-                    flags: 0,
-                },
-            ))?;
-            handle_db_err(q.execute(&self.pool).await)?;
+            let root_attr = FileAttr {
+                ino: 1,
+                atime: SystemTime::now(),
+                mtime: SystemTime::now(),
+                ctime: SystemTime::now(),
+                crtime: SystemTime::now(),
+                kind: FileType::Directory,
+                uid: req.uid(),
+                gid: req.gid(),
+                perm: 0o777, // TODO: permission related, sync with original dir mayhaps?
+                size: 0,     // TODO: calculate size
+
+                // unused
+                nlink: 1,
+                rdev: 0,
+                blocks: 0,
+                blksize: 0,
+                flags: 0,
+            };
+            handle_db_err(self.store.insert_attr_if_missing(&root_attr).await)?;
 
             Ok(())
         })
@@ -56,26 +68,30 @@ impl Filesystem for PTFS<Sqlite> {
     fn destroy(&mut self) {
         // TODO: delete shm and wal files
         self.runtime_handle.block_on(async {
-            let _c = &self.pool.close().await;
+            self.store.close().await;
         });
     }
 
+    #[tracing::instrument]
+    fn forget(&mut self, _req: &Request<'_>, ino: u64, nlookup: u64) {
+        // only virtual directories are kept track of, to reclaim their inodes
+        self.vdirs.forget(ino, nlookup);
+    }
+
     #[tracing::instrument]
     fn getattr(&mut self, req: &Request<'_>, ino: u64, _fh: Option<u64>, reply: ReplyAttr) {
         self.runtime_handle.block_on(async {
-            handle_auth_perm!(self, ino, req, reply, 0b100);
+            if self.vdirs.get(ino).is_some() {
+                let attr = handle_db_err!(self.get_vdir_attr(ino).await, reply);
+                reply.attr(&self.ttl.attr, &attr);
+                return;
+            }
 
-            let attr_row = handle_db_err!(
-                query_as::<_, FileAttrRow>("SELECT * FROM file_attrs WHERE ino = ?")
-                    .bind(to_i64!(ino, reply))
-                    .fetch_one(&self.pool)
-                    .await,
-                reply
-            );
+            handle_auth_perm!(self, ino, req, reply, 0b100);
 
-            let attr = handle_db_err!(FileAttr::try_from(&attr_row), reply);
+            let attr = handle_db_err!(self.store.get_attr(ino).await, reply);
 
-            reply.attr(&Duration::from_secs(1), &attr);
+            reply.attr(&self.ttl.attr, &attr);
         });
     }
 
@@ -88,30 +104,24 @@ impl Filesystem for PTFS<Sqlite> {
         reply: ReplyEntry,
     ) {
         self.runtime_handle.block_on(async {
-            handle_auth_perm!(self, parent, req, reply, 0b100);
-
-            let mut query_builder =
-                QueryBuilder::<Sqlite>::new("SELECT * FROM readdir_rows WHERE (ino IN (");
-
-            let ptags = handle_db_err!(self.get_ass_tags(parent).await, reply);
-            handle_db_err!(chain_tagged_inos(&mut query_builder, &ptags), reply);
-
-            query_builder
-                .push(") OR ino IN (SELECT cnt_ino FROM dir_contents WHERE dir_ino = ")
-                .push_bind(to_i64!(parent, reply))
-                .push(")) AND name = ")
-                .push_bind(name.to_str());
+            let vdir = self.vdirs.get(parent);
+            if vdir.is_none() {
+                handle_auth_perm!(self, parent, req, reply, 0b100);
+            }
 
-            let row = handle_db_err!(
-                query_builder
-                    .build_query_as::<ReadDirRow>()
-                    .fetch_one(&self.pool)
-                    .await,
-                reply
-            );
-            let attr = handle_db_err!(FileAttr::try_from(&row.attr), reply);
-            reply.entry(&Duration::from_secs(1), &attr, 0);
+            match handle_db_err(self.lookup_attr(parent, vdir, name.to_str().unwrap()).await) {
+                Ok(attr) => {
+                    self.vdirs.looked_up(attr.ino);
+                    reply.entry(&self.ttl.entry, &attr, 0)
+                }
+                // inode 0 lets the kernel cache that the name doesn't exist
+                Err(libc::ENOENT) if !self.ttl.negative.is_zero() => {
+                    reply.entry(&self.ttl.negative, &negative_attr(), 0)
+                }
+                Err(e) => reply.error(e),
+            }
         });
+        self.vdirs.reclaim();
     }
 
     #[tracing::instrument]
@@ -126,6 +136,8 @@ impl Filesystem for PTFS<Sqlite> {
         reply: ReplyEntry,
     ) {
         self.runtime_handle.block_on(async {
+            handle_vdir_readonly!(self, parent, reply);
+            handle_vdir_name!(self, parent, name.to_str().unwrap(), reply);
             handle_auth_perm!(self, parent, req, reply, 0b010);
 
             let kind = handle_db_err!(mode_to_filetype(mode), reply);
@@ -157,53 +169,34 @@ impl Filesystem for PTFS<Sqlite> {
                 flags: 0,
             };
 
-            f_attrs.ino = handle_db_err!(self.ins_attrs(&f_attrs).await, reply);
+            f_attrs.ino = handle_db_err!(self.store.insert_attr(&f_attrs).await, reply);
 
             handle_db_err!(
-                query("INSERT INTO file_names VALUES (?, ?)")
-                    .bind(to_i64!(f_attrs.ino, reply))
-                    .bind(name.to_str())
-                    .execute(&self.pool)
+                self.store
+                    .insert_name(f_attrs.ino, &self.enc_name(name.to_str().unwrap()))
                     .await,
                 reply
             );
 
-            let parent_name = handle_db_err!(
-                query_scalar::<_, String>("SELECT name FROM file_names WHERE ino = ?")
-                    .bind(to_i64!(parent, reply))
-                    .fetch_optional(&self.pool)
-                    .await,
-                reply
-            );
+            let parent_name = handle_db_err!(self.store.get_name(parent).await, reply);
 
             if let Some(parent_name) = parent_name
                 && self.is_prefixed(parent_name.as_str())
             {
                 // associate created directory with parent tags
-                for ptag in handle_db_err!(self.get_ass_tags(parent).await, reply) {
-                    handle_db_err!(
-                        query("INSERT INTO associated_tags VALUES (?, ?)")
-                            .bind(to_i64!(ptag, reply))
-                            .bind(to_i64!(f_attrs.ino, reply))
-                            .execute(&self.pool)
-                            .await,
-                        reply
-                    );
+                for ptag in handle_db_err!(self.store.get_tags(parent).await, reply) {
+                    handle_db_err!(self.store.tag(ptag, f_attrs.ino).await, reply);
                 }
             } else {
-                handle_db_err!(
-                    query("INSERT INTO dir_contents (dir_ino, cnt_ino) VALUES (?, ?)")
-                        .bind(to_i64!(parent, reply))
-                        .bind(to_i64!(f_attrs.ino, reply))
-                        .execute(&self.pool)
-                        .await,
-                    reply
-                );
+                handle_db_err!(self.store.add_to_dir(parent, f_attrs.ino).await, reply);
             }
 
             handle_db_err!(self.sync_mtime(parent).await, reply);
 
-            reply.entry(&Duration::from_secs(1), &f_attrs, 0);
+            let views = handle_db_err!(self.get_tag_views(&[f_attrs.ino]).await, reply);
+            self.inval_views(&views);
+
+            reply.entry(&self.ttl.entry, &f_attrs, 0);
         });
     }
 
@@ -214,67 +207,25 @@ impl Filesystem for PTFS<Sqlite> {
         ino: u64,
         _fh: u64,
         offset: i64,
-        mut reply: ReplyDirectory,
+        reply: ReplyDirectory,
     ) {
-        self.runtime_handle.block_on(async {
-            handle_auth_perm!(self, ino, req, reply, 0b100);
-            let name = if ino != 1 {
-                Some(handle_db_err!(
-                    self.get_ino_name(to_i64!(ino, reply)).await,
-                    reply
-                ))
-            } else {
-                None
-            };
-
-            let children: Vec<ReadDirRow> = if let Some(name) = name
-                && self.is_prefixed(&name)
-            {
-                let mut query_builder =
-                    QueryBuilder::<Sqlite>::new("SELECT * FROM readdir_rows WHERE (ino IN (");
-
-                let tags = handle_db_err!(self.get_ass_tags(ino).await, reply);
-                handle_db_err!(chain_tagged_inos(&mut query_builder, &tags), reply);
-
-                handle_db_err!(
-                    query_builder
-                        .push(") AND name NOT LIKE ")
-                        .push_bind(format!("{}%", self.tag_prefix))
-                        .push(" OR ino IN (SELECT cnt_ino FROM dir_contents WHERE dir_ino = ")
-                        .push_bind(to_i64!(ino, reply))
-                        .push(")) ORDER BY ino LIMIT -1 OFFSET ")
-                        .push_bind(offset)
-                        .build_query_as()
-                        .fetch_all(&self.pool)
-                        .await,
-                    reply
-                )
-            } else {
-                handle_db_err!(
-                    query_as(
-                        "SELECT * FROM readdir_rows WHERE ino IN (SELECT cnt_ino FROM \
-                         dir_contents WHERE dir_ino = ?) ORDER BY ino LIMIT -1 OFFSET ?",
-                    )
-                    .bind(to_i64!(ino, reply))
-                    .bind(offset)
-                    .fetch_all(&self.pool)
-                    .await,
-                    reply
-                )
-            };
-
-            for (i, child) in children.iter().enumerate() {
-                let attr = &child.attr;
-                let name = &child.name;
-                let ftyp = handle_db_err!(to_filetype(attr.kind), reply);
+        self.runtime_handle
+            .block_on(self.fill_dir(req, ino, offset, reply));
+        self.vdirs.reclaim();
+    }
 
-                if reply.add(attr.ino, offset + to_i64!(i, reply) + 1, ftyp, name) {
-                    break;
-                };
-            }
-            handle_db_err!(self.sync_atime(ino).await, reply);
-            reply.ok();
-        });
+    #[tracing::instrument]
+    fn readdirplus(
+        &mut self,
+        req: &Request<'_>,
+        ino: u64,
+        _fh: u64,
+        offset: i64,
+        reply: ReplyDirectoryPlus,
+    ) {
+        self.runtime_handle
+            .block_on(self.fill_dir(req, ino, offset, reply));
+        self.vdirs.reclaim();
     }
 
     #[tracing::instrument]
@@ -288,30 +239,29 @@ impl Filesystem for PTFS<Sqlite> {
         reply: ReplyEntry,
     ) {
         self.runtime_handle.block_on(async {
+            handle_vdir_readonly!(self, parent, reply);
+            handle_vdir_name!(self, parent, name.to_str().unwrap(), reply);
             handle_auth_perm!(self, parent, req, reply, 0b010);
 
             let name_str = name.to_str().unwrap();
             let is_prefixed = self.is_prefixed(name.to_str().unwrap());
             let parent_prefixed = if parent != 1 // not root
-                && self.is_prefixed(&handle_db_err!(
-                    self.get_ino_name(to_i64!(parent, reply)).await,
-                    reply
-                )) {
+                && self.is_prefixed(&handle_db_err!(self.get_ino_name(parent).await, reply))
+            {
                 true
             } else {
                 false
             };
             let parent_tags = if parent_prefixed {
-                Some(handle_db_err!(self.get_ass_tags(parent).await, reply))
+                Some(handle_db_err!(self.store.get_tags(parent).await, reply))
             } else {
                 None
             };
             let tid = if self.is_prefixed(name_str) {
                 Some(
                     match handle_db_err!(
-                        query_scalar::<_, u64>("SELECT tid FROM tags WHERE name = ?")
-                            .bind(name.to_str())
-                            .fetch_optional(&self.pool)
+                        self.store
+                            .get_tid(&self.enc_name(name.to_str().unwrap()))
                             .await,
                         reply
                     ) {
@@ -331,15 +281,14 @@ impl Filesystem for PTFS<Sqlite> {
                         }
                         // create tag if doesn't exist
                         None => {
-                            handle_db_err!(
-                                query_scalar::<_, u64>(
-                                    "INSERT INTO tags(name) VALUES (?) RETURNING tid"
-                                )
-                                .bind(name.to_str())
-                                .fetch_one(&self.pool)
-                                .await,
+                            let tid = handle_db_err!(
+                                self.store
+                                    .create_tag(&self.enc_name(name.to_str().unwrap()))
+                                    .await,
                                 reply
-                            )
+                            );
+                            self.inval_tag_entry(name.to_str().unwrap());
+                            tid
                         }
                     },
                 )
@@ -368,14 +317,12 @@ impl Filesystem for PTFS<Sqlite> {
                 blksize: 0,
                 flags: 0,
             };
-            f_attrs.ino = handle_db_err!(self.ins_attrs(&f_attrs).await, reply);
+            f_attrs.ino = handle_db_err!(self.store.insert_attr(&f_attrs).await, reply);
 
             // create file_names entry
             handle_db_err!(
-                query("INSERT INTO file_names VALUES (?, ?)")
-                    .bind(to_i64!(f_attrs.ino, reply))
-                    .bind(name.to_str())
-                    .execute(&self.pool)
+                self.store
+                    .insert_name(f_attrs.ino, &self.enc_name(name.to_str().unwrap()))
                     .await,
                 reply
             );
@@ -383,129 +330,92 @@ impl Filesystem for PTFS<Sqlite> {
             if parent_prefixed {
                 // associate created directory with parent tags
                 for ptag in parent_tags.unwrap() {
-                    handle_db_err!(
-                        query("INSERT INTO associated_tags VALUES (?, ?)")
-                            .bind(to_i64!(ptag, reply))
-                            .bind(to_i64!(f_attrs.ino, reply))
-                            .execute(&self.pool)
-                            .await,
-                        reply
-                    );
+                    handle_db_err!(self.store.tag(ptag, f_attrs.ino).await, reply);
                 }
             }
 
             if !parent_prefixed || is_prefixed {
                 // insert into dir_contents
-                handle_db_err!(
-                    query("INSERT INTO dir_contents VALUES (?, ?)")
-                        .bind(to_i64!(parent, reply))
-                        .bind(to_i64!(f_attrs.ino, reply))
-                        .execute(&self.pool)
-                        .await,
-                    reply
-                );
+                handle_db_err!(self.store.add_to_dir(parent, f_attrs.ino).await, reply);
             }
 
             if is_prefixed {
                 // associate created directory with the tid above
-                handle_db_err!(
-                    query("INSERT INTO associated_tags VALUES (?, ?)")
-                        .bind(to_i64!(tid.unwrap(), reply))
-                        .bind(to_i64!(f_attrs.ino, reply))
-                        .execute(&self.pool)
-                        .await,
-                    reply
-                );
+                handle_db_err!(self.store.tag(tid.unwrap(), f_attrs.ino).await, reply);
             }
 
             handle_db_err!(self.sync_mtime(parent).await, reply);
 
-            reply.entry(&Duration::from_secs(1), &f_attrs, 1);
+            // unprefixed directories are listed through their tags too
+            if !is_prefixed {
+                let views = handle_db_err!(self.get_tag_views(&[f_attrs.ino]).await, reply);
+                self.inval_views(&views);
+            }
+
+            reply.entry(&self.ttl.entry, &f_attrs, 1);
         });
     }
 
     #[tracing::instrument]
     fn rmdir(&mut self, req: &Request<'_>, parent: u64, name: &std::ffi::OsStr, reply: ReplyEmpty) {
         self.runtime_handle.block_on(async {
+            handle_vdir_readonly!(self, parent, reply);
             handle_auth_perm!(self, parent, req, reply, 0b010);
 
             let parent_prefixed = if parent != 1 // not root
-                && self.is_prefixed(&handle_db_err!(
-                    self.get_ino_name(to_i64!(parent, reply)).await,
-                    reply
-                )) {
+                && self.is_prefixed(&handle_db_err!(self.get_ino_name(parent).await, reply))
+            {
                 true
             } else {
                 false
             };
 
-            let ino = if parent_prefixed {
-                let mut query_builder =
-                    QueryBuilder::<Sqlite>::new("SELECT * FROM readdir_rows WHERE (ino IN (");
-
-                let parent_tags = handle_db_err!(self.get_ass_tags(parent).await, reply);
-                handle_db_err!(chain_tagged_inos(&mut query_builder, &parent_tags), reply);
-
-                handle_db_err!(
-                    query_builder
-                        .push(
-                            ") OR ino IN (SELECT cnt_ino FROM dir_contents WHERE dir_ino = ?)) \
-                             AND name = ?"
-                        )
-                        .build_query_scalar()
-                        .bind(to_i64!(parent, reply))
-                        .bind(name.to_str().unwrap())
-                        .fetch_one(&self.pool)
-                        .await,
-                    reply
-                )
+            let parent_tags = if parent_prefixed {
+                handle_db_err!(self.store.get_tags(parent).await, reply)
             } else {
-                handle_db_err!(
-                    query_scalar::<_, i64>(
-                        "SELECT cnt_ino FROM dir_contents INNER JOIN file_names ON file_names.ino \
-                         = dir_contents.cnt_ino WHERE dir_ino = ? AND name = ?"
-                    )
-                    .bind(to_i64!(parent, reply))
-                    .bind(name.to_str().unwrap())
-                    .fetch_one(&self.pool)
-                    .await,
-                    reply
-                )
+                vec![]
             };
-
-            // error if not empty
-            let is_empty = handle_db_err!(
-                query("SELECT TRUE FROM dir_contents WHERE dir_ino = ? LIMIT 1")
-                    .bind(ino)
-                    .fetch_optional(&self.pool)
+            let ino = handle_db_err!(
+                self.store
+                    .lookup_entry(parent, &parent_tags, &self.enc_name(name.to_str().unwrap()))
                     .await,
                 reply
             )
-            .is_none();
-            if !is_empty {
+            .attr
+            .ino;
+
+            // error if not empty, files only listed through a tag directory's tags don't count
+            let contents = handle_db_err!(self.store.get_dir_contents(ino).await, reply);
+            if !contents.is_empty() {
                 reply.error(libc::ENOTEMPTY);
                 return;
             }
 
-            handle_db_err!(
-                query("DELETE FROM file_attrs WHERE ino = ?")
-                    .bind(ino)
-                    .execute(&self.pool)
-                    .await,
-                reply
-            );
-
-            if self.is_prefixed(name.to_str().unwrap()) {
-                let tid: u64 = handle_db_err!(
-                    query_scalar("SELECT tid FROM tags WHERE name = ?")
-                        .bind(name.to_str().unwrap())
-                        .fetch_one(&self.pool)
-                        .await,
+            let tid: Option<u64> = if self.is_prefixed(name.to_str().unwrap()) {
+                Some(handle_db_err!(
+                    self.get_tid(name.to_str().unwrap()).await,
                     reply
-                );
+                ))
+            } else {
+                None
+            };
+
+            // files leave the views of their tags, which are found before the tag is removed
+            let mut untagged = vec![];
+            if let Some(tid) = tid {
+                // untag the files listed by the tag directory
+                let members = handle_db_err!(self.get_tagged_members(ino).await, reply);
+                untagged = handle_db_err!(self.get_tag_views(&members).await, reply);
+                handle_db_err!(self.untag_inos(tid, &members).await, reply);
+            }
 
+            handle_db_err!(self.store.delete_inode(ino).await, reply);
+
+            if let Some(tid) = tid {
                 handle_db_err!(self.del_tid_if_orphan(tid).await, reply);
+                self.inval_tag_entry(name.to_str().unwrap());
             }
+            self.inval_views(&untagged);
 
             reply.ok();
         })
@@ -520,37 +430,24 @@ impl Filesystem for PTFS<Sqlite> {
         reply: ReplyEmpty,
     ) {
         self.runtime_handle.block_on(async {
+            handle_vdir_readonly!(self, parent, reply);
             handle_auth_perm!(self, parent, req, reply, 0b010);
 
-            let mut query_builder =
-                QueryBuilder::<Sqlite>::new("SELECT * FROM readdir_rows WHERE (ino IN (");
-
-            let ptags = handle_db_err!(self.get_ass_tags(parent).await, reply);
-            handle_db_err!(chain_tagged_inos(&mut query_builder, &ptags), reply);
-
-            query_builder
-                .push(") OR ino IN (SELECT cnt_ino FROM dir_contents WHERE dir_ino = ")
-                .push_bind(to_i64!(parent, reply))
-                .push(")) AND name = ")
-                .push_bind(name.to_str());
-
+            let ptags = handle_db_err!(self.store.get_tags(parent).await, reply);
             let f_attrs = handle_db_err!(
-                query_builder
-                    .build_query_as::<FileAttrRow>()
-                    .fetch_one(&self.pool)
+                self.store
+                    .lookup_entry(parent, &ptags, &self.enc_name(name.to_str().unwrap()))
                     .await,
                 reply
-            );
+            )
+            .attr;
 
             handle_auth_perm!(self, f_attrs.ino, req, reply, 0b010);
 
-            handle_db_err!(
-                query("DELETE FROM file_attrs WHERE ino = ?")
-                    .bind(to_i64!(f_attrs.ino, reply))
-                    .execute(&self.pool)
-                    .await,
-                reply
-            );
+            let views = handle_db_err!(self.get_tag_views(&[f_attrs.ino]).await, reply);
+            handle_db_err!(self.store.delete_inode(f_attrs.ino).await, reply);
+            handle_db_err!(self.gc_chunks().await, reply);
+            self.inval_views(&views);
 
             reply.ok();
         });
@@ -576,25 +473,16 @@ impl Filesystem for PTFS<Sqlite> {
         reply: ReplyAttr,
     ) {
         self.runtime_handle.block_on(async {
+            handle_vdir_readonly!(self, ino, reply);
             handle_auth_perm!(self, ino, req, reply, 0b010);
 
-            let row = handle_db_err!(
-                query_as::<_, FileAttrRow>("SELECT * FROM file_attrs WHERE ino = $1")
-                    .bind(to_i64!(ino, reply))
-                    .fetch_one(&self.pool)
-                    .await,
-                reply
-            );
+            // resizing updates the size and blocks, read the row after it
+            if let Some(new_size) = size {
+                handle_db_err!(self.change_file_size(ino, new_size).await, reply);
+            }
 
-            let mut attr: FileAttr = handle_db_err!(FileAttr::try_from(&row), reply).into();
+            let mut attr = handle_db_err!(self.store.get_attr(ino).await, reply);
 
-            attr.size = match size {
-                Some(new_size) => {
-                    handle_db_err!(self.change_file_size(ino, new_size).await, reply);
-                    new_size
-                }
-                None => attr.size,
-            };
             attr.atime = atime.map_or(attr.atime, |tn| match tn {
                 TimeOrNow::Now => SystemTime::now(),
                 TimeOrNow::SpecificTime(t) => t,
@@ -611,9 +499,9 @@ impl Filesystem for PTFS<Sqlite> {
             attr.uid = uid.unwrap_or(attr.uid);
             attr.gid = gid.unwrap_or(attr.gid);
 
-            handle_db_err!(self.upd_attrs(&attr).await, reply);
+            handle_db_err!(self.store.update_attr(&attr).await, reply);
 
-            reply.attr(&Duration::from_secs(1), &attr);
+            reply.attr(&self.ttl.attr, &attr);
         })
     }
 
@@ -633,155 +521,8 @@ impl Filesystem for PTFS<Sqlite> {
         self.runtime_handle.block_on(async {
             handle_auth_perm!(self, ino, req, reply, 0b010);
 
-            let page_size = handle_db_err!(self.get_db_page_size().await, reply);
-            let usize_page_size: usize = handle_from_int_err!(page_size.try_into(), reply);
             let u_offset: u64 = handle_from_int_err!(offset.try_into(), reply);
-            let usize_offset = handle_from_int_err!(offset.try_into(), reply);
-            let paged_offset = usize_offset % usize_page_size;
-            let start_page = u_offset / page_size;
-            let data_length: u64 = handle_from_int_err!(data.len().try_into(), reply);
-            let page_span = (data_length - 1) / page_size + 1;
-            let end_page = start_page + page_span - 1;
-            let old_last_page: Option<u64> = handle_db_err!(
-                query_scalar(
-                    "SELECT page FROM file_contents WHERE ino = ? ORDER BY page DESC LIMIT 1"
-                )
-                .bind(to_i64!(ino, reply))
-                .fetch_optional(&self.pool)
-                .await,
-                reply
-            );
-            let last_page: u64 = match old_last_page {
-                Some(old_last_page) => max(old_last_page, end_page),
-                None => end_page,
-            };
-
-            if let Some(old_last_page) = old_last_page
-                && last_page > old_last_page
-            {
-                let mut content: Vec<u8> = handle_db_err!(
-                    query_scalar("SELECT bytes FROM file_contents WHERE ino = ? AND page = ?")
-                        .bind(to_i64!(ino, reply))
-                        .bind(to_i64!(old_last_page, reply))
-                        .fetch_one(&self.pool)
-                        .await,
-                    reply
-                );
-                content.resize(usize_page_size, 0);
-                handle_db_err!(
-                    query("UPDATE file_contents SET bytes = ? WHERE ino = ? and page = ?")
-                        .bind(content)
-                        .bind(to_i64!(ino, reply))
-                        .bind(to_i64!(old_last_page, reply))
-                        .execute(&self.pool)
-                        .await,
-                    reply
-                );
-            }
-            for offset_page in 0..page_span {
-                let usize_offset_page: usize = handle_from_int_err!(offset_page.try_into(), reply);
-                let page = start_page + offset_page;
-                let data_start = match offset_page {
-                    0 => 0,
-                    _ => usize_offset_page * usize_page_size - paged_offset,
-                };
-                let data_end = match offset_page {
-                    op if op == page_span - 1 => data.len(),
-                    0 => usize_page_size - paged_offset,
-                    _ => data_start + usize_page_size,
-                };
-                let Some(data_slice) = data.get(data_start..data_end) else {
-                    reply.error(libc::EIO); // TODO: find appropriate error code
-                    return;
-                };
-                let db_data: Option<Vec<u8>> = handle_db_err!(
-                    query_scalar("SELECT bytes FROM file_contents WHERE ino = ? AND page = ?")
-                        .bind(to_i64!(ino, reply))
-                        .bind(to_i64!(page, reply))
-                        .fetch_optional(&self.pool)
-                        .await,
-                    reply
-                );
-                if let Some(mut db_data) = db_data {
-                    if page == last_page {
-                        let new_len = max(
-                            match offset_page == start_page && offset_page == end_page {
-                                true => usize_offset + data_slice.len(),
-                                false => data_slice.len(),
-                            },
-                            db_data.len(),
-                        );
-                        db_data.resize(new_len, 0);
-                    }
-                    let start_offset = match offset_page == start_page {
-                        true => usize_offset,
-                        false => 0,
-                    };
-                    db_data[start_offset..start_offset + data_slice.len()]
-                        .copy_from_slice(data_slice);
-                    handle_db_err!(
-                        query("UPDATE file_contents SET bytes = ? WHERE ino = ? AND page = ?")
-                            .bind(db_data)
-                            .bind(to_i64!(ino, reply))
-                            .bind(to_i64!(page, reply))
-                            .execute(&self.pool)
-                            .await,
-                        reply
-                    );
-                    continue;
-                }
-                let data_slice_vec = match data_slice.len() == usize_page_size {
-                    true => None,
-                    false => {
-                        let mut data_slice_vec = data_slice.to_vec();
-                        if page == start_page && paged_offset != 0 {
-                            let old_len = data_slice_vec.len();
-                            data_slice_vec.resize(old_len + paged_offset, 0);
-                            data_slice_vec.copy_within(0..old_len, paged_offset);
-                            data_slice_vec[..paged_offset].fill(0);
-                        }
-                        if page != last_page {
-                            data_slice_vec.resize(usize_page_size, 0);
-                        }
-                        Some(data_slice_vec)
-                    }
-                };
-                handle_db_err!(
-                    query("INSERT INTO file_contents (ino, page, bytes) VALUES (?, ?, ?)")
-                        .bind(to_i64!(ino, reply))
-                        .bind(to_i64!(page, reply))
-                        .bind(
-                            data_slice_vec
-                                .as_ref()
-                                .map(|v| v.as_ref())
-                                .unwrap_or(data_slice)
-                        )
-                        .execute(&self.pool)
-                        .await,
-                    reply
-                );
-            }
-
-            // update file size
-            let last_page_size: i64 = handle_db_err!(
-                query_scalar("SELECT LENGTH(bytes) FROM file_contents WHERE ino = ? AND page = ?")
-                    .bind(to_i64!(ino, reply))
-                    .bind(to_i64!(last_page, reply))
-                    .fetch_one(&self.pool)
-                    .await,
-                reply
-            );
-            let mut file_size: i64 =
-                handle_from_int_err!((page_size * last_page).try_into(), reply);
-            file_size += last_page_size;
-            handle_db_err!(
-                query("UPDATE file_attrs SET size = ? WHERE ino = ?")
-                    .bind(file_size)
-                    .bind(to_i64!(ino, reply))
-                    .execute(&self.pool)
-                    .await,
-                reply
-            );
+            handle_db_err!(self.write_data(ino, u_offset, data).await, reply);
 
             let _written_size = handle_from_int_err!(data.len().try_into(), reply);
             reply.written(_written_size);
@@ -801,31 +542,126 @@ impl Filesystem for PTFS<Sqlite> {
         reply: ReplyData,
     ) {
         self.runtime_handle.block_on(async {
-            let mut data = vec![0u8; 0];
-            let page_size = handle_db_err!(self.get_db_page_size().await, reply);
-            let usize_page_size: usize = handle_db_err!(page_size.try_into(), reply);
             let u_offset: u64 = handle_from_int_err!(offset.try_into(), reply);
-            let start_page = u_offset / page_size;
-            let page_span = (u64::from(size) - 1) / page_size + 1;
-
-            for offset_page in 0..page_span {
-                let page = start_page + offset_page;
-                let db_bytes: Option<Vec<u8>> = handle_db_err!(
-                    query_scalar("SELECT bytes FROM file_contents WHERE ino = ? AND page = ?")
-                        .bind(to_i64!(ino, reply))
-                        .bind(to_i64!(page, reply))
-                        .fetch_optional(&self.pool)
-                        .await,
-                    reply
-                );
-                let bytes = db_bytes.unwrap_or(vec![0u8; usize_page_size]);
-                data.extend_from_slice(&bytes);
-            }
+            let data = handle_db_err!(self.read_data(ino, u_offset, size.into()).await, reply);
 
             reply.data(&data);
         });
     }
 
+    #[tracing::instrument]
+    fn copy_file_range(
+        &mut self,
+        req: &Request<'_>,
+        ino_in: u64,
+        _fh_in: u64,
+        offset_in: i64,
+        ino_out: u64,
+        _fh_out: u64,
+        offset_out: i64,
+        len: u64,
+        _flags: u32,
+        reply: ReplyWrite,
+    ) {
+        self.runtime_handle.block_on(async {
+            handle_auth_perm!(self, ino_in, req, reply, 0b100);
+            handle_auth_perm!(self, ino_out, req, reply, 0b010);
+
+            let u_offset_in: u64 = handle_from_int_err!(offset_in.try_into(), reply);
+            let u_offset_out: u64 = handle_from_int_err!(offset_out.try_into(), reply);
+            // overlapping ranges of the same file
+            if ino_in == ino_out
+                && u_offset_in < u_offset_out.saturating_add(len)
+                && u_offset_out < u_offset_in.saturating_add(len)
+            {
+                reply.error(libc::EINVAL);
+                return;
+            }
+
+            let copied = handle_db_err!(
+                self.copy_data(ino_in, u_offset_in, ino_out, u_offset_out, len)
+                    .await,
+                reply
+            );
+            let copied_size = handle_from_int_err!(copied.try_into(), reply);
+            reply.written(copied_size);
+        });
+    }
+
+    #[tracing::instrument]
+    fn fallocate(
+        &mut self,
+        req: &Request<'_>,
+        ino: u64,
+        _fh: u64,
+        offset: i64,
+        length: i64,
+        mode: i32,
+        reply: ReplyEmpty,
+    ) {
+        self.runtime_handle.block_on(async {
+            handle_auth_perm!(self, ino, req, reply, 0b010);
+
+            let u_offset: u64 = handle_from_int_err!(offset.try_into(), reply);
+            let u_length: u64 = handle_from_int_err!(length.try_into(), reply);
+            let keep_size = mode & libc::FALLOC_FL_KEEP_SIZE != 0;
+
+            // files are sparse, so allocating only changes the size
+            match mode & !libc::FALLOC_FL_KEEP_SIZE {
+                0 => {}
+                libc::FALLOC_FL_PUNCH_HOLE if keep_size => {
+                    handle_db_err!(self.zero_range(ino, u_offset, u_length).await, reply);
+                }
+                libc::FALLOC_FL_ZERO_RANGE => {
+                    handle_db_err!(self.zero_range(ino, u_offset, u_length).await, reply);
+                }
+                _ => {
+                    reply.error(libc::EOPNOTSUPP);
+                    return;
+                }
+            }
+
+            let end = u_offset.saturating_add(u_length);
+            if !keep_size && end > handle_db_err!(self.get_file_size(ino).await, reply) {
+                handle_db_err!(self.change_file_size(ino, end).await, reply);
+            }
+
+            reply.ok();
+        });
+    }
+
+    #[tracing::instrument]
+    fn lseek(
+        &mut self,
+        _req: &Request<'_>,
+        ino: u64,
+        _fh: u64,
+        offset: i64,
+        whence: i32,
+        reply: ReplyLseek,
+    ) {
+        self.runtime_handle.block_on(async {
+            // the kernel handles other whences itself
+            let data = match whence {
+                libc::SEEK_DATA => true,
+                libc::SEEK_HOLE => false,
+                _ => {
+                    reply.error(libc::EINVAL);
+                    return;
+                }
+            };
+
+            let u_offset: u64 = handle_from_int_err!(offset.try_into(), reply);
+            match handle_db_err!(self.seek_data(ino, u_offset, data).await, reply) {
+                Some(found) => {
+                    let found_offset = to_i64!(found, reply);
+                    reply.offset(found_offset);
+                }
+                None => reply.error(libc::ENXIO),
+            }
+        });
+    }
+
     #[tracing::instrument]
     fn rename(
         &mut self,
@@ -838,28 +674,34 @@ impl Filesystem for PTFS<Sqlite> {
         reply: ReplyEmpty,
     ) {
         self.runtime_handle.block_on(async {
-            let old_parent_name = if parent == 1 {
+            handle_vdir_readonly!(self, newparent, reply);
+            handle_vdir_name!(self, newparent, newname.to_str().unwrap(), reply);
+            // files can only be moved out of the untagged virtual directory
+            let from_untagged = match self.vdirs.get(parent) {
+                Some(VirtualDir::Untagged) => true,
+                Some(_) => {
+                    reply.error(libc::EROFS);
+                    return;
+                }
+                None => false,
+            };
+
+            let old_parent_name = if parent == 1 || from_untagged {
                 None
             } else {
-                Some(handle_db_err!(
-                    self.get_ino_name(to_i64!(parent, reply)).await,
-                    reply
-                ))
+                Some(handle_db_err!(self.get_ino_name(parent).await, reply))
             };
             let new_parent_name = if newparent == 1 {
                 None
             } else {
-                Some(handle_db_err!(
-                    self.get_ino_name(to_i64!(newparent, reply)).await,
-                    reply
-                ))
+                Some(handle_db_err!(self.get_ino_name(newparent).await, reply))
             };
             let old_parent_prefixed = old_parent_name.map(|n| self.is_prefixed(&n));
             let new_parent_prefixed = new_parent_name.map(|n| self.is_prefixed(&n));
             let new_parent_tags = if let Some(new_parent_prefixed) = new_parent_prefixed
                 && new_parent_prefixed
             {
-                Some(handle_db_err!(self.get_ass_tags(newparent).await, reply))
+                Some(handle_db_err!(self.store.get_tags(newparent).await, reply))
             } else {
                 None
             };
@@ -867,244 +709,401 @@ impl Filesystem for PTFS<Sqlite> {
             let new_name_prefixed = self.is_prefixed(newname.to_str().unwrap());
 
             // get file ino
-            let ino: u64 = handle_db_err!(
-                if let Some(old_parent_prefixed) = old_parent_prefixed
+            let ino: u64 = if from_untagged {
+                handle_db_err!(
+                    self.lookup_in_vdir(&VirtualDir::Untagged, name.to_str().unwrap())
+                        .await,
+                    reply
+                )
+                .ino
+            } else {
+                let parent_tags = if let Some(old_parent_prefixed) = old_parent_prefixed
                     && old_parent_prefixed
                 {
-                    let parent_tags = handle_db_err!(self.get_ass_tags(parent).await, reply);
-                    let mut query_builder =
-                        QueryBuilder::<Sqlite>::new("SELECT ino FROM file_names WHERE (ino IN (");
-                    handle_db_err!(chain_tagged_inos(&mut query_builder, &parent_tags), reply);
-                    query_builder
-                        .push(
-                            ") OR ino IN (SELECT cnt_ino FROM dir_contents WHERE dir_ino = ?)) \
-                             AND name = ?",
-                        )
-                        .build_query_scalar()
-                        .bind(to_i64!(parent, reply))
-                        .bind(name.to_str())
-                        .fetch_one(&self.pool)
-                        .await
+                    handle_db_err!(self.store.get_tags(parent).await, reply)
                 } else {
-                    query_scalar(
-                        "SELECT ino FROM file_names WHERE ino IN (SELECT cnt_ino FROM \
-                         dir_contents WHERE dir_ino = ?) AND name = ?",
-                    )
-                    .bind(to_i64!(parent, reply))
-                    .bind(name.to_str())
-                    .fetch_one(&self.pool)
-                    .await
-                },
-                reply
-            );
+                    vec![]
+                };
+                handle_db_err!(
+                    self.store
+                        .lookup_entry(parent, &parent_tags, &self.enc_name(name.to_str().unwrap()))
+                        .await,
+                    reply
+                )
+                .attr
+                .ino
+            };
 
             // check permissions
-            handle_auth_perm!(self, parent, req, reply, 0b100); // TODO: write too?
+            if !from_untagged {
+                handle_auth_perm!(self, parent, req, reply, 0b100); // TODO: write too?
+            }
             handle_auth_perm!(self, newparent, req, reply, 0b010);
             handle_auth_perm!(self, ino, req, reply, 0b010);
 
             // get file type
-            let kind: u64 = handle_db_err!(
-                query_scalar("SELECT kind FROM file_attrs WHERE ino = ?")
-                    .bind(to_i64!(ino, reply))
-                    .fetch_one(&self.pool)
-                    .await,
-                reply
-            );
-            let filetype = handle_db_err!(
-                to_filetype(handle_from_int_err!(u8::try_from(kind), reply)),
-                reply
-            );
-
-            // deny changing directory prefixed status
-            if filetype == FileType::Directory && old_name_prefixed != new_name_prefixed {
-                reply.error(libc::EINVAL);
-                return;
+            let filetype = handle_db_err!(self.store.get_attr(ino).await, reply).kind;
+
+            // directories becoming tag directories get virtual directories, which their contents
+            // can't be named like
+            if filetype == FileType::Directory && !old_name_prefixed && new_name_prefixed {
+                let contents = handle_db_err!(self.store.get_dir_contents(ino).await, reply);
+                let vdir_names = [RECENT_NAME, BY_MTIME_NAME, BY_CRTIME_NAME, SEARCH_NAME];
+                if vdir_names.iter().any(|vdir| {
+                    contents
+                        .iter()
+                        .any(|(_, name)| *name == self.enc_name(vdir))
+                }) {
+                    reply.error(libc::EEXIST);
+                    return;
+                }
             }
 
-            let tagged_children = if filetype == FileType::Directory && old_name_prefixed {
-                // get children baesd on old tags and dir content
-                let old_tags = handle_db_err!(self.get_ass_tags(ino).await, reply);
-                let mut query_builder =
-                    QueryBuilder::<Sqlite>::new("SELECT ino FROM file_attrs WHERE ino IN (");
-                handle_db_err!(chain_tagged_inos(&mut query_builder, &old_tags), reply);
-                query_builder
-                    .push(") OR ino IN (SELECT cnt_ino FROM dir_contents WHERE dir_ino = ?)");
-                Some(handle_db_err!(
-                    query_builder
-                        .build_query_scalar::<u64>()
-                        .bind(to_i64!(ino, reply))
-                        .fetch_all(&self.pool)
-                        .await,
+            // the file and, when a tag directory is renamed, promoted or demoted, the files whose
+            // tags change along with it leave their old tag views and join new ones
+            let mut moved = vec![ino];
+            if filetype == FileType::Directory && (old_name_prefixed || new_name_prefixed) {
+                let tags = if old_name_prefixed {
+                    handle_db_err!(self.store.get_tags(ino).await, reply)
+                } else {
+                    vec![]
+                };
+                moved.extend(handle_db_err!(
+                    self.store.get_members(ino, &tags).await,
                     reply
-                ))
-            } else {
-                None
-            };
+                ));
+                moved.sort();
+                moved.dedup();
+            }
+            let old_views = handle_db_err!(self.get_tag_views(&moved).await, reply);
+
+            let tagged_children =
+                if filetype == FileType::Directory && old_name_prefixed && new_name_prefixed {
+                    // get children baesd on old tags and dir content
+                    let old_tags = handle_db_err!(self.store.get_tags(ino).await, reply);
+                    Some(handle_db_err!(
+                        self.store.get_members(ino, &old_tags).await,
+                        reply
+                    ))
+                } else {
+                    None
+                };
+
+            let tag_members =
+                if filetype == FileType::Directory && old_name_prefixed && !new_name_prefixed {
+                    // get files listed by the tag before the directory loses its tags
+                    Some(handle_db_err!(self.get_tagged_members(ino).await, reply))
+                } else {
+                    None
+                };
 
             if let Some(old_parent_prefixed) = old_parent_prefixed
                 && old_parent_prefixed
             {
                 // remove all file's associations
-                handle_db_err!(
-                    query("DELETE from associated_tags WHERE ino = $1")
-                        .bind(to_i64!(ino, reply))
-                        .execute(&self.pool)
-                        .await,
-                    reply
-                );
-            } else {
+                handle_db_err!(self.store.clear_tags(ino).await, reply);
+            } else if !from_untagged {
                 // delete file from old parent's contents
-                handle_db_err!(
-                    query("DELETE FROM dir_contents WHERE cnt_ino = $1 AND dir_ino = $2")
-                        .bind(to_i64!(ino, reply))
-                        .bind(to_i64!(parent, reply))
-                        .execute(&self.pool)
-                        .await,
-                    reply
-                );
+                handle_db_err!(self.store.remove_from_dir(parent, ino).await, reply);
             }
 
             if let Some(new_parent_tags) = &new_parent_tags {
                 // associate file with new parent's tags
                 for new_tid in new_parent_tags {
-                    handle_db_err!(
-                        query("INSERT INTO associated_tags (ino, tid) VALUES ($1, $2)")
-                            .bind(to_i64!(ino, reply))
-                            .bind(to_i64!(*new_tid, reply))
-                            .execute(&self.pool)
-                            .await,
-                        reply
-                    );
+                    handle_db_err!(self.store.tag(*new_tid, ino).await, reply);
                 }
 
                 if filetype == FileType::Directory && new_name_prefixed {
                     // put file into newparent's content
-                    handle_db_err!(
-                        query("INSERT INTO dir_contents (cnt_ino, dir_ino) VALUES ($1, $2)")
-                            .bind(to_i64!(ino, reply))
-                            .bind(to_i64!(newparent, reply))
-                            .execute(&self.pool)
-                            .await,
-                        reply
-                    );
+                    handle_db_err!(self.store.add_to_dir(newparent, ino).await, reply);
                 }
             } else {
                 // put file into newparent's content
-                handle_db_err!(
-                    query("INSERT INTO dir_contents (cnt_ino, dir_ino) VALUES ($1, $2)")
-                        .bind(to_i64!(ino, reply))
-                        .bind(to_i64!(newparent, reply))
-                        .execute(&self.pool)
-                        .await,
-                    reply
-                );
+                handle_db_err!(self.store.add_to_dir(newparent, ino).await, reply);
             }
 
-            if filetype == FileType::Directory && old_name_prefixed && new_name_prefixed {
+            if filetype == FileType::Directory && new_name_prefixed {
                 // get new tid basd on new name
-                let new_tid = match handle_db_err!(
-                    query_scalar::<_, u64>("SELECT tid FROM tags WHERE name = $1")
-                        .bind(newname.to_str())
-                        .fetch_optional(&self.pool)
-                        .await,
-                    reply
-                ) {
+                let new_tag = self.enc_name(newname.to_str().unwrap());
+                let new_tid = match handle_db_err!(self.store.get_tid(&new_tag).await, reply) {
                     Some(tid_row) => tid_row,
                     None => {
                         // create new corresponding tag if it doesn't yet exist
-                        handle_db_err!(
-                            query_scalar::<_, u64>(
-                                "INSERT INTO tags (name) VALUES ($1) RETURNING tid"
-                            )
-                            .bind(newname.to_str())
-                            .fetch_one(&self.pool)
-                            .await,
-                            reply
-                        )
+                        let tid = handle_db_err!(self.store.create_tag(&new_tag).await, reply);
+                        self.inval_tag_entry(newname.to_str().unwrap());
+                        tid
                     }
                 };
 
-                // remove all children associations
-                for child_ino in tagged_children.as_ref().unwrap() {
-                    handle_db_err!(
-                        query("DELETE FROM associated_tags WHERE ino = $1")
-                            .bind(to_i64!(*child_ino, reply))
-                            .execute(&self.pool)
-                            .await,
-                        reply
-                    );
-                }
+                if let Some(tagged_children) = &tagged_children {
+                    // remove all children associations
+                    for child_ino in tagged_children {
+                        handle_db_err!(self.store.clear_tags(*child_ino).await, reply);
+                    }
 
-                if let Some(new_parent_prefixed) = new_parent_prefixed
-                    && new_parent_prefixed
-                {
-                    // associate children with newparent's tags
-                    for child_ino in tagged_children.as_ref().unwrap() {
-                        for new_tid in new_parent_tags.as_ref().unwrap() {
-                            handle_db_err!(
-                                query("INSERT INTO associated_tags (ino, tid) VALUES ($1, $2)")
-                                    .bind(to_i64!(*child_ino, reply))
-                                    .bind(to_i64!(*new_tid, reply))
-                                    .execute(&self.pool)
-                                    .await,
-                                reply
-                            );
+                    if let Some(new_parent_prefixed) = new_parent_prefixed
+                        && new_parent_prefixed
+                    {
+                        // associate children with newparent's tags
+                        for child_ino in tagged_children {
+                            for new_tid in new_parent_tags.as_ref().unwrap() {
+                                handle_db_err!(self.store.tag(*new_tid, *child_ino).await, reply);
+                            }
                         }
                     }
-                }
 
-                // associate children with the new tid
-                for child_ino in tagged_children.as_ref().unwrap() {
-                    handle_db_err!(
-                        query("INSERT INTO associated_tags (tid, ino) VALUES ($1, $2)")
-                            .bind(to_i64!(new_tid, reply))
-                            .bind(to_i64!(*child_ino, reply))
-                            .execute(&self.pool)
-                            .await,
-                        reply
-                    );
-                }
+                    // associate children with the new tid
+                    for child_ino in tagged_children {
+                        handle_db_err!(self.store.tag(new_tid, *child_ino).await, reply);
+                    }
 
-                // delete old tag if there are no other associations
-                let old_tid = handle_db_err!(
-                    query_scalar::<_, u64>("SELECT tid FROM tags WHERE name = $1")
-                        .bind(name.to_str())
-                        .fetch_one(&self.pool)
-                        .await,
-                    reply
-                );
-                let associated_old_tags_count = handle_db_err!(
-                    query_scalar::<_, u64>("SELECT COUNT(*) FROM associated_tags WHERE tid = $1")
-                        .bind(to_i64!(old_tid, reply))
-                        .fetch_one(&self.pool)
-                        .await,
-                    reply
-                );
-                if associated_old_tags_count == 0 {
-                    handle_db_err!(
-                        query("DELETE FROM tags WHERE tid = $1")
-                            .bind(to_i64!(old_tid, reply))
-                            .execute(&self.pool)
-                            .await,
-                        reply
-                    );
+                    // delete old tag if there are no other associations
+                    let old_tid = handle_db_err!(self.get_tid(name.to_str().unwrap()).await, reply);
+                    handle_db_err!(self.del_tid_if_orphan(old_tid).await, reply);
+                    self.inval_tag_entry(name.to_str().unwrap());
+                } else {
+                    // promote directory into a tag, associating it and its contents with the new
+                    // tid
+                    handle_db_err!(self.store.tag(new_tid, ino).await, reply);
+                    let dir_tags = handle_db_err!(self.store.get_tags(ino).await, reply);
+                    let contents = handle_db_err!(self.store.get_dir_contents(ino).await, reply);
+                    for (child_ino, child_name) in contents {
+                        for tid in &dir_tags {
+                            handle_db_err!(self.store.tag(*tid, child_ino).await, reply);
+                        }
+
+                        // unprefixed files in a tag are only listed through their tags
+                        if !self.is_prefixed(&child_name) {
+                            handle_db_err!(self.store.remove_from_dir(ino, child_ino).await, reply);
+                        }
+                    }
+                }
+            } else if let Some(tag_members) = &tag_members {
+                // demote tag into a plain directory, moving the files listed by the tag into its
+                // contents
+                let old_tid = handle_db_err!(self.get_tid(name.to_str().unwrap()).await, reply);
+                for member_ino in tag_members {
+                    handle_db_err!(self.store.add_to_dir(ino, *member_ino).await, reply);
+                }
+                // the directory and everything in it no longer carry the tag
+                handle_db_err!(self.store.untag(old_tid, ino).await, reply);
+                let contents = handle_db_err!(self.store.get_dir_contents(ino).await, reply);
+                for (child_ino, _) in contents {
+                    handle_db_err!(self.store.untag(old_tid, child_ino).await, reply);
                 }
+                handle_db_err!(self.del_tid_if_orphan(old_tid).await, reply);
+                self.inval_tag_entry(name.to_str().unwrap());
             }
 
             if name != newname {
                 // update file_names table
                 handle_db_err!(
-                    query("UPDATE file_names SET name = $1 WHERE ino = $2")
-                        .bind(newname.to_str())
-                        .bind(to_i64!(ino, reply))
-                        .execute(&self.pool)
+                    self.store
+                        .set_name(ino, &self.enc_name(newname.to_str().unwrap()))
                         .await,
                     reply
                 );
             }
 
+            let new_views = handle_db_err!(self.get_tag_views(&moved).await, reply);
+            self.inval_views(&old_views);
+            self.inval_views(&new_views);
+
             reply.ok();
         })
     }
 }
+
+/// Reply to `readdir` or `readdirplus`, the latter of which takes entries with their attributes
+trait DirReply {
+    /// Whether entries are added with their attributes
+    const PLUS: bool;
+
+    /// Add `entry` at `offset`, cached for `ttl` if it has attributes, returns whether the reply
+    /// is full
+    fn add_entry(&mut self, offset: i64, entry: &DirEntry, ttl: &Duration) -> bool;
+    fn ok(self);
+    fn error(self, err: c_int);
+}
+
+impl DirReply for ReplyDirectory {
+    const PLUS: bool = false;
+
+    fn add_entry(&mut self, offset: i64, entry: &DirEntry, _ttl: &Duration) -> bool {
+        self.add(entry.ino, offset, entry.kind, &entry.name)
+    }
+
+    fn ok(self) {
+        self.ok();
+    }
+
+    fn error(self, err: c_int) {
+        self.error(err);
+    }
+}
+
+impl DirReply for ReplyDirectoryPlus {
+    const PLUS: bool = true;
+
+    fn add_entry(&mut self, offset: i64, entry: &DirEntry, ttl: &Duration) -> bool {
+        let attr = entry
+            .attr
+            .as_ref()
+            .expect("readdirplus entries are listed with their attributes");
+        self.add(entry.ino, offset, &entry.name, ttl, attr, 0)
+    }
+
+    fn ok(self) {
+        self.ok();
+    }
+
+    fn error(self, err: c_int) {
+        self.error(err);
+    }
+}
+
+impl<S: Store> PTFS<S> {
+    /// Fill `reply` with the entries of `ino` from `offset` on
+    ///
+    /// `.` and `..` take the first two offsets, later entries are at their key + 2, so listing
+    /// resumes after the last entry the kernel got even if others were removed in between.
+    async fn fill_dir<R: DirReply>(&self, req: &Request<'_>, ino: u64, offset: i64, mut reply: R) {
+        let vdir = self.vdirs.get(ino);
+        if vdir.is_none() {
+            handle_auth_perm!(self, ino, req, reply, 0b100);
+        }
+
+        if offset < 1 {
+            let dot = handle_db_err!(self.dir_entry_of(ino, ".").await, reply);
+            if reply.add_entry(1, &dot, &self.ttl.entry) {
+                reply.ok();
+                return;
+            }
+        }
+        if offset < 2 {
+            let parent = match &vdir {
+                Some(vdir) => self.vdir_parent(vdir),
+                // directories only listed through their tags are reached from the root
+                None => handle_db_err!(self.store.get_parent(ino).await, reply).unwrap_or(1),
+            };
+            let dotdot = handle_db_err!(self.dir_entry_of(parent, "..").await, reply);
+            if reply.add_entry(2, &dotdot, &self.ttl.entry) {
+                reply.ok();
+                return;
+            }
+        }
+        let mut after: u64 = handle_from_int_err!((offset.max(2) - 2).try_into(), reply);
+
+        // files tagged with all of a tag directory's tags are listed along with its contents
+        let tags = if vdir.is_none()
+            && ino != 1
+            && self.is_prefixed(&handle_db_err!(self.get_ino_name(ino).await, reply))
+        {
+            handle_db_err!(self.store.get_tags(ino).await, reply)
+        } else {
+            vec![]
+        };
+
+        'fill: loop {
+            let entries = match &vdir {
+                Some(vdir) => handle_db_err!(
+                    self.get_vdir_entries(vdir, after, READDIR_BATCH).await,
+                    reply
+                ),
+                None => {
+                    let children = handle_db_err!(
+                        self.store
+                            .list_entries(ino, &tags, &self.tag_prefix, after, READDIR_BATCH)
+                            .await,
+                        reply
+                    );
+                    let mut entries = Vec::with_capacity(children.len());
+                    for child in children {
+                        entries.push(handle_db_err!(self.dir_entry(child), reply));
+                    }
+                    entries
+                }
+            };
+            let last_batch = (entries.len() as u64) < READDIR_BATCH;
+
+            for mut entry in entries {
+                if R::PLUS && entry.attr.is_none() {
+                    entry.attr = Some(handle_db_err!(self.get_vdir_attr(entry.ino).await, reply));
+                }
+                if reply.add_entry(to_i64!(entry.key + 2, reply), &entry, &self.ttl.entry) {
+                    break 'fill;
+                }
+                // the kernel looks up entries with attributes, except for `.` and `..`
+                if R::PLUS {
+                    self.vdirs.looked_up(entry.ino);
+                }
+                after = entry.key;
+            }
+            if last_batch {
+                break;
+            }
+        }
+        if vdir.is_none() {
+            handle_db_err!(self.sync_atime(ino).await, reply);
+        }
+        reply.ok();
+    }
+
+    /// Get the attributes of the entry called `name` in `parent`, which is `vdir` if it's virtual
+    async fn lookup_attr(
+        &self,
+        parent: u64,
+        vdir: Option<VirtualDir>,
+        name: &str,
+    ) -> Result<FileAttr, DBError> {
+        if let Some(vdir) = vdir {
+            return self.lookup_in_vdir(&vdir, name).await;
+        }
+        if let Some(vdir_ino) = self.lookup_vdir(parent, name).await? {
+            return self.get_vdir_attr(vdir_ino).await;
+        }
+
+        let ptags = self.store.get_tags(parent).await?;
+        Ok(self
+            .store
+            .lookup_entry(parent, &ptags, &self.enc_name(name))
+            .await?
+            .attr)
+    }
+
+    /// Get the directory `ino` as the entry `name`, along with its attributes
+    async fn dir_entry_of(&self, ino: u64, name: &str) -> Result<DirEntry, DBError> {
+        let attr = match self.vdirs.get(ino) {
+            Some(_) => self.get_vdir_attr(ino).await?,
+            None => self.store.get_attr(ino).await?,
+        };
+        Ok(DirEntry {
+            key: 0,
+            ino,
+            kind: attr.kind,
+            name: name.to_string(),
+            attr: Some(attr),
+        })
+    }
+}
+
+/// Attributes of a negative entry, which only has inode 0
+fn negative_attr() -> FileAttr {
+    FileAttr {
+        ino: 0,
+        size: 0,
+        blocks: 0,
+        atime: SystemTime::UNIX_EPOCH,
+        mtime: SystemTime::UNIX_EPOCH,
+        ctime: SystemTime::UNIX_EPOCH,
+        crtime: SystemTime::UNIX_EPOCH,
+        kind: FileType::RegularFile,
+        perm: 0,
+        nlink: 0,
+        uid: 0,
+        gid: 0,
+        rdev: 0,
+        blksize: 0,
+        flags: 0,
+    }
+}
@@ -0,0 +1,172 @@
+use crate::{PTFS, db_helpers::types::DBError, store::Store, vdirs::VirtualDir};
+use fuser::Notifier;
+use std::{
+    collections::HashMap,
+    ffi::OsString,
+    io::ErrorKind as IoErrorKind,
+    sync::{
+        Arc, OnceLock,
+        mpsc::{Sender, channel},
+    },
+    thread,
+};
+
+/// Files whose tag views are looked up at a time, bounding the parameters of a query
+const VIEW_BATCH: usize = 512;
+
+/// Kernel cache entry to drop
+#[derive(Debug)]
+enum Inval {
+    /// Entry called `name` in `parent`
+    Entry { parent: u64, name: OsString },
+    /// Attributes and contents of an inode
+    Inode(u64),
+    /// Nothing, replied to once the invalidations before it are sent
+    Flush(Sender<()>),
+}
+
+/// Sender of a mount's kernel cache invalidations
+///
+/// The kernel keeps the directories a request changes locked until it's replied to, and
+/// invalidating entries in them waits for that lock, so invalidations are sent by a thread of
+/// their own instead of the request's handler. Nothing is sent before [`Invalidator::start`].
+#[derive(Debug, Default, Clone)]
+pub struct Invalidator(Arc<OnceLock<Sender<Inval>>>);
+
+impl Invalidator {
+    /// Start sending invalidations with the notifier of the mount's session
+    pub fn start(&self, notifier: Notifier) {
+        let (sender, receiver) = channel();
+        if self.0.set(sender).is_err() {
+            return;
+        }
+        thread::spawn(move || {
+            for inval in receiver {
+                let sent = match &inval {
+                    Inval::Entry { parent, name } => notifier.inval_entry(*parent, name),
+                    Inval::Inode(ino) => notifier.inval_inode(*ino, 0, 0),
+                    Inval::Flush(flushed) => {
+                        flushed.send(()).ok();
+                        continue;
+                    }
+                };
+                // the kernel doesn't know about entries and inodes it hasn't cached
+                if let Err(e) = sent
+                    && e.kind() != IoErrorKind::NotFound
+                {
+                    tracing::warn!("failed invalidating {inval:?}: {e}");
+                }
+            }
+        });
+    }
+
+    /// Drop the kernel's cached entry called `name` in `parent`, whether it was found or not
+    pub fn inval_entry(&self, parent: u64, name: &str) {
+        self.send(Inval::Entry {
+            parent,
+            name: name.into(),
+        });
+    }
+
+    /// Drop the kernel's cached attributes and contents of `ino`
+    pub fn inval_inode(&self, ino: u64) {
+        self.send(Inval::Inode(ino));
+    }
+
+    /// Wait until the kernel dropped the entries and inodes invalidated so far
+    ///
+    /// Invalidations can be sent after the request that caused them was replied to, so other
+    /// processes can briefly see stale entries, unless they wait for this.
+    pub fn flush(&self) {
+        let (flushed, done) = channel();
+        self.send(Inval::Flush(flushed));
+        // fails right away if nothing is sent, i.e. before `start`
+        done.recv().ok();
+    }
+
+    fn send(&self, inval: Inval) {
+        if let Some(sender) = self.0.get() {
+            // the thread only stops once every sender is dropped
+            sender.send(inval).ok();
+        }
+    }
+}
+
+impl<S: Store> PTFS<S> {
+    /// Get the directories listing each of `inos` through its tags, rather than as their content,
+    /// along with its name
+    ///
+    /// These are the tag directories and `.tags` entries of its tags, and the virtual directories
+    /// of files filtered by something else than tags, in the root or in those tag directories,
+    /// which might list it as well.
+    pub(crate) async fn get_tag_views(
+        &self,
+        inos: &[u64],
+    ) -> Result<Vec<(Vec<u64>, String)>, DBError> {
+        let vdirs = self.vdirs.all();
+        let mut named_views = Vec::with_capacity(inos.len());
+        for batch in inos.chunks(VIEW_BATCH) {
+            let mut views: HashMap<u64, Vec<u64>> = HashMap::new();
+            for (ino, dir) in self.store.get_tag_views(batch, &self.tag_prefix).await? {
+                views.entry(ino).or_default().push(dir);
+            }
+            let mut files: HashMap<u64, (String, Vec<u64>)> = HashMap::new();
+            for (ino, name, tid) in self.store.get_names_and_tags(batch).await? {
+                files.entry(ino).or_insert((name, vec![])).1.extend(tid);
+            }
+
+            for ino in batch {
+                let (name, tags) = files.get(ino).ok_or(sqlx::Error::RowNotFound)?;
+                let mut views = views.get(ino).cloned().unwrap_or_default();
+                let is_view = |base: &u64| *base == 1 || views.contains(base);
+                let vdir_views: Vec<u64> = vdirs
+                    .iter()
+                    .filter(|(_, vdir)| match vdir {
+                        VirtualDir::Tag(tid) => tags.contains(tid),
+                        VirtualDir::Untagged => tags.is_empty(),
+                        VirtualDir::RecentWindow { base, .. }
+                        | VirtualDir::ByTime {
+                            base,
+                            month: Some(_),
+                            ..
+                        }
+                        | VirtualDir::Search {
+                            base,
+                            pattern: Some(_),
+                        } => is_view(base),
+                        VirtualDir::Tags
+                        | VirtualDir::Recent { .. }
+                        | VirtualDir::ByTime { .. }
+                        | VirtualDir::Search { .. } => false,
+                    })
+                    .map(|(vdir_ino, _)| *vdir_ino)
+                    .collect();
+                views.extend(vdir_views);
+                named_views.push((views, self.dec_name(name.clone())?));
+            }
+        }
+        Ok(named_views)
+    }
+
+    /// Drop the kernel's cached `.tags` entry called `name`, e.g. after creating or deleting the
+    /// tag
+    pub(crate) fn inval_tag_entry(&self, name: &str) {
+        // the kernel can't have cached `.tags` if it has no inode
+        if let Some(tags) = self.vdirs.find(&VirtualDir::Tags) {
+            self.invalidator.inval_entry(tags, name);
+        }
+    }
+
+    /// Drop the kernel's cached entries of files in their tag views, see [`PTFS::get_tag_views`],
+    /// along with the attributes of the views, e.g. the file counts of `.tags` entries
+    pub(crate) fn inval_views(&self, named_views: &[(Vec<u64>, String)]) {
+        for (views, name) in named_views {
+            for view in views {
+                self.invalidator.inval_entry(*view, name);
+                if self.vdirs.get(*view).is_some() {
+                    self.invalidator.inval_inode(*view);
+                }
+            }
+        }
+    }
+}
@@ -1,72 +1,94 @@
 #[macro_use]
 mod macros;
+mod blobs;
+mod contents;
+mod crypto;
 mod db_helpers;
 mod fs;
+mod inval;
+mod settings;
+mod store;
 mod test_db;
-use db_helpers::{
-    try_bind_attrs,
-    types::{Bindable, DBError, FileAttrRow, from_systime},
-};
-use fuser::{FileAttr, Request};
+mod vdirs;
+use db_helpers::types::{ConvError, DBError};
+use fuser::{FileAttr, FileType, Request};
 use libc::c_int;
-use sqlx::{Database, Pool, Sqlite, query, query_as, query_scalar};
-use std::{num::TryFromIntError, time::SystemTime};
+use std::{num::TryFromIntError, path::PathBuf, time::SystemTime};
 use tokio::runtime::Handle;
+use vdirs::VirtualDirs;
+
+pub use blobs::FsckReport;
+pub use crypto::{Cipher, SALT_LEN};
+pub use inval::Invalidator;
+pub use settings::{Compression, DEFAULT_CHUNK_SIZE, Encryption, Settings, Ttl};
+#[cfg(feature = "postgres")]
+pub use store::postgres::PgStore;
+pub use store::{Store, sqlite::SqliteStore};
+
+/// Name of the root directory holding files that were left without tags or a parent directory
+pub const LOST_FOUND_NAME: &str = "lost+found";
 
 #[derive(Debug)]
-pub struct PTFS<DB: Database> {
-    pub pool: Pool<DB>,
+pub struct PTFS<S: Store> {
+    pub store: S,
     pub runtime_handle: Handle,
     pub tag_prefix: String,
+    /// Name of the root's virtual directory listing files with no tags and no parent directory
+    pub untagged_name: String,
+    pub vdirs: VirtualDirs,
+    /// Replaced by the database's settings on mount
+    pub settings: Settings,
+    /// Key of an encrypted database, see [`PTFS::unlock`]
+    pub cipher: Option<Cipher>,
+    /// Directory holding chunks above [`Settings::blob_threshold`], named by hash
+    pub blob_dir: PathBuf,
+    /// How long the kernel caches replies, [`Settings::ttl`] unless overridden when mounting
+    pub ttl: Ttl,
+    /// Invalidates kernel caches of entries that change outside of the directory they're changed
+    /// in, e.g. in tag directories listing a renamed file
+    pub invalidator: Invalidator,
 }
 
-impl PTFS<Sqlite> {
-    async fn ins_attrs(&self, attr: &FileAttr) -> Result<u64, DBError> {
-        let q = query_scalar::<_, u64>(
-            "INSERT INTO file_attrs VALUES (NULL, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, \
-             $13, $14, $15) RETURNING ino",
-        );
-        Ok(try_bind_attrs(q, attr)?
-            .inner()
-            .fetch_one(&self.pool)
-            .await?)
-    }
-
-    async fn upd_attrs(&self, attr: &FileAttr) -> Result<(), DBError> {
-        let q = query(
-            "UPDATE file_attrs SET size = $2, blocks = $3, atime = $4, mtime = $5, ctime = $6, \
-             crtime = $7, kind = $8, perm = $9, nlink = $10, uid = $11, gid = $12, rdev = $13, \
-             blksize = $14, flags = $15 WHERE ino = $1",
-        );
-        try_bind_attrs(q, attr)?.execute(&self.pool).await?;
-        Ok(())
+impl<S: Store> PTFS<S> {
+    /// Create a new database with the current settings
+    pub async fn mkfs(&self) -> Result<(), DBError> {
+        self.migrate().await?;
+        self.settings.save(&self.store).await
     }
 
-    async fn get_ass_tags(&self, ino: u64) -> Result<Vec<u64>, DBError> {
-        Ok(
-            query_scalar::<_, u64>("SELECT tid FROM associated_tags WHERE ino = ?")
-                .bind(i64::try_from(ino)?)
-                .fetch_all(&self.pool)
-                .await?,
-        )
+    /// Set the key of an encrypted database, failing if it isn't the database's key or if the tag
+    /// prefix is hex, see [`PTFS::hex_prefix`]
+    pub fn unlock(&mut self, cipher: Cipher) -> Result<(), DBError> {
+        if self.hex_prefix() {
+            return Err(ConvError::TagPrefix.into());
+        }
+        match &self.settings.encryption {
+            Some(encryption) if encryption.key_check == cipher.key_check() => {
+                self.cipher = Some(cipher);
+                Ok(())
+            }
+            _ => Err(ConvError::Crypto.into()),
+        }
+    }
+
+    /// Whether the tag prefix starts like names encrypted into hex, which would be mistaken for
+    /// prefixed names
+    pub fn hex_prefix(&self) -> bool {
+        self.tag_prefix
+            .starts_with(|c: char| c.is_ascii_digit() || ('a'..='f').contains(&c))
+    }
+
+    /// Create or update the database's tables
+    pub async fn migrate(&self) -> Result<(), DBError> {
+        self.store.migrate().await
     }
 
     async fn sync_mtime(&self, ino: u64) -> Result<(), DBError> {
-        query("UPDATE file_attrs SET mtime = ? WHERE ino = ?")
-            .bind(i64::try_from(from_systime(SystemTime::now())?)?)
-            .bind(i64::try_from(ino)?)
-            .execute(&self.pool)
-            .await?;
-        Ok(())
+        self.store.set_mtime(ino, SystemTime::now()).await
     }
 
     async fn sync_atime(&self, ino: u64) -> Result<(), DBError> {
-        query("UPDATE file_attrs SET atime = ? WHERE ino = ?")
-            .bind(i64::try_from(from_systime(SystemTime::now())?)?)
-            .bind(i64::try_from(ino)?)
-            .execute(&self.pool)
-            .await?;
-        Ok(())
+        self.store.set_atime(ino, SystemTime::now()).await
     }
 
     async fn req_has_ino_perm(
@@ -79,10 +101,7 @@ impl PTFS<Sqlite> {
     }
 
     async fn has_ino_perm(&self, ino: u64, uid: u32, gid: u32, rwx: u16) -> Result<bool, DBError> {
-        let p_attrs = query_as::<_, FileAttrRow>("SELECT * FROM file_attrs WHERE ino = ?")
-            .bind(i64::try_from(ino)?)
-            .fetch_one(&self.pool)
-            .await?;
+        let p_attrs = self.store.get_attr(ino).await?;
 
         Ok(has_perm(
             p_attrs.uid,
@@ -102,105 +121,171 @@ impl PTFS<Sqlite> {
         }
     }
 
-    async fn get_ino_name(&self, ino: i64) -> Result<String, DBError> {
-        query_scalar("SELECT name FROM file_names WHERE ino = ?")
-            .bind(ino)
-            .fetch_one(&self.pool)
-            .await
-            .map_err(|e| DBError::from(e))
+    /// Get unprefixed inodes that are tagged with all of `ino`'s tags
+    async fn get_tagged_members(&self, ino: u64) -> Result<Vec<u64>, DBError> {
+        let tags = self.store.get_tags(ino).await?;
+        self.store.get_tagged(&tags, &self.tag_prefix).await
     }
 
-    async fn get_db_page_size(&self) -> Result<u64, DBError> {
-        query_scalar("PRAGMA page_size")
-            .fetch_one(&self.pool)
-            .await
-            .map_err(|e| DBError::from(e))
+    async fn get_ino_name(&self, ino: u64) -> Result<String, DBError> {
+        self.dec_name(
+            self.store
+                .get_name(ino)
+                .await?
+                .ok_or(sqlx::Error::RowNotFound)?,
+        )
+    }
+    /// Get the tid of the tag called `name`
+    async fn get_tid(&self, name: &str) -> Result<u64, DBError> {
+        Ok(self
+            .store
+            .get_tid(&self.enc_name(name))
+            .await?
+            .ok_or(sqlx::Error::RowNotFound)?)
     }
 
-    async fn change_file_size(&self, ino: u64, new_size: u64) -> Result<(), DBError> {
-        let ino: i64 = ino.try_into()?;
-        let new_size: i64 = new_size.try_into()?;
-        let page_size: i64 = self.get_db_page_size().await?.try_into()?;
-        let usize_page_size = page_size.try_into()?;
-        let new_last_page = new_size / page_size;
-        let new_last_page_size = new_size % page_size;
-
-        let create_new_last_page = || {
-            query("INSERT INTO file_contents (ino, page, bytes) VALUES (?,?,ZEROBLOB(?))")
-                .bind(ino)
-                .bind(new_last_page)
-                .bind(new_last_page_size)
-                .execute(&self.pool)
-        };
-        let truncate_new_last_page = || {
-            query(
-                "UPDATE file_contents SET bytes = CAST(SUBSTR(bytes, 1, ?) AS BLOB) WHERE ino = ? \
-                 and page = ?",
-            )
-            .bind(new_last_page_size)
-            .bind(ino)
-            .bind(new_last_page)
-            .execute(&self.pool)
+    /// Get the name stored for `name`, which is encrypted on encrypted databases
+    ///
+    /// The tag prefix is kept unencrypted, so prefixed names can still be told apart in queries.
+    fn enc_name(&self, name: &str) -> String {
+        let Some(cipher) = &self.cipher else {
+            return name.to_string();
         };
+        match self.is_prefixed(name) {
+            true => {
+                let rest = &name[self.tag_prefix.len()..];
+                format!("{}{}", self.tag_prefix, cipher.encrypt_name(rest))
+            }
+            false => cipher.encrypt_name(name),
+        }
+    }
 
-        // check file has content
-        let old_last_page_query: Option<(i64, Vec<u8>)> = query_as(
-            "SELECT page, bytes FROM file_contents WHERE ino = ? ORDER BY page DESC LIMIT 1",
-        )
-        .bind(ino)
-        .fetch_optional(&self.pool)
-        .await?;
-        if let Some((old_last_page, mut old_last_page_bytes)) = old_last_page_query {
-            if new_last_page > old_last_page {
-                // rpad old last page
-                old_last_page_bytes.resize(usize_page_size, 0);
-                query("UPDATE file_contents SET bytes = ? WHERE ino = ? and page = ?")
-                    .bind(old_last_page_bytes)
-                    .bind(ino)
-                    .bind(old_last_page)
-                    .execute(&self.pool)
-                    .await?;
-
-                create_new_last_page().await?;
-            } else if new_last_page < old_last_page {
-                // delete pages > new_last_page
-                query("DELETE FROM file_contents WHERE ino = ? AND page > ?")
-                    .bind(ino)
-                    .bind(new_last_page)
-                    .execute(&self.pool)
-                    .await?;
-                // check if new_last_page exists
-                match query("SELECT 1 FROM file_contents WHERE ino = ? AND page = ?")
-                    .bind(ino)
-                    .bind(new_last_page)
-                    .fetch_optional(&self.pool)
-                    .await?
-                {
-                    Some(_) => truncate_new_last_page().await?,
-                    None => create_new_last_page().await?,
-                };
-            } else if new_last_page == old_last_page {
-                truncate_new_last_page().await?;
-            };
-        } else {
-            create_new_last_page().await?;
+    /// Get the name of a name stored by [`PTFS::enc_name`]
+    fn dec_name(&self, name: String) -> Result<String, DBError> {
+        let Some(cipher) = &self.cipher else {
+            return Ok(name);
         };
-        Ok(())
+        Ok(match self.is_prefixed(&name) {
+            true => format!(
+                "{}{}",
+                self.tag_prefix,
+                cipher.decrypt_name(&name[self.tag_prefix.len()..])?
+            ),
+            false => cipher.decrypt_name(&name)?,
+        })
+    }
+
+    /// Set the size of `ino` to `new_size`, dropping pages past it
+    ///
+    /// Growing a file leaves a hole, no pages are stored for it.
+    async fn change_file_size(&self, ino: u64, new_size: u64) -> Result<(), DBError> {
+        let page_size = self.settings.chunk_size;
+
+        self.store
+            .delete_pages(ino, new_size.div_ceil(page_size), None)
+            .await?;
+        // cut bytes past the new size off the new last page, so they don't reappear if the file
+        // grows again
+        let new_last_page_size: usize = (new_size % page_size).try_into()?;
+        if new_last_page_size > 0
+            && let Some(mut bytes) = self.get_page(ino, new_size / page_size).await?
+            && bytes.len() > new_last_page_size
+        {
+            bytes.truncate(new_last_page_size);
+            self.put_page(ino, new_size / page_size, &bytes).await?;
+        }
+
+        self.store.set_size(ino, new_size).await?;
+        self.update_blocks(ino).await?;
+        self.gc_chunks().await
     }
 
     /// Delete tag if it has no associated files
     async fn del_tid_if_orphan(&self, tid: u64) -> Result<(), DBError> {
-        let i64_tid: i64 = tid.try_into()?;
-        let associated_files_amt: u64 =
-            query_scalar("SELECT COUNT() FROM associated_tags WHERE tid = ?")
-                .bind(i64_tid)
-                .fetch_one(&self.pool)
-                .await?;
-        if associated_files_amt == 0 {
-            query("DELETE FROM tags WHERE tid = ?")
-                .bind(i64_tid)
-                .execute(&self.pool)
-                .await?;
+        if self.store.count_tagged(tid).await? == 0 {
+            self.store.delete_tag(tid).await?;
+        }
+        Ok(())
+    }
+
+    /// Get the inode of the root's lost+found directory, creating it if it doesn't exist
+    async fn get_lost_found(&self) -> Result<u64, DBError> {
+        let lost_found_name = self.enc_name(LOST_FOUND_NAME);
+        match self.store.lookup_entry(1, &[], &lost_found_name).await {
+            Ok(entry) => return Ok(entry.attr.ino),
+            Err(DBError::SQLX(sqlx::Error::RowNotFound)) => {}
+            Err(e) => return Err(e),
+        }
+
+        let root_attrs = self.store.get_attr(1).await?;
+        let now = SystemTime::now();
+        let ino = self
+            .store
+            .insert_attr(&FileAttr {
+                ino: 0,
+                size: 0,
+                blocks: 0,
+                atime: now,
+                mtime: now,
+                ctime: now,
+                crtime: now,
+                kind: FileType::Directory,
+                perm: 0o700,
+                nlink: 1,
+                uid: root_attrs.uid,
+                gid: root_attrs.gid,
+                rdev: 0,
+                blksize: 0,
+                flags: 0,
+            })
+            .await?;
+        self.store.insert_name(ino, &lost_found_name).await?;
+        self.store.add_to_dir(1, ino).await?;
+        Ok(ino)
+    }
+
+    /// Remove `tid` from `inos`, moving files that are left without tags or a parent directory
+    /// into lost+found
+    async fn untag_inos(&self, tid: u64, inos: &[u64]) -> Result<(), DBError> {
+        for ino in inos {
+            self.store.untag(tid, *ino).await?;
+            if !self.store.is_reachable(*ino).await? {
+                let lost_found = self.get_lost_found().await?;
+                self.store.add_to_dir(lost_found, *ino).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Remove `tag` from every file and delete it, along with all of its tag directories
+    ///
+    /// Files that are left without tags or a parent directory are moved into lost+found. Like with
+    /// rmdir, tag directories have to be empty unless `recursive`, in which case everything nested
+    /// in them is deleted as well.
+    pub async fn untag_all(&self, tag: &str, recursive: bool) -> Result<(), DBError> {
+        let tag = &self.enc_name(tag);
+        let tid = self
+            .store
+            .get_tid(tag)
+            .await?
+            .ok_or(sqlx::Error::RowNotFound)?;
+
+        let tag_dirs = self.store.get_tag_dirs(tag).await?;
+        if !recursive && tag_dirs.iter().any(|(_, name)| name != tag) {
+            return Err(std::io::Error::from_raw_os_error(libc::ENOTEMPTY).into());
+        }
+        for (ino, _) in &tag_dirs {
+            self.store.delete_inode(*ino).await?;
+        }
+
+        let members = self.store.get_tagged(&[tid], &self.tag_prefix).await?;
+        self.untag_inos(tid, &members).await?;
+
+        self.store.delete_tag(tid).await?;
+        for (_, name) in tag_dirs.iter().filter(|(_, name)| name != tag) {
+            if let Some(nested_tid) = self.store.get_tid(name).await? {
+                self.del_tid_if_orphan(nested_tid).await?;
+            }
         }
         Ok(())
     }
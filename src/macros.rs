@@ -20,6 +20,24 @@ macro_rules! handle_auth_perm {
     };
 }
 
+macro_rules! handle_vdir_readonly {
+    ($self: expr, $ino: expr, $reply: expr) => {
+        if $self.vdirs.get($ino).is_some() {
+            $reply.error(libc::EROFS);
+            return;
+        }
+    };
+}
+
+macro_rules! handle_vdir_name {
+    ($self: expr, $parent: expr, $name: expr, $reply: expr) => {
+        if handle_db_err!($self.vdir_named($parent, $name).await, $reply).is_some() {
+            $reply.error(libc::EEXIST);
+            return;
+        }
+    };
+}
+
 macro_rules! handle_from_int_err {
     ($e: expr, $reply: expr) => {
         match handle_from_int_err($e) {
@@ -1,26 +1,186 @@
 use std::{
-    fs::{File, create_dir},
+    fs::{File, create_dir, read},
     io::ErrorKind as IoErrorKind,
+    path::PathBuf,
+    process::exit,
     str::FromStr,
+    time::Duration,
 };
 
-use clap::Parser;
-use ptfs::PTFS;
+use clap::{Parser, Subcommand};
+#[cfg(feature = "postgres")]
+use ptfs::PgStore;
+use ptfs::{
+    Cipher, Compression, DEFAULT_CHUNK_SIZE, Encryption, PTFS, SALT_LEN, Settings, SqliteStore,
+    Store, Ttl,
+};
 use sqlx::{SqlitePool, sqlite::SqliteConnectOptions};
 use tokio::runtime::{Handle, Runtime};
 
+/// Largest chunk size accepted by mkfs, pages are read and written whole
+const MAX_CHUNK_SIZE: u64 = 1 << 20;
+
+/// Evaluate `$body` with `$store` bound to the store of `$database`, connected in `$rt`
+///
+/// Databases are SQLite files, or PostgreSQL databases given by URL, see [`is_postgres`].
+macro_rules! with_store {
+    ($rt:expr, $database:expr, |$store:ident| $body:expr) => {
+        if is_postgres($database) {
+            #[cfg(feature = "postgres")]
+            {
+                let $store = PgStore::new($rt.block_on(connect_postgres($database)));
+                $body
+            }
+            #[cfg(not(feature = "postgres"))]
+            panic!("PostgreSQL databases aren't supported, build with the postgres feature")
+        } else {
+            let $store = SqliteStore::new($rt.block_on(connect($database)));
+            $body
+        }
+    };
+}
+
 fn main() {
     let Args {
-        database,
-        mountpoint,
-        new,
-        prefix,
+        command,
+        mount: mount_args,
     } = Args::parse();
 
     tracing_subscriber::fmt::try_init().ok();
 
+    match command.unwrap_or(Command::Mount(mount_args)) {
+        Command::Mkfs {
+            database,
+            chunk_size,
+            compression,
+            blob_threshold,
+            encryption,
+            ttl,
+        } => mkfs(
+            database,
+            chunk_size,
+            compression,
+            blob_threshold,
+            encryption,
+            ttl,
+        ),
+        Command::Mount(MountArgs {
+            database,
+            mountpoint,
+            memory,
+            new,
+            prefix,
+            untagged_name,
+            key_file,
+            ttl,
+        }) => match memory {
+            Some(mountpoint) => mount(None, mountpoint, new, prefix, untagged_name, None, ttl),
+            // both are required without --memory
+            None => mount(
+                database,
+                mountpoint.unwrap(),
+                new,
+                prefix,
+                untagged_name,
+                key_file,
+                ttl,
+            ),
+        },
+        Command::Untag {
+            database,
+            tag,
+            all: _,
+            recursive,
+            prefix,
+            key_file,
+        } => untag(database, tag, recursive, prefix, key_file),
+        Command::Fsck { database, repair } => fsck(database, repair),
+    }
+}
+
+fn mkfs(
+    database: String,
+    chunk_size: u64,
+    compression: Compression,
+    blob_threshold: Option<u64>,
+    EncryptArgs {
+        encrypt,
+        dedup_encrypted,
+        key_file,
+    }: EncryptArgs,
+    ttl: TtlArgs,
+) {
+    if is_postgres(&database) {
+        if blob_threshold.is_some() {
+            panic!("PostgreSQL databases keep chunks in the database, they can't have blobs")
+        }
+    } else if let Err(e) = File::create_new(&database) {
+        panic!("{e}")
+    }
+
+    let mut settings = Settings {
+        chunk_size,
+        compression,
+        blob_threshold,
+        encryption: None,
+        ttl: ttl.apply(Ttl::default()),
+    };
+    if encrypt {
+        let salt: [u8; SALT_LEN] = rand::random();
+        let cipher = match key_file {
+            Some(key_file) => Cipher::from_key_file(&read(key_file).unwrap()),
+            None => {
+                let passphrase = rpassword::prompt_password("Passphrase: ").unwrap();
+                if passphrase != rpassword::prompt_password("Repeat passphrase: ").unwrap() {
+                    panic!("passphrases don't match")
+                }
+                Cipher::from_passphrase(passphrase.as_bytes(), &salt)
+                    .unwrap_or_else(|_| panic!("failed deriving key"))
+            }
+        };
+        settings.encryption = Some(Encryption {
+            salt: salt.to_vec(),
+            key_check: cipher.key_check(),
+            dedup: dedup_encrypted,
+        });
+    }
+
+    let rt = Runtime::new().unwrap();
+    with_store!(rt, &database, |store| rt.block_on(async {
+        let fs = PTFS {
+            store,
+            runtime_handle: Handle::current(),
+            tag_prefix: String::new(),
+            untagged_name: String::new(),
+            vdirs: Default::default(),
+            settings,
+            cipher: None,
+            ttl: Default::default(),
+            invalidator: Default::default(),
+            blob_dir: blob_dir(&database),
+        };
+
+        if let Err(e) = fs.mkfs().await {
+            panic!("failed creating database: {}", e.map_db_err().1);
+        }
+    }))
+}
+
+/// Mount `database`, or a new in-memory database if it's `None`
+fn mount(
+    database: Option<String>,
+    mountpoint: String,
+    new: bool,
+    prefix: String,
+    untagged_name: String,
+    key_file: Option<String>,
+    ttl: TtlArgs,
+) {
     if new {
-        let db_err = File::create_new(&database).err();
+        let db_err = database
+            .as_ref()
+            .filter(|db| !is_postgres(db))
+            .and_then(|db| File::create_new(db).err());
         let mp_err = create_dir(&mountpoint).err();
 
         for e in [db_err, mp_err] {
@@ -33,35 +193,352 @@ fn main() {
     }
 
     let rt = Runtime::new().unwrap();
-    let fs = rt.block_on(async {
-        let pool = SqlitePool::connect_with(
-            SqliteConnectOptions::from_str(format!("sqlite:{}", database).as_str())
-                .unwrap()
-                // disable caching
-                // .pragma("cache_size", "0")
-                // .statement_cache_capacity(0)
-                .locking_mode(sqlx::sqlite::SqliteLockingMode::Normal)
-                .journal_mode(sqlx::sqlite::SqliteJournalMode::Wal),
-        )
-        .await
-        .unwrap();
-
-        PTFS {
-            pool: pool,
+    let Some(database) = database else {
+        let store = match rt.block_on(SqliteStore::memory()) {
+            Ok(store) => store,
+            Err(e) => panic!("failed creating database: {}", e.map_db_err().1),
+        };
+        // in-memory databases have no blob threshold, so nothing is stored there
+        let fs = new_fs(&rt, store, prefix, untagged_name, PathBuf::new(), None);
+        return run_session(fs, mountpoint, ttl);
+    };
+
+    with_store!(rt, &database, |store| {
+        let fs = new_fs(
+            &rt,
+            store,
+            prefix,
+            untagged_name,
+            blob_dir(&database),
+            key_file,
+        );
+        run_session(fs, mountpoint, ttl)
+    })
+}
+
+/// Mount `fs` at `mountpoint` with the database's TTLs overridden by `ttl`, until it's unmounted
+fn run_session<S: Store + 'static>(mut fs: PTFS<S>, mountpoint: String, ttl: TtlArgs) {
+    fs.ttl = ttl.apply(fs.settings.ttl);
+    // keeps `fsck --repair` from removing blobs whose chunks aren't committed yet
+    let _lock = match fs.lock_blobs(false) {
+        Ok(lock) => lock,
+        Err(e) if e.map_db_err().0 == libc::EWOULDBLOCK => {
+            panic!("the database is being repaired")
+        }
+        Err(e) => panic!("failed locking blobs: {}", e.map_db_err().1),
+    };
+    let invalidator = fs.invalidator.clone();
+    let mut session = fuser::Session::new(fs, mountpoint, &[]).unwrap();
+    invalidator.start(session.notifier());
+    session.run().unwrap()
+}
+
+/// Create a file system on `store` and unlock it, see [`unlock`]
+fn new_fs<S: Store>(
+    rt: &Runtime,
+    store: S,
+    tag_prefix: String,
+    untagged_name: String,
+    blob_dir: PathBuf,
+    key_file: Option<String>,
+) -> PTFS<S> {
+    rt.block_on(async {
+        let mut fs = PTFS {
+            store,
+            runtime_handle: Handle::current(),
+            tag_prefix,
+            untagged_name,
+            vdirs: Default::default(),
+            settings: Default::default(),
+            cipher: None,
+            ttl: Default::default(),
+            invalidator: Default::default(),
+            blob_dir,
+        };
+        unlock(&mut fs, key_file).await;
+        fs
+    })
+}
+
+fn untag(database: String, tag: String, recursive: bool, prefix: String, key_file: Option<String>) {
+    let rt = Runtime::new().unwrap();
+    with_store!(rt, &database, |store| {
+        let fs = new_fs(
+            &rt,
+            store,
+            prefix,
+            String::new(),
+            blob_dir(&database),
+            key_file,
+        );
+        if let Err(e) = rt.block_on(fs.untag_all(&tag, recursive)) {
+            panic!("failed removing tag {tag}: {}", e.map_db_err().1);
+        }
+    })
+}
+
+fn fsck(database: String, repair: bool) {
+    let rt = Runtime::new().unwrap();
+    let report = with_store!(rt, &database, |store| rt.block_on(async {
+        let mut fs = PTFS {
+            store,
             runtime_handle: Handle::current(),
-            tag_prefix: prefix,
+            tag_prefix: String::new(),
+            untagged_name: String::new(),
+            vdirs: Default::default(),
+            settings: Default::default(),
+            cipher: None,
+            ttl: Default::default(),
+            invalidator: Default::default(),
+            blob_dir: blob_dir(&database),
+        };
+        load_settings(&mut fs).await;
+
+        match fs.fsck(repair).await {
+            Ok(report) => report,
+            Err(e) if e.map_db_err().0 == libc::EWOULDBLOCK => {
+                panic!("can't repair a mounted database")
+            }
+            Err(e) => panic!("failed checking database: {}", e.map_db_err().1),
         }
-    });
+    }));
 
-    fuser::mount2(fs, mountpoint, &[]).unwrap();
+    for hash in &report.missing_blobs {
+        println!("missing blob of chunk {hash}");
+    }
+    for path in &report.orphan_blobs {
+        match repair {
+            true => println!("removed orphan blob {}", path.display()),
+            false => println!("orphan blob {}", path.display()),
+        }
+    }
+    if !report.missing_blobs.is_empty() || (!repair && !report.orphan_blobs.is_empty()) {
+        exit(1)
+    }
 }
 
-#[derive(Parser)]
-struct Args {
-    database: String,
-    mountpoint: String,
+/// Update the database and load its settings
+async fn load_settings<S: Store>(fs: &mut PTFS<S>) {
+    let loaded = match fs.migrate().await {
+        Ok(()) => Settings::load(&fs.store).await,
+        Err(e) => Err(e),
+    };
+    fs.settings = match loaded {
+        Ok(settings) => settings,
+        Err(e) => panic!("failed loading settings: {}", e.map_db_err().1),
+    };
+}
+
+/// Load the database's settings, and unlock it with `key_file` or a prompted passphrase if it's
+/// encrypted
+async fn unlock<S: Store>(fs: &mut PTFS<S>, key_file: Option<String>) {
+    load_settings(fs).await;
+    let Some(encryption) = &fs.settings.encryption else {
+        return;
+    };
+
+    if fs.hex_prefix() {
+        panic!("the tag prefix of an encrypted database can't start with a digit or a to f")
+    }
+
+    let cipher = match key_file {
+        Some(key_file) => Cipher::from_key_file(&read(key_file).unwrap()),
+        None => {
+            let passphrase = rpassword::prompt_password("Passphrase: ").unwrap();
+            Cipher::from_passphrase(passphrase.as_bytes(), &encryption.salt)
+                .unwrap_or_else(|_| panic!("failed deriving key"))
+        }
+    };
+    if fs.unlock(cipher).is_err() {
+        panic!("wrong passphrase or key file")
+    }
+}
+
+/// Whether `database` is the URL of a PostgreSQL database rather than a SQLite file
+fn is_postgres(database: &str) -> bool {
+    database.starts_with("postgres://") || database.starts_with("postgresql://")
+}
+
+/// Directory next to the database holding its blobs, none for PostgreSQL databases
+fn blob_dir(database: &str) -> PathBuf {
+    match is_postgres(database) {
+        true => PathBuf::new(),
+        false => format!("{database}.blobs").into(),
+    }
+}
+
+async fn connect(database: &str) -> SqlitePool {
+    SqlitePool::connect_with(
+        SqliteConnectOptions::from_str(format!("sqlite:{}", database).as_str())
+            .unwrap()
+            // disable caching
+            // .pragma("cache_size", "0")
+            // .statement_cache_capacity(0)
+            .locking_mode(sqlx::sqlite::SqliteLockingMode::Normal)
+            .journal_mode(sqlx::sqlite::SqliteJournalMode::Wal),
+    )
+    .await
+    .unwrap()
+}
+
+#[cfg(feature = "postgres")]
+async fn connect_postgres(url: &str) -> sqlx::PgPool {
+    sqlx::PgPool::connect(url).await.unwrap()
+}
+
+fn parse_chunk_size(s: &str) -> Result<u64, String> {
+    match s.parse::<u64>() {
+        Ok(size) if size.is_power_of_two() && (512..=MAX_CHUNK_SIZE).contains(&size) => Ok(size),
+        _ => Err(format!(
+            "invalid chunk size {s}, expected a power of two from 512 to {MAX_CHUNK_SIZE}"
+        )),
+    }
+}
+
+fn parse_ttl(s: &str) -> Result<Duration, String> {
+    s.parse()
+        .ok()
+        .and_then(|secs| Duration::try_from_secs_f64(secs).ok())
+        .ok_or(format!("invalid TTL {s}, expected a number of seconds"))
+}
+
+fn parse_compression(s: &str) -> Result<Compression, String> {
+    s.parse()
+        .map_err(|_| format!("unknown compression {s}, expected none, zstd or lz4"))
+}
+
+/// How long the kernel caches replies, see [`Ttl`]
+#[derive(clap::Args)]
+struct TtlArgs {
+    /// Seconds the kernel caches file attributes for [default: 1]
+    #[arg(long, value_name = "SECONDS", value_parser = parse_ttl)]
+    attr_ttl: Option<Duration>,
+    /// Seconds the kernel caches found names for [default: 1]
+    #[arg(long, value_name = "SECONDS", value_parser = parse_ttl)]
+    entry_ttl: Option<Duration>,
+    /// Seconds the kernel caches names that weren't found for, 0 to not cache them [default: 0]
+    #[arg(long, value_name = "SECONDS", value_parser = parse_ttl)]
+    negative_ttl: Option<Duration>,
+}
+
+impl TtlArgs {
+    /// Override the TTLs of `ttl` that were given
+    fn apply(&self, ttl: Ttl) -> Ttl {
+        Ttl {
+            attr: self.attr_ttl.unwrap_or(ttl.attr),
+            entry: self.entry_ttl.unwrap_or(ttl.entry),
+            negative: self.negative_ttl.unwrap_or(ttl.negative),
+        }
+    }
+}
+
+/// How mkfs encrypts the database, see [`Encryption`]
+#[derive(clap::Args)]
+struct EncryptArgs {
+    /// Encrypt file names and contents with a key derived from a prompted passphrase, or from
+    /// --key-file
+    #[arg(default_value_t = false, long)]
+    encrypt: bool,
+    /// Deduplicate the pages of an encrypted database, which reveals which of its pages are equal
+    #[arg(default_value_t = false, long, requires = "encrypt")]
+    dedup_encrypted: bool,
+    #[arg(long)]
+    key_file: Option<String>,
+}
+
+/// How to mount a database, see [`mount`]
+#[derive(clap::Args)]
+struct MountArgs {
+    /// SQLite file, or URL of a PostgreSQL database (postgres://...)
+    #[arg(required_unless_present = "memory")]
+    database: Option<String>,
+    #[arg(required_unless_present = "memory")]
+    mountpoint: Option<String>,
+    /// Mount a new in-memory database at MOUNTPOINT instead, which is dropped on unmount
+    #[arg(
+        long,
+        value_name = "MOUNTPOINT",
+        conflicts_with_all = ["database", "mountpoint", "key_file"]
+    )]
+    memory: Option<String>,
     #[arg(default_value_t = false, short, long)]
     new: bool,
     #[arg(default_value = "#", short, long)]
     prefix: String,
+    /// Name of the root's virtual directory listing files with no tags and no parent directory
+    #[arg(default_value = ".untagged", long)]
+    untagged_name: String,
+    /// File to derive the key of an encrypted database from, instead of prompting for a passphrase
+    #[arg(long)]
+    key_file: Option<String>,
+    /// Kernel cache TTLs, the database's unless given
+    #[command(flatten)]
+    ttl: TtlArgs,
+}
+
+/// Mount a database, or manage one with a subcommand
+#[derive(Parser)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
+struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+    /// Arguments of the mount subcommand, which `ptfs <DATABASE> <MOUNTPOINT>` runs
+    #[command(flatten)]
+    mount: MountArgs,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Create a new database
+    Mkfs {
+        /// SQLite file, or URL of a PostgreSQL database (postgres://...)
+        database: String,
+        /// Size of the pages file contents are split into, deduplicated, compressed and encrypted
+        #[arg(default_value_t = DEFAULT_CHUNK_SIZE, long, value_parser = parse_chunk_size)]
+        chunk_size: u64,
+        /// Codec compressing file contents: none, zstd or lz4
+        #[arg(default_value_t = Compression::None, long, value_parser = parse_compression)]
+        compression: Compression,
+        /// Store chunks larger than this many bytes as files in a directory next to the database,
+        /// named <DATABASE>.blobs
+        #[arg(long)]
+        blob_threshold: Option<u64>,
+        #[command(flatten)]
+        encryption: EncryptArgs,
+        /// Kernel cache TTLs of mounts, which mount options override
+        #[command(flatten)]
+        ttl: TtlArgs,
+    },
+    /// Mount a database, also done without a subcommand
+    Mount(MountArgs),
+    /// Remove a tag from every file of an unmounted database and delete it, moving files left
+    /// without tags or a parent directory into lost+found
+    Untag {
+        /// SQLite file, or URL of a PostgreSQL database (postgres://...)
+        database: String,
+        /// Tag name, including its prefix
+        tag: String,
+        /// Remove the tag from every file and delete it, moving files left without tags or a
+        /// parent directory into lost+found
+        #[arg(long, required = true)]
+        all: bool,
+        /// Also delete everything nested in the tag's directories, which are only removed when
+        /// empty otherwise, like with rmdir
+        #[arg(default_value_t = false, short, long)]
+        recursive: bool,
+        #[arg(default_value = "#", short, long)]
+        prefix: String,
+        /// File to derive the key of an encrypted database from, instead of prompting for a
+        /// passphrase
+        #[arg(long)]
+        key_file: Option<String>,
+    },
+    /// Check that the blob directory matches the database's chunks
+    Fsck {
+        /// SQLite file, or URL of a PostgreSQL database (postgres://...)
+        database: String,
+        /// Remove blobs of no chunk and chunks no file references
+        #[arg(default_value_t = false, long)]
+        repair: bool,
+    },
 }
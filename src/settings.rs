@@ -0,0 +1,209 @@
+use crate::{
+    db_helpers::types::{ConvError, DBError},
+    store::Store,
+};
+use std::{fmt::Display, str::FromStr, time::Duration};
+
+/// Default size of file pages, SQLite's default page size which sized them before they were
+/// configurable
+pub const DEFAULT_CHUNK_SIZE: u64 = 4096;
+
+/// Settings chosen when creating a database, stored in its `settings` table
+#[derive(Debug, Clone)]
+pub struct Settings {
+    /// Size of the pages file contents are split into, which can't change once files are written
+    pub chunk_size: u64,
+    /// Codec used for new pages
+    pub compression: Compression,
+    /// Stored size above which chunks are kept in the blob directory instead of the database,
+    /// see [`crate::PTFS::blob_dir`]
+    pub blob_threshold: Option<u64>,
+    /// Set for databases with encrypted names and file contents
+    pub encryption: Option<Encryption>,
+    /// How long mounts let the kernel cache replies, unless overridden, see [`crate::PTFS::ttl`]
+    pub ttl: Ttl,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            chunk_size: DEFAULT_CHUNK_SIZE,
+            compression: Default::default(),
+            blob_threshold: None,
+            encryption: None,
+            ttl: Default::default(),
+        }
+    }
+}
+
+/// How long the kernel caches replies before asking again
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Ttl {
+    /// Attributes of files
+    pub attr: Duration,
+    /// Names found in directories, along with the attributes of their files
+    pub entry: Duration,
+    /// Names that weren't found, which aren't cached if it's zero
+    pub negative: Duration,
+}
+
+impl Default for Ttl {
+    fn default() -> Self {
+        Ttl {
+            attr: Duration::from_secs(1),
+            entry: Duration::from_secs(1),
+            negative: Duration::ZERO,
+        }
+    }
+}
+
+/// Parse a setting of seconds, e.g. `0.5`
+fn parse_secs(value: &str) -> Result<Duration, ConvError> {
+    value
+        .parse()
+        .ok()
+        .and_then(|secs| Duration::try_from_secs_f64(secs).ok())
+        .ok_or(ConvError::Setting)
+}
+
+/// Settings of an encrypted database
+#[derive(Debug, Default, Clone)]
+pub struct Encryption {
+    /// Salt keys are derived from passphrases with
+    pub salt: Vec<u8>,
+    /// [`crate::Cipher::key_check`] of the database's key
+    pub key_check: String,
+    /// Whether equal pages share chunks, which are then encrypted with a nonce derived from their
+    /// content rather than a random one, revealing which pages are equal
+    pub dedup: bool,
+}
+
+impl Settings {
+    /// Load settings of a database, settings it doesn't have are left as default
+    pub async fn load(store: &impl Store) -> Result<Settings, DBError> {
+        let mut settings = Settings::default();
+        for (key, value) in store.get_settings().await? {
+            match key.as_str() {
+                "chunk_size" => {
+                    settings.chunk_size = value
+                        .parse()
+                        .ok()
+                        .filter(|size| *size > 0)
+                        .ok_or(ConvError::Setting)?
+                }
+                "compression" => settings.compression = value.parse()?,
+                "blob_threshold" => {
+                    settings.blob_threshold = Some(value.parse().map_err(|_| ConvError::Setting)?)
+                }
+                "encryption_salt" => {
+                    settings.encryption.get_or_insert_default().salt =
+                        hex::decode(value).map_err(|_| ConvError::Setting)?
+                }
+                "encryption_key_check" => {
+                    settings.encryption.get_or_insert_default().key_check = value
+                }
+                "encryption_dedup" => {
+                    settings.encryption.get_or_insert_default().dedup =
+                        value.parse().map_err(|_| ConvError::Setting)?
+                }
+                "attr_ttl" => settings.ttl.attr = parse_secs(&value)?,
+                "entry_ttl" => settings.ttl.entry = parse_secs(&value)?,
+                "negative_ttl" => settings.ttl.negative = parse_secs(&value)?,
+                _ => tracing::warn!("unknown setting {key}"),
+            }
+        }
+        Ok(settings)
+    }
+
+    pub async fn save(&self, store: &impl Store) -> Result<(), DBError> {
+        let mut rows = vec![
+            ("chunk_size", self.chunk_size.to_string()),
+            ("compression", self.compression.to_string()),
+            ("attr_ttl", self.ttl.attr.as_secs_f64().to_string()),
+            ("entry_ttl", self.ttl.entry.as_secs_f64().to_string()),
+            ("negative_ttl", self.ttl.negative.as_secs_f64().to_string()),
+        ];
+        if let Some(blob_threshold) = self.blob_threshold {
+            rows.push(("blob_threshold", blob_threshold.to_string()));
+        }
+        if let Some(encryption) = &self.encryption {
+            rows.push(("encryption_salt", hex::encode(&encryption.salt)));
+            rows.push(("encryption_key_check", encryption.key_check.clone()));
+            rows.push(("encryption_dedup", encryption.dedup.to_string()));
+        }
+        for (key, value) in rows {
+            store.set_setting(key, &value).await?;
+        }
+        Ok(())
+    }
+}
+
+/// Page compression codec, stored in `chunks.codec`
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Compression {
+    #[default]
+    None = 0,
+    Zstd = 1,
+    Lz4 = 2,
+}
+
+/// zstd compression level
+const ZSTD_LEVEL: i32 = 3;
+
+impl Compression {
+    pub fn from_codec(codec: u8) -> Result<Compression, ConvError> {
+        match codec {
+            0 => Ok(Compression::None),
+            1 => Ok(Compression::Zstd),
+            2 => Ok(Compression::Lz4),
+            _ => Err(ConvError::Decompress),
+        }
+    }
+
+    /// Compress `bytes`, returning `None` if they don't get smaller
+    pub fn compress(&self, bytes: &[u8]) -> Option<Vec<u8>> {
+        let compressed = match self {
+            Compression::None => return None,
+            Compression::Zstd => zstd::bulk::compress(bytes, ZSTD_LEVEL).ok()?,
+            Compression::Lz4 => lz4_flex::block::compress(bytes),
+        };
+        (compressed.len() < bytes.len()).then_some(compressed)
+    }
+
+    /// Decompress `bytes` into `len` bytes
+    pub fn decompress(&self, bytes: Vec<u8>, len: usize) -> Result<Vec<u8>, ConvError> {
+        match self {
+            Compression::None => Ok(bytes),
+            Compression::Zstd => {
+                zstd::bulk::decompress(&bytes, len).map_err(|_| ConvError::Decompress)
+            }
+            Compression::Lz4 => {
+                lz4_flex::block::decompress(&bytes, len).map_err(|_| ConvError::Decompress)
+            }
+        }
+    }
+}
+
+impl FromStr for Compression {
+    type Err = ConvError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(Compression::None),
+            "zstd" => Ok(Compression::Zstd),
+            "lz4" => Ok(Compression::Lz4),
+            _ => Err(ConvError::Setting),
+        }
+    }
+}
+
+impl Display for Compression {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Compression::None => "none",
+            Compression::Zstd => "zstd",
+            Compression::Lz4 => "lz4",
+        })
+    }
+}
@@ -0,0 +1,233 @@
+#[cfg(feature = "postgres")]
+pub mod postgres;
+pub mod sqlite;
+
+use crate::{
+    db_helpers::types::DBError,
+    vdirs::{RecentWindow, TimeField},
+};
+use fuser::FileAttr;
+use std::{fmt::Debug, time::SystemTime};
+
+/// File listed by a directory
+#[derive(Debug)]
+pub struct Entry {
+    pub attr: FileAttr,
+    /// Stored name, see [`crate::PTFS::enc_name`]
+    pub name: String,
+}
+
+/// Content-addressed chunk of file contents, as stored
+#[derive(Debug)]
+pub struct Chunk {
+    pub hash: Vec<u8>,
+    /// `None` for chunks stored in the blob directory
+    pub bytes: Option<Vec<u8>>,
+    /// [`crate::Compression`] of `bytes`
+    pub codec: u8,
+    /// Length of the chunk once decompressed
+    pub len: u64,
+    /// Whether the chunk is stored in the blob directory
+    pub blob: bool,
+}
+
+/// Files listed by a virtual directory, see [`crate::vdirs::VirtualDir`]
+///
+/// Filters with `base_tags` only list non-directory files tagged with all of them, or every
+/// non-directory file if they're `None`.
+#[derive(Debug)]
+pub enum FileFilter<'a> {
+    /// Files with no tags and no parent directory
+    Untagged,
+    /// Files associated with `tid`, except for prefixed ones
+    Tag { tid: u64, tag_prefix: &'a str },
+    /// Files modified within `window`
+    Recent {
+        base_tags: Option<&'a [u64]>,
+        window: RecentWindow,
+    },
+    /// Files with `field` within a local year and month
+    Month {
+        base_tags: Option<&'a [u64]>,
+        field: TimeField,
+        year: u32,
+        month: u32,
+    },
+    /// Files with names matching the glob `pattern`
+    Glob {
+        base_tags: Option<&'a [u64]>,
+        pattern: &'a str,
+    },
+}
+
+/// Storage backend holding inode attributes, names, tags, directory membership and file contents
+///
+/// Names are stored as given, which are encrypted on encrypted databases, and lookups by name
+/// match them exactly. Getting something that doesn't exist fails with
+/// [`sqlx::Error::RowNotFound`], unless it returns an `Option`.
+///
+/// Listings resume after the key of the last listed item instead of skipping a number of them, so
+/// items created or deleted in between don't shift the rest.
+// stores are only awaited through `Handle::block_on`, so their futures don't have to be `Send`
+#[allow(async_fn_in_trait)]
+pub trait Store: Debug + Send + 'static {
+    /// Create or update the store's tables
+    async fn migrate(&self) -> Result<(), DBError>;
+    async fn close(&self);
+    /// Get the `key`, `value` pairs of the settings table
+    async fn get_settings(&self) -> Result<Vec<(String, String)>, DBError>;
+    async fn set_setting(&self, key: &str, value: &str) -> Result<(), DBError>;
+
+    async fn get_attr(&self, ino: u64) -> Result<FileAttr, DBError>;
+    /// Insert `attr` under a new inode, ignoring `attr.ino`, and return the inode
+    async fn insert_attr(&self, attr: &FileAttr) -> Result<u64, DBError>;
+    /// Insert `attr` under `attr.ino`, unless the inode already exists
+    async fn insert_attr_if_missing(&self, attr: &FileAttr) -> Result<(), DBError>;
+    async fn update_attr(&self, attr: &FileAttr) -> Result<(), DBError>;
+    async fn set_atime(&self, ino: u64, atime: SystemTime) -> Result<(), DBError>;
+    async fn set_mtime(&self, ino: u64, mtime: SystemTime) -> Result<(), DBError>;
+    async fn set_size(&self, ino: u64, size: u64) -> Result<(), DBError>;
+    /// Set the size of `ino` to `size` if it's smaller
+    async fn grow_size(&self, ino: u64, size: u64) -> Result<(), DBError>;
+    async fn set_blocks(&self, ino: u64, blocks: u64) -> Result<(), DBError>;
+    /// Delete `ino` along with its name, tags, directory membership and pages
+    async fn delete_inode(&self, ino: u64) -> Result<(), DBError>;
+
+    /// Get the name of `ino`, `None` for the root
+    async fn get_name(&self, ino: u64) -> Result<Option<String>, DBError>;
+    async fn insert_name(&self, ino: u64, name: &str) -> Result<(), DBError>;
+    async fn set_name(&self, ino: u64, name: &str) -> Result<(), DBError>;
+
+    async fn get_tid(&self, name: &str) -> Result<Option<u64>, DBError>;
+    /// Create a tag called `name` and return its tid
+    async fn create_tag(&self, name: &str) -> Result<u64, DBError>;
+    /// Delete `tid` along with its associations
+    async fn delete_tag(&self, tid: u64) -> Result<(), DBError>;
+    /// Get tids and names of at most `limit` tags with tids above `after`, ordered by tid
+    async fn list_tags(&self, after: u64, limit: u64) -> Result<Vec<(u64, String)>, DBError>;
+    /// Get the tags associated with `ino`
+    async fn get_tags(&self, ino: u64) -> Result<Vec<u64>, DBError>;
+    /// Associate `ino` with `tid`, unless it already is
+    async fn tag(&self, tid: u64, ino: u64) -> Result<(), DBError>;
+    async fn untag(&self, tid: u64, ino: u64) -> Result<(), DBError>;
+    /// Remove every tag from `ino`
+    async fn clear_tags(&self, ino: u64) -> Result<(), DBError>;
+    /// Count the inodes associated with `tid`
+    async fn count_tagged(&self, tid: u64) -> Result<u64, DBError>;
+    /// Count the inodes associated with `tid` whose names don't start with `tag_prefix`, which
+    /// [`FileFilter::Tag`] lists
+    async fn count_tag_files(&self, tid: u64, tag_prefix: &str) -> Result<u64, DBError>;
+    /// Get inodes tagged with all of `tags` whose names don't start with `tag_prefix`
+    async fn get_tagged(&self, tags: &[u64], tag_prefix: &str) -> Result<Vec<u64>, DBError>;
+    /// Get the prefixed directories listing each of `inos` through its tags, i.e. tagged with
+    /// nothing but some of its tags, as pairs of inode and directory
+    async fn get_tag_views(
+        &self,
+        inos: &[u64],
+        tag_prefix: &str,
+    ) -> Result<Vec<(u64, u64)>, DBError>;
+    /// Get the name of each of `inos` along with each of its tags, or once with no tag if it has
+    /// none
+    async fn get_names_and_tags(
+        &self,
+        inos: &[u64],
+    ) -> Result<Vec<(u64, String, Option<u64>)>, DBError>;
+    /// Get inodes and names of the directories called `name`, and of everything nested in them
+    async fn get_tag_dirs(&self, name: &str) -> Result<Vec<(u64, String)>, DBError>;
+
+    /// Put `ino` into `dir`, unless it's already there
+    async fn add_to_dir(&self, dir: u64, ino: u64) -> Result<(), DBError>;
+    async fn remove_from_dir(&self, dir: u64, ino: u64) -> Result<(), DBError>;
+    /// Get inodes and names of the contents of `dir`
+    async fn get_dir_contents(&self, dir: u64) -> Result<Vec<(u64, String)>, DBError>;
+    /// Whether `ino` has tags or a parent directory
+    async fn is_reachable(&self, ino: u64) -> Result<bool, DBError>;
+    /// Get the directory `ino` is in, `None` if it's only listed through its tags
+    async fn get_parent(&self, ino: u64) -> Result<Option<u64>, DBError>;
+
+    /// Get the entry called `name` among the contents of `dir` and the inodes tagged with all of
+    /// `tags`
+    async fn lookup_entry(&self, dir: u64, tags: &[u64], name: &str) -> Result<Entry, DBError>;
+    /// Get at most `limit` of the contents of `dir` and the unprefixed inodes tagged with all of
+    /// `tags`, with inodes above `after` and ordered by inode
+    async fn list_entries(
+        &self,
+        dir: u64,
+        tags: &[u64],
+        tag_prefix: &str,
+        after: u64,
+        limit: u64,
+    ) -> Result<Vec<Entry>, DBError>;
+    /// Get the contents of `dir` and the inodes tagged with all of `tags`
+    async fn get_members(&self, dir: u64, tags: &[u64]) -> Result<Vec<u64>, DBError>;
+    /// Get at most `limit` of the files matching `filter`, only the one called `name` if it's set,
+    /// with inodes above `after` and ordered by inode
+    async fn filter_entries(
+        &self,
+        filter: &FileFilter<'_>,
+        name: Option<&str>,
+        after: u64,
+        limit: u64,
+    ) -> Result<Vec<Entry>, DBError>;
+    /// Get at most `limit` of the local years with files whose `field` is within them, or of the
+    /// months of `year` if it's set, above `after` and in order
+    ///
+    /// Files are filtered by `base_tags` like [`FileFilter`]s.
+    async fn list_time_parts(
+        &self,
+        base_tags: Option<&[u64]>,
+        field: TimeField,
+        year: Option<u32>,
+        after: u64,
+        limit: u64,
+    ) -> Result<Vec<u32>, DBError>;
+
+    /// Get the chunk referenced by page `page` of `ino`
+    async fn get_chunk(&self, ino: u64, page: u64) -> Result<Option<Chunk>, DBError>;
+    async fn has_chunk(&self, hash: &[u8]) -> Result<bool, DBError>;
+    /// Make page `page` of `ino` reference chunk `hash`, inserting `new_chunk` first if it's set
+    async fn put_page(
+        &self,
+        ino: u64,
+        page: u64,
+        hash: &[u8],
+        new_chunk: Option<Chunk>,
+    ) -> Result<(), DBError>;
+    /// Delete pages of `ino` from `from` up to `to`, or all of them if it's `None`
+    async fn delete_pages(&self, ino: u64, from: u64, to: Option<u64>) -> Result<(), DBError>;
+    /// Make `pages` pages of `ino_out` from `page_out` reference the chunks of the pages of
+    /// `ino_in` from `page_in`, pages `ino_in` doesn't store are deleted
+    async fn copy_pages(
+        &self,
+        ino_in: u64,
+        page_in: u64,
+        ino_out: u64,
+        page_out: u64,
+        pages: u64,
+    ) -> Result<(), DBError>;
+    /// Count the stored pages of `ino`
+    async fn count_pages(&self, ino: u64) -> Result<u64, DBError>;
+    /// Get the first stored page of `ino` from `page`
+    async fn next_page(&self, ino: u64, page: u64) -> Result<Option<u64>, DBError>;
+    /// Get the first page after the consecutive stored pages of `ino` starting at `page`, `None`
+    /// if `page` isn't stored
+    async fn end_of_pages(&self, ino: u64, page: u64) -> Result<Option<u64>, DBError>;
+    /// Delete chunks no page references, returning their hashes and whether they're blobs
+    async fn delete_unreferenced_chunks(&self) -> Result<Vec<(Vec<u8>, bool)>, DBError>;
+    /// Get the hashes of chunks stored in the blob directory
+    async fn get_blob_hashes(&self) -> Result<Vec<Vec<u8>>, DBError>;
+
+    /// Get inodes, page numbers and bytes of up to `limit` pages stored before chunks existed,
+    /// ordered by inode and page from after the page `after`
+    async fn get_legacy_pages(
+        &self,
+        after: (u64, u64),
+        limit: u64,
+    ) -> Result<Vec<(u64, u64, Vec<u8>)>, DBError>;
+    /// Make pages stored before chunks existed reference chunks instead of their bytes, given as
+    /// inode, page number, hash and new chunk like for [`Store::put_page`], in one transaction
+    async fn put_legacy_pages(
+        &self,
+        pages: Vec<(u64, u64, Vec<u8>, Option<Chunk>)>,
+    ) -> Result<(), DBError>;
+}
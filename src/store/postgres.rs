@@ -0,0 +1,775 @@
+use super::{Chunk, Entry, FileFilter, Store};
+use crate::{
+    db_helpers::{
+        glob_to_like,
+        queries::{
+            Dialect, count_tag_files_query, filter_entries_query, list_entries_query,
+            lookup_entry_query, members_query, names_and_tags_query, tag_views_query, tagged_query,
+            time_parts_query,
+        },
+        types::{DBError, FileAttrRow, from_filetype, from_systime},
+    },
+    vdirs::{RecentWindow, TimeField},
+};
+use fuser::{FileAttr, FileType};
+use sqlx::{FromRow, PgPool, Postgres, QueryBuilder, migrate, query, query_as, query_scalar};
+use std::time::SystemTime;
+
+/// Store keeping everything in a PostgreSQL database, file contents included
+///
+/// Virtual directories by time use the server's time zone instead of the local one.
+#[derive(Debug)]
+pub struct PgStore {
+    pub pool: PgPool,
+}
+
+/// Row of `file_attrs`, whose columns are all `BIGINT` since Postgres has no unsigned integers
+#[derive(FromRow)]
+struct AttrRow {
+    ino: i64,
+    size: i64,
+    blocks: i64,
+    atime: i64,
+    mtime: i64,
+    ctime: i64,
+    crtime: i64,
+    kind: i64,
+    perm: i64,
+    nlink: i64,
+    uid: i64,
+    gid: i64,
+    rdev: i64,
+    blksize: i64,
+    flags: i64,
+}
+
+#[derive(FromRow)]
+struct EntryRow {
+    #[sqlx(flatten)]
+    attr: AttrRow,
+    name: String,
+}
+
+/// Chunk of a page, as stored
+#[derive(FromRow)]
+struct ChunkRow {
+    hash: Vec<u8>,
+    /// `None` for blobs
+    bytes: Option<Vec<u8>>,
+    codec: i16,
+    len: i64,
+    blob: bool,
+}
+
+impl TryFrom<&AttrRow> for FileAttr {
+    type Error = DBError;
+
+    fn try_from(row: &AttrRow) -> Result<Self, Self::Error> {
+        let row = FileAttrRow {
+            ino: row.ino.try_into()?,
+            size: row.size.try_into()?,
+            blocks: row.blocks.try_into()?,
+            atime: row.atime.try_into()?,
+            mtime: row.mtime.try_into()?,
+            ctime: row.ctime.try_into()?,
+            crtime: row.crtime.try_into()?,
+            kind: row.kind.try_into()?,
+            perm: row.perm.try_into()?,
+            nlink: row.nlink.try_into()?,
+            uid: row.uid.try_into()?,
+            gid: row.gid.try_into()?,
+            rdev: row.rdev.try_into()?,
+            blksize: row.blksize.try_into()?,
+            flags: row.flags.try_into()?,
+        };
+        Ok(FileAttr::try_from(&row)?)
+    }
+}
+
+fn to_entry(row: EntryRow) -> Result<Entry, DBError> {
+    Ok(Entry {
+        attr: FileAttr::try_from(&row.attr)?,
+        name: row.name,
+    })
+}
+
+/// Columns of `file_attrs` for `attr`, in order
+fn attr_columns(attr: &FileAttr) -> Result<[i64; 15], DBError> {
+    Ok([
+        attr.ino.try_into()?,
+        attr.size.try_into()?,
+        attr.blocks.try_into()?,
+        from_systime(attr.atime)?.try_into()?,
+        from_systime(attr.mtime)?.try_into()?,
+        from_systime(attr.ctime)?.try_into()?,
+        from_systime(attr.crtime)?.try_into()?,
+        from_filetype(attr.kind).into(),
+        attr.perm.into(),
+        attr.nlink.into(),
+        attr.uid.into(),
+        attr.gid.into(),
+        attr.rdev.into(),
+        attr.blksize.into(),
+        attr.flags.into(),
+    ])
+}
+
+fn to_u64s(values: Vec<i64>) -> Result<Vec<u64>, DBError> {
+    Ok(values
+        .into_iter()
+        .map(u64::try_from)
+        .collect::<Result<_, _>>()?)
+}
+
+impl Dialect for Postgres {
+    fn push_prefixed(qb: &mut QueryBuilder<'_, Postgres>, column: &str, prefix: &str) {
+        qb.push(format!("starts_with({column}, "))
+            .push_bind(prefix.to_string())
+            .push(")");
+    }
+
+    fn push_glob(qb: &mut QueryBuilder<'_, Postgres>, column: &str, glob: &str) {
+        qb.push(format!("{column} LIKE "))
+            .push_bind(glob_to_like(glob))
+            .push(" ESCAPE '\\'");
+    }
+
+    fn window_start(window: RecentWindow) -> String {
+        let start = match window {
+            RecentWindow::Today => "date_trunc('day', now())",
+            RecentWindow::Week => "now() - INTERVAL '7 days'",
+            RecentWindow::Month => "now() - INTERVAL '1 month'",
+        };
+        format!("EXTRACT(EPOCH FROM {start})")
+    }
+
+    fn year(column: &str) -> String {
+        format!("EXTRACT(YEAR FROM to_timestamp({column}))::BIGINT")
+    }
+
+    fn month(column: &str) -> String {
+        format!("EXTRACT(MONTH FROM to_timestamp({column}))::BIGINT")
+    }
+}
+
+impl PgStore {
+    pub fn new(pool: PgPool) -> PgStore {
+        PgStore { pool }
+    }
+}
+
+impl Store for PgStore {
+    async fn migrate(&self) -> Result<(), DBError> {
+        migrate!("./migrations/postgres")
+            .run(&self.pool)
+            .await
+            .map_err(|e| sqlx::Error::Migrate(Box::new(e)).into())
+    }
+
+    async fn close(&self) {
+        self.pool.close().await
+    }
+
+    async fn get_settings(&self) -> Result<Vec<(String, String)>, DBError> {
+        Ok(query_as("SELECT key, value FROM settings")
+            .fetch_all(&self.pool)
+            .await?)
+    }
+
+    async fn set_setting(&self, key: &str, value: &str) -> Result<(), DBError> {
+        query(
+            "INSERT INTO settings (key, value) VALUES ($1, $2) ON CONFLICT (key) DO UPDATE SET \
+             value = excluded.value",
+        )
+        .bind(key)
+        .bind(value)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn get_attr(&self, ino: u64) -> Result<FileAttr, DBError> {
+        let row: AttrRow = query_as("SELECT * FROM file_attrs WHERE ino = $1")
+            .bind(i64::try_from(ino)?)
+            .fetch_one(&self.pool)
+            .await?;
+        FileAttr::try_from(&row)
+    }
+
+    async fn insert_attr(&self, attr: &FileAttr) -> Result<u64, DBError> {
+        let mut q = query_scalar::<_, i64>(
+            "INSERT INTO file_attrs (size, blocks, atime, mtime, ctime, crtime, kind, perm, \
+             nlink, uid, gid, rdev, blksize, flags) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, \
+             $10, $11, $12, $13, $14) RETURNING ino",
+        );
+        for value in &attr_columns(attr)?[1..] {
+            q = q.bind(*value);
+        }
+        Ok(q.fetch_one(&self.pool).await?.try_into()?)
+    }
+
+    async fn insert_attr_if_missing(&self, attr: &FileAttr) -> Result<(), DBError> {
+        let mut q = query(
+            "INSERT INTO file_attrs VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, \
+             $13, $14, $15) ON CONFLICT (ino) DO NOTHING",
+        );
+        for value in attr_columns(attr)? {
+            q = q.bind(value);
+        }
+        let mut tx = self.pool.begin().await?;
+        q.execute(&mut *tx).await?;
+        // explicit inodes don't advance the identity, keep new inodes from colliding with them
+        query(
+            "SELECT setval(pg_get_serial_sequence('file_attrs', 'ino'), MAX(ino)) FROM file_attrs",
+        )
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn update_attr(&self, attr: &FileAttr) -> Result<(), DBError> {
+        let mut q = query(
+            "UPDATE file_attrs SET size = $2, blocks = $3, atime = $4, mtime = $5, ctime = $6, \
+             crtime = $7, kind = $8, perm = $9, nlink = $10, uid = $11, gid = $12, rdev = $13, \
+             blksize = $14, flags = $15 WHERE ino = $1",
+        );
+        for value in attr_columns(attr)? {
+            q = q.bind(value);
+        }
+        q.execute(&self.pool).await?;
+        Ok(())
+    }
+
+    async fn set_atime(&self, ino: u64, atime: SystemTime) -> Result<(), DBError> {
+        query("UPDATE file_attrs SET atime = $1 WHERE ino = $2")
+            .bind(i64::try_from(from_systime(atime)?)?)
+            .bind(i64::try_from(ino)?)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn set_mtime(&self, ino: u64, mtime: SystemTime) -> Result<(), DBError> {
+        query("UPDATE file_attrs SET mtime = $1 WHERE ino = $2")
+            .bind(i64::try_from(from_systime(mtime)?)?)
+            .bind(i64::try_from(ino)?)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn set_size(&self, ino: u64, size: u64) -> Result<(), DBError> {
+        query("UPDATE file_attrs SET size = $1 WHERE ino = $2")
+            .bind(i64::try_from(size)?)
+            .bind(i64::try_from(ino)?)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn grow_size(&self, ino: u64, size: u64) -> Result<(), DBError> {
+        query("UPDATE file_attrs SET size = GREATEST(size, $1) WHERE ino = $2")
+            .bind(i64::try_from(size)?)
+            .bind(i64::try_from(ino)?)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn set_blocks(&self, ino: u64, blocks: u64) -> Result<(), DBError> {
+        query("UPDATE file_attrs SET blocks = $1 WHERE ino = $2")
+            .bind(i64::try_from(blocks)?)
+            .bind(i64::try_from(ino)?)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn delete_inode(&self, ino: u64) -> Result<(), DBError> {
+        // names, tags, directory membership and pages cascade
+        query("DELETE FROM file_attrs WHERE ino = $1")
+            .bind(i64::try_from(ino)?)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn get_name(&self, ino: u64) -> Result<Option<String>, DBError> {
+        Ok(query_scalar("SELECT name FROM file_names WHERE ino = $1")
+            .bind(i64::try_from(ino)?)
+            .fetch_optional(&self.pool)
+            .await?)
+    }
+
+    async fn insert_name(&self, ino: u64, name: &str) -> Result<(), DBError> {
+        query("INSERT INTO file_names VALUES ($1, $2)")
+            .bind(i64::try_from(ino)?)
+            .bind(name)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn set_name(&self, ino: u64, name: &str) -> Result<(), DBError> {
+        query("UPDATE file_names SET name = $1 WHERE ino = $2")
+            .bind(name)
+            .bind(i64::try_from(ino)?)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn get_tid(&self, name: &str) -> Result<Option<u64>, DBError> {
+        let tid: Option<i64> = query_scalar("SELECT tid FROM tags WHERE name = $1")
+            .bind(name)
+            .fetch_optional(&self.pool)
+            .await?;
+        Ok(tid.map(u64::try_from).transpose()?)
+    }
+
+    async fn create_tag(&self, name: &str) -> Result<u64, DBError> {
+        let tid: i64 = query_scalar("INSERT INTO tags (name) VALUES ($1) RETURNING tid")
+            .bind(name)
+            .fetch_one(&self.pool)
+            .await?;
+        Ok(tid.try_into()?)
+    }
+
+    async fn delete_tag(&self, tid: u64) -> Result<(), DBError> {
+        query("DELETE FROM tags WHERE tid = $1")
+            .bind(i64::try_from(tid)?)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn list_tags(&self, after: u64, limit: u64) -> Result<Vec<(u64, String)>, DBError> {
+        let tags: Vec<(i64, String)> =
+            query_as("SELECT tid, name FROM tags WHERE tid > $1 ORDER BY tid LIMIT $2")
+                .bind(i64::try_from(after)?)
+                .bind(i64::try_from(limit)?)
+                .fetch_all(&self.pool)
+                .await?;
+        tags.into_iter()
+            .map(|(tid, name)| Ok((u64::try_from(tid)?, name)))
+            .collect()
+    }
+
+    async fn get_tags(&self, ino: u64) -> Result<Vec<u64>, DBError> {
+        to_u64s(
+            query_scalar("SELECT tid FROM associated_tags WHERE ino = $1")
+                .bind(i64::try_from(ino)?)
+                .fetch_all(&self.pool)
+                .await?,
+        )
+    }
+
+    async fn tag(&self, tid: u64, ino: u64) -> Result<(), DBError> {
+        query("INSERT INTO associated_tags (tid, ino) VALUES ($1, $2) ON CONFLICT DO NOTHING")
+            .bind(i64::try_from(tid)?)
+            .bind(i64::try_from(ino)?)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn untag(&self, tid: u64, ino: u64) -> Result<(), DBError> {
+        query("DELETE FROM associated_tags WHERE tid = $1 AND ino = $2")
+            .bind(i64::try_from(tid)?)
+            .bind(i64::try_from(ino)?)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn clear_tags(&self, ino: u64) -> Result<(), DBError> {
+        query("DELETE FROM associated_tags WHERE ino = $1")
+            .bind(i64::try_from(ino)?)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn count_tagged(&self, tid: u64) -> Result<u64, DBError> {
+        let count: i64 = query_scalar("SELECT COUNT(*) FROM associated_tags WHERE tid = $1")
+            .bind(i64::try_from(tid)?)
+            .fetch_one(&self.pool)
+            .await?;
+        Ok(count.try_into()?)
+    }
+
+    async fn count_tag_files(&self, tid: u64, tag_prefix: &str) -> Result<u64, DBError> {
+        let count: i64 = count_tag_files_query(tid, tag_prefix)?
+            .build_query_scalar()
+            .fetch_one(&self.pool)
+            .await?;
+        Ok(count.try_into()?)
+    }
+
+    async fn get_tagged(&self, tags: &[u64], tag_prefix: &str) -> Result<Vec<u64>, DBError> {
+        to_u64s(
+            tagged_query(tags, tag_prefix)?
+                .build_query_scalar()
+                .fetch_all(&self.pool)
+                .await?,
+        )
+    }
+
+    async fn get_tag_views(
+        &self,
+        inos: &[u64],
+        tag_prefix: &str,
+    ) -> Result<Vec<(u64, u64)>, DBError> {
+        if inos.is_empty() {
+            return Ok(vec![]);
+        }
+        let views: Vec<(i64, i64)> = tag_views_query(inos, tag_prefix)?
+            .build_query_as()
+            .fetch_all(&self.pool)
+            .await?;
+        views
+            .into_iter()
+            .map(|(ino, dir)| Ok((u64::try_from(ino)?, u64::try_from(dir)?)))
+            .collect()
+    }
+
+    async fn get_names_and_tags(
+        &self,
+        inos: &[u64],
+    ) -> Result<Vec<(u64, String, Option<u64>)>, DBError> {
+        if inos.is_empty() {
+            return Ok(vec![]);
+        }
+        let names: Vec<(i64, String, Option<i64>)> = names_and_tags_query(inos)?
+            .build_query_as()
+            .fetch_all(&self.pool)
+            .await?;
+        names
+            .into_iter()
+            .map(|(ino, name, tid)| {
+                Ok((
+                    u64::try_from(ino)?,
+                    name,
+                    tid.map(u64::try_from).transpose()?,
+                ))
+            })
+            .collect()
+    }
+
+    async fn get_tag_dirs(&self, name: &str) -> Result<Vec<(u64, String)>, DBError> {
+        let dirs: Vec<(i64, String)> = query_as(
+            "WITH RECURSIVE tag_dirs(ino) AS (SELECT ino FROM readdir_rows WHERE name = $1 AND \
+             kind = $2 UNION SELECT cnt_ino FROM dir_contents INNER JOIN tag_dirs ON dir_ino = \
+             tag_dirs.ino) SELECT file_names.ino, name FROM file_names INNER JOIN tag_dirs ON \
+             file_names.ino = tag_dirs.ino",
+        )
+        .bind(name)
+        .bind(i64::from(from_filetype(FileType::Directory)))
+        .fetch_all(&self.pool)
+        .await?;
+        dirs.into_iter()
+            .map(|(ino, name)| Ok((u64::try_from(ino)?, name)))
+            .collect()
+    }
+
+    async fn add_to_dir(&self, dir: u64, ino: u64) -> Result<(), DBError> {
+        query("INSERT INTO dir_contents (dir_ino, cnt_ino) VALUES ($1, $2) ON CONFLICT DO NOTHING")
+            .bind(i64::try_from(dir)?)
+            .bind(i64::try_from(ino)?)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn remove_from_dir(&self, dir: u64, ino: u64) -> Result<(), DBError> {
+        query("DELETE FROM dir_contents WHERE dir_ino = $1 AND cnt_ino = $2")
+            .bind(i64::try_from(dir)?)
+            .bind(i64::try_from(ino)?)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn get_dir_contents(&self, dir: u64) -> Result<Vec<(u64, String)>, DBError> {
+        let contents: Vec<(i64, String)> = query_as(
+            "SELECT cnt_ino, name FROM dir_contents INNER JOIN file_names ON file_names.ino = \
+             dir_contents.cnt_ino WHERE dir_ino = $1",
+        )
+        .bind(i64::try_from(dir)?)
+        .fetch_all(&self.pool)
+        .await?;
+        contents
+            .into_iter()
+            .map(|(ino, name)| Ok((u64::try_from(ino)?, name)))
+            .collect()
+    }
+
+    async fn is_reachable(&self, ino: u64) -> Result<bool, DBError> {
+        Ok(query(
+            "SELECT 1 FROM associated_tags WHERE ino = $1 UNION SELECT 1 FROM dir_contents WHERE \
+             cnt_ino = $1",
+        )
+        .bind(i64::try_from(ino)?)
+        .fetch_optional(&self.pool)
+        .await?
+        .is_some())
+    }
+
+    async fn get_parent(&self, ino: u64) -> Result<Option<u64>, DBError> {
+        let dir: Option<i64> =
+            query_scalar("SELECT dir_ino FROM dir_contents WHERE cnt_ino = $1 LIMIT 1")
+                .bind(i64::try_from(ino)?)
+                .fetch_optional(&self.pool)
+                .await?;
+        Ok(dir.map(u64::try_from).transpose()?)
+    }
+
+    async fn lookup_entry(&self, dir: u64, tags: &[u64], name: &str) -> Result<Entry, DBError> {
+        let row: EntryRow = lookup_entry_query(dir, tags, name)?
+            .build_query_as()
+            .fetch_one(&self.pool)
+            .await?;
+        to_entry(row)
+    }
+
+    async fn list_entries(
+        &self,
+        dir: u64,
+        tags: &[u64],
+        tag_prefix: &str,
+        after: u64,
+        limit: u64,
+    ) -> Result<Vec<Entry>, DBError> {
+        let rows: Vec<EntryRow> = list_entries_query(dir, tags, tag_prefix, after, limit)?
+            .build_query_as()
+            .fetch_all(&self.pool)
+            .await?;
+        rows.into_iter().map(to_entry).collect()
+    }
+
+    async fn get_members(&self, dir: u64, tags: &[u64]) -> Result<Vec<u64>, DBError> {
+        to_u64s(
+            members_query(dir, tags)?
+                .build_query_scalar()
+                .fetch_all(&self.pool)
+                .await?,
+        )
+    }
+
+    async fn filter_entries(
+        &self,
+        filter: &FileFilter<'_>,
+        name: Option<&str>,
+        after: u64,
+        limit: u64,
+    ) -> Result<Vec<Entry>, DBError> {
+        let rows: Vec<EntryRow> = filter_entries_query(filter, name, after, limit)?
+            .build_query_as()
+            .fetch_all(&self.pool)
+            .await?;
+        rows.into_iter().map(to_entry).collect()
+    }
+
+    async fn list_time_parts(
+        &self,
+        base_tags: Option<&[u64]>,
+        field: TimeField,
+        year: Option<u32>,
+        after: u64,
+        limit: u64,
+    ) -> Result<Vec<u32>, DBError> {
+        let parts: Vec<i64> = time_parts_query(base_tags, field, year, after, limit)?
+            .build_query_scalar()
+            .fetch_all(&self.pool)
+            .await?;
+        Ok(parts
+            .into_iter()
+            .map(u32::try_from)
+            .collect::<Result<_, _>>()?)
+    }
+
+    async fn get_chunk(&self, ino: u64, page: u64) -> Result<Option<Chunk>, DBError> {
+        let row: Option<ChunkRow> = query_as(
+            "SELECT chunks.hash, chunks.bytes, codec, len, blob FROM file_contents INNER JOIN \
+             chunks ON chunks.hash = file_contents.hash WHERE ino = $1 AND page = $2",
+        )
+        .bind(i64::try_from(ino)?)
+        .bind(i64::try_from(page)?)
+        .fetch_optional(&self.pool)
+        .await?;
+        let Some(row) = row else {
+            return Ok(None);
+        };
+        Ok(Some(Chunk {
+            hash: row.hash,
+            bytes: row.bytes,
+            codec: row.codec.try_into()?,
+            len: row.len.try_into()?,
+            blob: row.blob,
+        }))
+    }
+
+    async fn has_chunk(&self, hash: &[u8]) -> Result<bool, DBError> {
+        Ok(query("SELECT 1 FROM chunks WHERE hash = $1")
+            .bind(hash)
+            .fetch_optional(&self.pool)
+            .await?
+            .is_some())
+    }
+
+    async fn put_page(
+        &self,
+        ino: u64,
+        page: u64,
+        hash: &[u8],
+        new_chunk: Option<Chunk>,
+    ) -> Result<(), DBError> {
+        // keep the chunk from being collected before it's referenced
+        let mut tx = self.pool.begin().await?;
+        if let Some(chunk) = new_chunk {
+            query(
+                "INSERT INTO chunks (hash, bytes, codec, len, blob) VALUES ($1, $2, $3, $4, $5) \
+                 ON CONFLICT (hash) DO NOTHING",
+            )
+            .bind(chunk.hash)
+            .bind(chunk.bytes)
+            .bind(i16::from(chunk.codec))
+            .bind(i64::try_from(chunk.len)?)
+            .bind(chunk.blob)
+            .execute(&mut *tx)
+            .await?;
+        }
+        query(
+            "INSERT INTO file_contents (ino, page, hash) VALUES ($1, $2, $3) ON CONFLICT (ino, \
+             page) DO UPDATE SET hash = excluded.hash",
+        )
+        .bind(i64::try_from(ino)?)
+        .bind(i64::try_from(page)?)
+        .bind(hash)
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn delete_pages(&self, ino: u64, from: u64, to: Option<u64>) -> Result<(), DBError> {
+        query(
+            "DELETE FROM file_contents WHERE ino = $1 AND page >= $2 AND ($3::BIGINT IS NULL OR \
+             page < $3)",
+        )
+        .bind(i64::try_from(ino)?)
+        .bind(i64::try_from(from)?)
+        .bind(to.map(i64::try_from).transpose()?)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn copy_pages(
+        &self,
+        ino_in: u64,
+        page_in: u64,
+        ino_out: u64,
+        page_out: u64,
+        pages: u64,
+    ) -> Result<(), DBError> {
+        let page_in = i64::try_from(page_in)?;
+        let page_out = i64::try_from(page_out)?;
+        let pages = i64::try_from(pages)?;
+        let mut tx = self.pool.begin().await?;
+        // clear pages the source doesn't have stored
+        query("DELETE FROM file_contents WHERE ino = $1 AND page >= $2 AND page < $3")
+            .bind(i64::try_from(ino_out)?)
+            .bind(page_out)
+            .bind(page_out + pages)
+            .execute(&mut *tx)
+            .await?;
+        query(
+            "INSERT INTO file_contents (ino, page, hash) SELECT $1, page - $2 + $3, hash FROM \
+             file_contents WHERE ino = $4 AND page >= $2 AND page < $5 ON CONFLICT (ino, page) DO \
+             UPDATE SET hash = excluded.hash",
+        )
+        .bind(i64::try_from(ino_out)?)
+        .bind(page_in)
+        .bind(page_out)
+        .bind(i64::try_from(ino_in)?)
+        .bind(page_in + pages)
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn count_pages(&self, ino: u64) -> Result<u64, DBError> {
+        let count: i64 = query_scalar("SELECT COUNT(*) FROM file_contents WHERE ino = $1")
+            .bind(i64::try_from(ino)?)
+            .fetch_one(&self.pool)
+            .await?;
+        Ok(count.try_into()?)
+    }
+
+    async fn next_page(&self, ino: u64, page: u64) -> Result<Option<u64>, DBError> {
+        let page: Option<i64> = query_scalar(
+            "SELECT page FROM file_contents WHERE ino = $1 AND page >= $2 ORDER BY page LIMIT 1",
+        )
+        .bind(i64::try_from(ino)?)
+        .bind(i64::try_from(page)?)
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(page.map(u64::try_from).transpose()?)
+    }
+
+    async fn end_of_pages(&self, ino: u64, page: u64) -> Result<Option<u64>, DBError> {
+        let i64_ino = i64::try_from(ino)?;
+        let stored = query("SELECT 1 FROM file_contents WHERE ino = $1 AND page = $2")
+            .bind(i64_ino)
+            .bind(i64::try_from(page)?)
+            .fetch_optional(&self.pool)
+            .await?
+            .is_some();
+        if !stored {
+            return Ok(None);
+        }
+        let end: i64 = query_scalar(
+            "SELECT page + 1 FROM file_contents AS fc WHERE ino = $1 AND page >= $2 AND NOT \
+             EXISTS (SELECT 1 FROM file_contents WHERE ino = $1 AND page = fc.page + 1) ORDER BY \
+             page LIMIT 1",
+        )
+        .bind(i64_ino)
+        .bind(i64::try_from(page)?)
+        .fetch_one(&self.pool)
+        .await?;
+        Ok(Some(end.try_into()?))
+    }
+
+    async fn delete_unreferenced_chunks(&self) -> Result<Vec<(Vec<u8>, bool)>, DBError> {
+        Ok(
+            query_as("DELETE FROM chunks WHERE refs <= 0 RETURNING hash, blob")
+                .fetch_all(&self.pool)
+                .await?,
+        )
+    }
+
+    async fn get_blob_hashes(&self) -> Result<Vec<Vec<u8>>, DBError> {
+        Ok(query_scalar("SELECT hash FROM chunks WHERE blob")
+            .fetch_all(&self.pool)
+            .await?)
+    }
+
+    async fn get_legacy_pages(
+        &self,
+        _after: (u64, u64),
+        _limit: u64,
+    ) -> Result<Vec<(u64, u64, Vec<u8>)>, DBError> {
+        // pages were never stored without chunks here
+        Ok(vec![])
+    }
+
+    async fn put_legacy_pages(
+        &self,
+        _pages: Vec<(u64, u64, Vec<u8>, Option<Chunk>)>,
+    ) -> Result<(), DBError> {
+        Ok(())
+    }
+}
@@ -0,0 +1,714 @@
+use super::{Chunk, Entry, FileFilter, Store};
+use crate::{
+    db_helpers::{
+        queries::{
+            Dialect, count_tag_files_query, filter_entries_query, list_entries_query,
+            lookup_entry_query, members_query, names_and_tags_query, tag_views_query, tagged_query,
+            time_parts_query,
+        },
+        try_bind_attrs,
+        types::{Bindable, DBError, FileAttrRow, ReadDirRow, from_filetype, from_systime},
+    },
+    vdirs::{RecentWindow, TimeField},
+};
+use fuser::{FileAttr, FileType};
+use sqlx::{
+    FromRow, QueryBuilder, Sqlite, SqlitePool, migrate, query, query_as, query_scalar,
+    sqlite::SqlitePoolOptions,
+};
+use std::time::SystemTime;
+
+/// Store keeping everything in a SQLite database, file contents included
+#[derive(Debug)]
+pub struct SqliteStore {
+    pub pool: SqlitePool,
+}
+
+/// Chunk of a page, as stored
+#[derive(FromRow)]
+struct ChunkRow {
+    hash: Vec<u8>,
+    /// `None` for blobs
+    bytes: Option<Vec<u8>>,
+    codec: u8,
+    len: i64,
+    blob: bool,
+}
+
+fn to_entry(row: ReadDirRow) -> Result<Entry, DBError> {
+    Ok(Entry {
+        attr: FileAttr::try_from(&row.attr)?,
+        name: row.name,
+    })
+}
+
+/// Translate a shell glob into a SQLite `GLOB` pattern that only has `*` and `?` as wildcards,
+/// like [`crate::db_helpers::glob_to_like`]
+fn glob_to_sqlite_glob(glob: &str) -> String {
+    glob.replace('[', "[[]")
+}
+
+/// Escape `s` to be matched literally by a SQLite `GLOB` pattern
+fn escape_glob(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '*' | '?' | '[' => escaped.extend(['[', c, ']']),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// SQLite date modifiers applied to `'now'` to get the start of `window`
+fn window_modifiers(window: RecentWindow) -> &'static str {
+    match window {
+        RecentWindow::Today => "'localtime', 'start of day', 'utc'",
+        RecentWindow::Week => "'-7 days'",
+        RecentWindow::Month => "'-1 month'",
+    }
+}
+
+impl Dialect for Sqlite {
+    fn push_prefixed(qb: &mut QueryBuilder<'_, Sqlite>, column: &str, prefix: &str) {
+        qb.push(format!("{column} GLOB "))
+            .push_bind(format!("{}*", escape_glob(prefix)));
+    }
+
+    // GLOB is case-sensitive, unlike LIKE
+    fn push_glob(qb: &mut QueryBuilder<'_, Sqlite>, column: &str, glob: &str) {
+        qb.push(format!("{column} GLOB "))
+            .push_bind(glob_to_sqlite_glob(glob));
+    }
+
+    fn window_start(window: RecentWindow) -> String {
+        format!(
+            "CAST(strftime('%s', 'now', {}) AS INTEGER)",
+            window_modifiers(window)
+        )
+    }
+
+    fn year(column: &str) -> String {
+        format!("CAST(strftime('%Y', {column}, 'unixepoch', 'localtime') AS INTEGER)")
+    }
+
+    fn month(column: &str) -> String {
+        format!("CAST(strftime('%m', {column}, 'unixepoch', 'localtime') AS INTEGER)")
+    }
+}
+
+impl SqliteStore {
+    pub fn new(pool: SqlitePool) -> SqliteStore {
+        SqliteStore { pool }
+    }
+
+    /// Create a store in a new in-memory database, which is dropped once the store is closed
+    pub async fn memory() -> Result<SqliteStore, DBError> {
+        // the database only lives as long as its connection, keep a single one open for good
+        let pool = SqlitePoolOptions::new()
+            .max_connections(1)
+            .min_connections(1)
+            .idle_timeout(None)
+            .max_lifetime(None)
+            .connect("sqlite::memory:")
+            .await?;
+        Ok(SqliteStore { pool })
+    }
+}
+
+impl Store for SqliteStore {
+    async fn migrate(&self) -> Result<(), DBError> {
+        migrate!()
+            .run(&self.pool)
+            .await
+            .map_err(|e| sqlx::Error::Migrate(Box::new(e)).into())
+    }
+
+    async fn close(&self) {
+        self.pool.close().await
+    }
+
+    async fn get_settings(&self) -> Result<Vec<(String, String)>, DBError> {
+        Ok(query_as("SELECT key, value FROM settings")
+            .fetch_all(&self.pool)
+            .await?)
+    }
+
+    async fn set_setting(&self, key: &str, value: &str) -> Result<(), DBError> {
+        query("INSERT OR REPLACE INTO settings (key, value) VALUES (?, ?)")
+            .bind(key)
+            .bind(value)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn get_attr(&self, ino: u64) -> Result<FileAttr, DBError> {
+        let row = query_as::<_, FileAttrRow>("SELECT * FROM file_attrs WHERE ino = ?")
+            .bind(i64::try_from(ino)?)
+            .fetch_one(&self.pool)
+            .await?;
+        Ok(FileAttr::try_from(&row)?)
+    }
+
+    async fn insert_attr(&self, attr: &FileAttr) -> Result<u64, DBError> {
+        let q = query_scalar::<_, u64>(
+            "INSERT INTO file_attrs VALUES (NULL, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, \
+             $13, $14, $15) RETURNING ino",
+        );
+        Ok(try_bind_attrs(q, attr)?
+            .inner()
+            .fetch_one(&self.pool)
+            .await?)
+    }
+
+    async fn insert_attr_if_missing(&self, attr: &FileAttr) -> Result<(), DBError> {
+        let q = query(
+            "INSERT OR IGNORE INTO file_attrs VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, \
+             $11, $12, $13, $14, $15)",
+        );
+        try_bind_attrs(q, attr)?.execute(&self.pool).await?;
+        Ok(())
+    }
+
+    async fn update_attr(&self, attr: &FileAttr) -> Result<(), DBError> {
+        let q = query(
+            "UPDATE file_attrs SET size = $2, blocks = $3, atime = $4, mtime = $5, ctime = $6, \
+             crtime = $7, kind = $8, perm = $9, nlink = $10, uid = $11, gid = $12, rdev = $13, \
+             blksize = $14, flags = $15 WHERE ino = $1",
+        );
+        try_bind_attrs(q, attr)?.execute(&self.pool).await?;
+        Ok(())
+    }
+
+    async fn set_atime(&self, ino: u64, atime: SystemTime) -> Result<(), DBError> {
+        query("UPDATE file_attrs SET atime = ? WHERE ino = ?")
+            .bind(i64::try_from(from_systime(atime)?)?)
+            .bind(i64::try_from(ino)?)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn set_mtime(&self, ino: u64, mtime: SystemTime) -> Result<(), DBError> {
+        query("UPDATE file_attrs SET mtime = ? WHERE ino = ?")
+            .bind(i64::try_from(from_systime(mtime)?)?)
+            .bind(i64::try_from(ino)?)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn set_size(&self, ino: u64, size: u64) -> Result<(), DBError> {
+        query("UPDATE file_attrs SET size = ? WHERE ino = ?")
+            .bind(i64::try_from(size)?)
+            .bind(i64::try_from(ino)?)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn grow_size(&self, ino: u64, size: u64) -> Result<(), DBError> {
+        query("UPDATE file_attrs SET size = MAX(size, ?) WHERE ino = ?")
+            .bind(i64::try_from(size)?)
+            .bind(i64::try_from(ino)?)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn set_blocks(&self, ino: u64, blocks: u64) -> Result<(), DBError> {
+        query("UPDATE file_attrs SET blocks = ? WHERE ino = ?")
+            .bind(i64::try_from(blocks)?)
+            .bind(i64::try_from(ino)?)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn delete_inode(&self, ino: u64) -> Result<(), DBError> {
+        // names, tags, directory membership and pages cascade
+        query("DELETE FROM file_attrs WHERE ino = ?")
+            .bind(i64::try_from(ino)?)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn get_name(&self, ino: u64) -> Result<Option<String>, DBError> {
+        Ok(query_scalar("SELECT name FROM file_names WHERE ino = ?")
+            .bind(i64::try_from(ino)?)
+            .fetch_optional(&self.pool)
+            .await?)
+    }
+
+    async fn insert_name(&self, ino: u64, name: &str) -> Result<(), DBError> {
+        query("INSERT INTO file_names VALUES (?, ?)")
+            .bind(i64::try_from(ino)?)
+            .bind(name)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn set_name(&self, ino: u64, name: &str) -> Result<(), DBError> {
+        query("UPDATE file_names SET name = ? WHERE ino = ?")
+            .bind(name)
+            .bind(i64::try_from(ino)?)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn get_tid(&self, name: &str) -> Result<Option<u64>, DBError> {
+        Ok(query_scalar("SELECT tid FROM tags WHERE name = ?")
+            .bind(name)
+            .fetch_optional(&self.pool)
+            .await?)
+    }
+
+    async fn create_tag(&self, name: &str) -> Result<u64, DBError> {
+        Ok(
+            query_scalar("INSERT INTO tags (name) VALUES (?) RETURNING tid")
+                .bind(name)
+                .fetch_one(&self.pool)
+                .await?,
+        )
+    }
+
+    async fn delete_tag(&self, tid: u64) -> Result<(), DBError> {
+        query("DELETE FROM tags WHERE tid = ?")
+            .bind(i64::try_from(tid)?)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn list_tags(&self, after: u64, limit: u64) -> Result<Vec<(u64, String)>, DBError> {
+        Ok(
+            query_as("SELECT tid, name FROM tags WHERE tid > ? ORDER BY tid LIMIT ?")
+                .bind(i64::try_from(after)?)
+                .bind(i64::try_from(limit)?)
+                .fetch_all(&self.pool)
+                .await?,
+        )
+    }
+
+    async fn get_tags(&self, ino: u64) -> Result<Vec<u64>, DBError> {
+        Ok(
+            query_scalar::<_, u64>("SELECT tid FROM associated_tags WHERE ino = ?")
+                .bind(i64::try_from(ino)?)
+                .fetch_all(&self.pool)
+                .await?,
+        )
+    }
+
+    async fn tag(&self, tid: u64, ino: u64) -> Result<(), DBError> {
+        query("INSERT OR IGNORE INTO associated_tags (tid, ino) VALUES (?, ?)")
+            .bind(i64::try_from(tid)?)
+            .bind(i64::try_from(ino)?)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn untag(&self, tid: u64, ino: u64) -> Result<(), DBError> {
+        query("DELETE FROM associated_tags WHERE tid = ? AND ino = ?")
+            .bind(i64::try_from(tid)?)
+            .bind(i64::try_from(ino)?)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn clear_tags(&self, ino: u64) -> Result<(), DBError> {
+        query("DELETE FROM associated_tags WHERE ino = ?")
+            .bind(i64::try_from(ino)?)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn count_tagged(&self, tid: u64) -> Result<u64, DBError> {
+        Ok(
+            query_scalar("SELECT COUNT() FROM associated_tags WHERE tid = ?")
+                .bind(i64::try_from(tid)?)
+                .fetch_one(&self.pool)
+                .await?,
+        )
+    }
+
+    async fn count_tag_files(&self, tid: u64, tag_prefix: &str) -> Result<u64, DBError> {
+        Ok(count_tag_files_query(tid, tag_prefix)?
+            .build_query_scalar()
+            .fetch_one(&self.pool)
+            .await?)
+    }
+
+    async fn get_tagged(&self, tags: &[u64], tag_prefix: &str) -> Result<Vec<u64>, DBError> {
+        Ok(tagged_query(tags, tag_prefix)?
+            .build_query_scalar()
+            .fetch_all(&self.pool)
+            .await?)
+    }
+
+    async fn get_tag_views(
+        &self,
+        inos: &[u64],
+        tag_prefix: &str,
+    ) -> Result<Vec<(u64, u64)>, DBError> {
+        if inos.is_empty() {
+            return Ok(vec![]);
+        }
+        Ok(tag_views_query(inos, tag_prefix)?
+            .build_query_as()
+            .fetch_all(&self.pool)
+            .await?)
+    }
+
+    async fn get_names_and_tags(
+        &self,
+        inos: &[u64],
+    ) -> Result<Vec<(u64, String, Option<u64>)>, DBError> {
+        if inos.is_empty() {
+            return Ok(vec![]);
+        }
+        Ok(names_and_tags_query(inos)?
+            .build_query_as()
+            .fetch_all(&self.pool)
+            .await?)
+    }
+
+    async fn get_tag_dirs(&self, name: &str) -> Result<Vec<(u64, String)>, DBError> {
+        Ok(query_as(
+            "WITH RECURSIVE tag_dirs(ino) AS (SELECT ino FROM readdir_rows WHERE name = $1 AND \
+             kind = $2 UNION SELECT cnt_ino FROM dir_contents INNER JOIN tag_dirs ON dir_ino = \
+             tag_dirs.ino) SELECT file_names.ino, name FROM file_names INNER JOIN tag_dirs ON \
+             file_names.ino = tag_dirs.ino",
+        )
+        .bind(name)
+        .bind(from_filetype(FileType::Directory))
+        .fetch_all(&self.pool)
+        .await?)
+    }
+
+    async fn add_to_dir(&self, dir: u64, ino: u64) -> Result<(), DBError> {
+        query("INSERT OR IGNORE INTO dir_contents (dir_ino, cnt_ino) VALUES (?, ?)")
+            .bind(i64::try_from(dir)?)
+            .bind(i64::try_from(ino)?)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn remove_from_dir(&self, dir: u64, ino: u64) -> Result<(), DBError> {
+        query("DELETE FROM dir_contents WHERE dir_ino = ? AND cnt_ino = ?")
+            .bind(i64::try_from(dir)?)
+            .bind(i64::try_from(ino)?)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn get_dir_contents(&self, dir: u64) -> Result<Vec<(u64, String)>, DBError> {
+        Ok(query_as(
+            "SELECT cnt_ino, name FROM dir_contents INNER JOIN file_names ON file_names.ino = \
+             dir_contents.cnt_ino WHERE dir_ino = ?",
+        )
+        .bind(i64::try_from(dir)?)
+        .fetch_all(&self.pool)
+        .await?)
+    }
+
+    async fn is_reachable(&self, ino: u64) -> Result<bool, DBError> {
+        Ok(query(
+            "SELECT 1 FROM associated_tags WHERE ino = $1 UNION SELECT 1 FROM dir_contents WHERE \
+             cnt_ino = $1",
+        )
+        .bind(i64::try_from(ino)?)
+        .fetch_optional(&self.pool)
+        .await?
+        .is_some())
+    }
+
+    async fn get_parent(&self, ino: u64) -> Result<Option<u64>, DBError> {
+        Ok(
+            query_scalar("SELECT dir_ino FROM dir_contents WHERE cnt_ino = ? LIMIT 1")
+                .bind(i64::try_from(ino)?)
+                .fetch_optional(&self.pool)
+                .await?,
+        )
+    }
+
+    async fn lookup_entry(&self, dir: u64, tags: &[u64], name: &str) -> Result<Entry, DBError> {
+        let row: ReadDirRow = lookup_entry_query(dir, tags, name)?
+            .build_query_as()
+            .fetch_one(&self.pool)
+            .await?;
+        to_entry(row)
+    }
+
+    async fn list_entries(
+        &self,
+        dir: u64,
+        tags: &[u64],
+        tag_prefix: &str,
+        after: u64,
+        limit: u64,
+    ) -> Result<Vec<Entry>, DBError> {
+        let rows: Vec<ReadDirRow> = list_entries_query(dir, tags, tag_prefix, after, limit)?
+            .build_query_as()
+            .fetch_all(&self.pool)
+            .await?;
+        rows.into_iter().map(to_entry).collect()
+    }
+
+    async fn get_members(&self, dir: u64, tags: &[u64]) -> Result<Vec<u64>, DBError> {
+        Ok(members_query(dir, tags)?
+            .build_query_scalar()
+            .fetch_all(&self.pool)
+            .await?)
+    }
+
+    async fn filter_entries(
+        &self,
+        filter: &FileFilter<'_>,
+        name: Option<&str>,
+        after: u64,
+        limit: u64,
+    ) -> Result<Vec<Entry>, DBError> {
+        let rows: Vec<ReadDirRow> = filter_entries_query(filter, name, after, limit)?
+            .build_query_as()
+            .fetch_all(&self.pool)
+            .await?;
+        rows.into_iter().map(to_entry).collect()
+    }
+
+    async fn list_time_parts(
+        &self,
+        base_tags: Option<&[u64]>,
+        field: TimeField,
+        year: Option<u32>,
+        after: u64,
+        limit: u64,
+    ) -> Result<Vec<u32>, DBError> {
+        Ok(time_parts_query(base_tags, field, year, after, limit)?
+            .build_query_scalar()
+            .fetch_all(&self.pool)
+            .await?)
+    }
+
+    async fn get_chunk(&self, ino: u64, page: u64) -> Result<Option<Chunk>, DBError> {
+        let row: Option<ChunkRow> = query_as(
+            "SELECT chunks.hash, chunks.bytes, codec, len, blob FROM file_contents INNER JOIN \
+             chunks ON chunks.hash = file_contents.hash WHERE ino = ? AND page = ?",
+        )
+        .bind(i64::try_from(ino)?)
+        .bind(i64::try_from(page)?)
+        .fetch_optional(&self.pool)
+        .await?;
+        let Some(row) = row else {
+            return Ok(None);
+        };
+        Ok(Some(Chunk {
+            hash: row.hash,
+            bytes: row.bytes,
+            codec: row.codec,
+            len: row.len.try_into()?,
+            blob: row.blob,
+        }))
+    }
+
+    async fn has_chunk(&self, hash: &[u8]) -> Result<bool, DBError> {
+        Ok(query("SELECT 1 FROM chunks WHERE hash = ?")
+            .bind(hash)
+            .fetch_optional(&self.pool)
+            .await?
+            .is_some())
+    }
+
+    async fn put_page(
+        &self,
+        ino: u64,
+        page: u64,
+        hash: &[u8],
+        new_chunk: Option<Chunk>,
+    ) -> Result<(), DBError> {
+        // keep the chunk from being collected before it's referenced
+        let mut tx = self.pool.begin().await?;
+        if let Some(chunk) = new_chunk {
+            query(
+                "INSERT OR IGNORE INTO chunks (hash, bytes, codec, len, blob) VALUES (?, ?, ?, ?, \
+                 ?)",
+            )
+            .bind(chunk.hash)
+            .bind(chunk.bytes)
+            .bind(chunk.codec)
+            .bind(i64::try_from(chunk.len)?)
+            .bind(chunk.blob)
+            .execute(&mut *tx)
+            .await?;
+        }
+        query(
+            "INSERT INTO file_contents (ino, page, hash) VALUES (?, ?, ?) ON CONFLICT (ino, page) \
+             DO UPDATE SET hash = excluded.hash",
+        )
+        .bind(i64::try_from(ino)?)
+        .bind(i64::try_from(page)?)
+        .bind(hash)
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn delete_pages(&self, ino: u64, from: u64, to: Option<u64>) -> Result<(), DBError> {
+        query("DELETE FROM file_contents WHERE ino = ? AND page >= ? AND (? IS NULL OR page < ?)")
+            .bind(i64::try_from(ino)?)
+            .bind(i64::try_from(from)?)
+            .bind(to.map(i64::try_from).transpose()?)
+            .bind(to.map(i64::try_from).transpose()?)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn copy_pages(
+        &self,
+        ino_in: u64,
+        page_in: u64,
+        ino_out: u64,
+        page_out: u64,
+        pages: u64,
+    ) -> Result<(), DBError> {
+        let page_in = i64::try_from(page_in)?;
+        let page_out = i64::try_from(page_out)?;
+        let pages = i64::try_from(pages)?;
+        let mut tx = self.pool.begin().await?;
+        // clear pages the source doesn't have stored
+        query("DELETE FROM file_contents WHERE ino = ? AND page >= ? AND page < ?")
+            .bind(i64::try_from(ino_out)?)
+            .bind(page_out)
+            .bind(page_out + pages)
+            .execute(&mut *tx)
+            .await?;
+        query(
+            "INSERT INTO file_contents (ino, page, hash) SELECT ?, page - ? + ?, hash FROM \
+             file_contents WHERE ino = ? AND page >= ? AND page < ? ON CONFLICT (ino, page) DO \
+             UPDATE SET hash = excluded.hash",
+        )
+        .bind(i64::try_from(ino_out)?)
+        .bind(page_in)
+        .bind(page_out)
+        .bind(i64::try_from(ino_in)?)
+        .bind(page_in)
+        .bind(page_in + pages)
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn count_pages(&self, ino: u64) -> Result<u64, DBError> {
+        Ok(
+            query_scalar("SELECT COUNT(*) FROM file_contents WHERE ino = ?")
+                .bind(i64::try_from(ino)?)
+                .fetch_one(&self.pool)
+                .await?,
+        )
+    }
+
+    async fn next_page(&self, ino: u64, page: u64) -> Result<Option<u64>, DBError> {
+        Ok(query_scalar(
+            "SELECT page FROM file_contents WHERE ino = ? AND page >= ? ORDER BY page LIMIT 1",
+        )
+        .bind(i64::try_from(ino)?)
+        .bind(i64::try_from(page)?)
+        .fetch_optional(&self.pool)
+        .await?)
+    }
+
+    async fn end_of_pages(&self, ino: u64, page: u64) -> Result<Option<u64>, DBError> {
+        let i64_ino = i64::try_from(ino)?;
+        let stored = query("SELECT 1 FROM file_contents WHERE ino = ? AND page = ?")
+            .bind(i64_ino)
+            .bind(i64::try_from(page)?)
+            .fetch_optional(&self.pool)
+            .await?
+            .is_some();
+        if !stored {
+            return Ok(None);
+        }
+        Ok(Some(
+            query_scalar(
+                "SELECT page + 1 FROM file_contents AS fc WHERE ino = $1 AND page >= $2 AND NOT \
+                 EXISTS (SELECT 1 FROM file_contents WHERE ino = $1 AND page = fc.page + 1) \
+                 ORDER BY page LIMIT 1",
+            )
+            .bind(i64_ino)
+            .bind(i64::try_from(page)?)
+            .fetch_one(&self.pool)
+            .await?,
+        ))
+    }
+
+    async fn delete_unreferenced_chunks(&self) -> Result<Vec<(Vec<u8>, bool)>, DBError> {
+        Ok(
+            query_as("DELETE FROM chunks WHERE refs <= 0 RETURNING hash, blob")
+                .fetch_all(&self.pool)
+                .await?,
+        )
+    }
+
+    async fn get_blob_hashes(&self) -> Result<Vec<Vec<u8>>, DBError> {
+        Ok(query_scalar("SELECT hash FROM chunks WHERE blob = 1")
+            .fetch_all(&self.pool)
+            .await?)
+    }
+
+    async fn get_legacy_pages(
+        &self,
+        after: (u64, u64),
+        limit: u64,
+    ) -> Result<Vec<(u64, u64, Vec<u8>)>, DBError> {
+        Ok(query_as(
+            "SELECT ino, page, bytes FROM file_contents WHERE hash IS NULL AND (ino, page) > (?, \
+             ?) ORDER BY ino, page LIMIT ?",
+        )
+        .bind(i64::try_from(after.0)?)
+        .bind(i64::try_from(after.1)?)
+        .bind(i64::try_from(limit)?)
+        .fetch_all(&self.pool)
+        .await?)
+    }
+
+    async fn put_legacy_pages(
+        &self,
+        pages: Vec<(u64, u64, Vec<u8>, Option<Chunk>)>,
+    ) -> Result<(), DBError> {
+        let mut tx = self.pool.begin().await?;
+        for (ino, page, hash, new_chunk) in pages {
+            if let Some(chunk) = new_chunk {
+                query(
+                    "INSERT OR IGNORE INTO chunks (hash, bytes, codec, len, blob) VALUES (?, ?, \
+                     ?, ?, ?)",
+                )
+                .bind(chunk.hash)
+                .bind(chunk.bytes)
+                .bind(chunk.codec)
+                .bind(i64::try_from(chunk.len)?)
+                .bind(chunk.blob)
+                .execute(&mut *tx)
+                .await?;
+            }
+            query("UPDATE file_contents SET hash = ?, bytes = NULL WHERE ino = ? AND page = ?")
+                .bind(hash)
+                .bind(i64::try_from(ino)?)
+                .bind(i64::try_from(page)?)
+                .execute(&mut *tx)
+                .await?;
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+}
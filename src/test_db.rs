@@ -1,8 +1,30 @@
 #[cfg(test)]
 mod test {
-    use crate::db_helpers::chain_tagged_inos;
-    use sqlx::{QueryBuilder, Sqlite};
-    use tokio::test;
+    use crate::{
+        Cipher, Compression, Encryption, PTFS, Settings, SqliteStore, Store, Ttl,
+        db_helpers::{chain_tagged_inos, glob_to_like},
+        vdirs::{VirtualDir, VirtualDirs},
+    };
+    use sqlx::{QueryBuilder, Sqlite, query, query_as, query_scalar};
+    use std::{
+        fs::{remove_dir_all, remove_file},
+        time::Duration,
+    };
+    use tokio::{runtime::Handle, test};
+
+    /// Migrated in-memory store
+    async fn memory_store() -> SqliteStore {
+        let store = SqliteStore::memory()
+            .await
+            .map_err(|_| "failed creating database")
+            .unwrap();
+        store
+            .migrate()
+            .await
+            .map_err(|_| "failed migrating")
+            .unwrap();
+        store
+    }
 
     #[test]
     async fn chain_tagged_inos_test() {
@@ -16,9 +38,883 @@ mod test {
 
         assert_eq!(
             qb.sql(),
-            "SELECT ino FROM associated_tags WHERE tid = ? AND ino IN (SELECT ino FROM \
-             associated_tags WHERE tid = ? AND ino IN (SELECT ino FROM associated_tags WHERE tid \
-             = ?))"
+            "SELECT ino FROM associated_tags WHERE tid IN (?, ?, ?) GROUP BY ino HAVING \
+             COUNT(DISTINCT tid) = ?"
+        );
+
+        // only inodes tagged with all of the tags are selected, however often they're given
+        let store = memory_store().await;
+        query(
+            "INSERT INTO file_attrs (ino) VALUES (1), (2), (3); INSERT INTO tags (tid, name) \
+             VALUES (1, '#a'), (2, '#b'), (3, '#c'); INSERT INTO associated_tags (tid, ino) \
+             VALUES (1, 1), (2, 1), (3, 1), (1, 2), (2, 2), (1, 3), (3, 3)",
+        )
+        .execute(&store.pool)
+        .await
+        .unwrap();
+        for (tags, inos) in [
+            (vec![1, 2, 3], vec![1]),
+            (vec![1, 2], vec![1, 2]),
+            (vec![2, 1, 2], vec![1, 2]),
+            (vec![1, 2, 1], vec![1, 2]),
+            (vec![1], vec![1, 2, 3]),
+            (vec![], vec![]),
+        ] {
+            let mut qb = QueryBuilder::<Sqlite>::new("");
+            chain_tagged_inos(&mut qb, &tags)
+                .map_err(|_| "failed binding tags")
+                .unwrap();
+            qb.push(" ORDER BY ino");
+            let selected: Vec<u64> = qb
+                .build_query_scalar()
+                .fetch_all(&store.pool)
+                .await
+                .unwrap();
+            assert_eq!(selected, inos);
+        }
+    }
+
+    #[test]
+    async fn glob_to_like_test() {
+        assert_eq!(glob_to_like("*.pdf"), "%.pdf");
+        assert_eq!(glob_to_like("img_??.png"), "img\\___.png");
+        assert_eq!(glob_to_like("100%\\*"), "100\\%\\\\%");
+    }
+
+    #[test]
+    async fn vdirs_test() {
+        let vdirs = VirtualDirs::default();
+        let search = |pattern: &str| VirtualDir::Search {
+            base: 1,
+            pattern: Some(pattern.to_string()),
+        };
+        let tags = vdirs.ino(VirtualDir::Tags);
+        let ino = vdirs.ino(search("*.pdf"));
+        assert_eq!(vdirs.ino(search("*.pdf")), ino);
+        vdirs.looked_up(tags);
+
+        // inodes are kept until every lookup is forgotten
+        vdirs.looked_up(ino);
+        vdirs.looked_up(ino);
+        vdirs.forget(ino, 1);
+        assert_eq!(vdirs.get(ino), Some(search("*.pdf")));
+        vdirs.forget(ino, 1);
+        assert_eq!(vdirs.get(ino), None);
+        assert_eq!(vdirs.find(&search("*.pdf")), None);
+        assert_eq!(vdirs.all(), vec![(tags, VirtualDir::Tags)]);
+
+        // and then reused
+        assert_eq!(vdirs.ino(search("*.txt")), ino);
+        // inodes that were only listed are reclaimed once the reply is sent
+        vdirs.reclaim();
+        assert_eq!(vdirs.get(ino), None);
+        assert_eq!(vdirs.get(tags), Some(VirtualDir::Tags));
+        let listed = vdirs.ino(search("*.txt"));
+        let looked_up = vdirs.ino(search("*.md"));
+        vdirs.looked_up(looked_up);
+        vdirs.reclaim();
+        assert_eq!(
+            vdirs.all(),
+            vec![(tags, VirtualDir::Tags), (looked_up, search("*.md"))]
+        );
+        assert_eq!(vdirs.ino(search("*.rs")), listed);
+        // files aren't kept track of
+        vdirs.forget(2, 1);
+        vdirs.looked_up(2);
+        vdirs.forget(tags, 1);
+        assert_eq!(vdirs.get(tags), None);
+    }
+
+    #[test]
+    async fn compression_test() {
+        let text = "log line\n".repeat(512).into_bytes();
+        let random: Vec<u8> = rand::random_iter().take(4096).collect();
+
+        for compression in [Compression::Zstd, Compression::Lz4] {
+            let compressed = compression.compress(&text).unwrap();
+            assert!(compressed.len() < text.len());
+            assert_eq!(
+                compression
+                    .decompress(compressed, text.len())
+                    .map_err(|_| "failed decompressing")
+                    .unwrap(),
+                text
+            );
+            // incompressible pages are stored raw
+            assert!(compression.compress(&random).is_none());
+        }
+        assert!(Compression::None.compress(&text).is_none());
+    }
+
+    #[test]
+    async fn cipher_test() {
+        let cipher = Cipher::from_key_file(b"key");
+
+        let name = cipher.encrypt_name("file.pdf");
+        assert_eq!(name, cipher.encrypt_name("file.pdf"));
+        assert!(name.chars().all(|c| c.is_ascii_hexdigit()));
+        assert_eq!(
+            cipher
+                .decrypt_name(&name)
+                .map_err(|_| "failed decrypting name")
+                .unwrap(),
+            "file.pdf"
+        );
+
+        let bytes = b"page".to_vec();
+        let hash = cipher.chunk_hash(&bytes);
+        let encrypted = cipher.encrypt_chunk(&hash, &bytes);
+        assert_ne!(encrypted, bytes);
+        assert_eq!(
+            cipher
+                .decrypt_chunk(&hash, &encrypted)
+                .map_err(|_| "failed decrypting chunk")
+                .unwrap(),
+            bytes
+        );
+
+        // other keys can't decrypt
+        let other = Cipher::from_key_file(b"other key");
+        assert_ne!(other.key_check(), cipher.key_check());
+        assert!(other.decrypt_name(&name).is_err());
+        assert!(other.decrypt_chunk(&hash, &encrypted).is_err());
+    }
+
+    #[test]
+    async fn encryption_test() {
+        let store = memory_store().await;
+        let cipher = Cipher::from_key_file(b"key");
+        Settings {
+            encryption: Some(Encryption {
+                salt: vec![1; 16],
+                key_check: cipher.key_check(),
+                dedup: false,
+            }),
+            ..Default::default()
+        }
+        .save(&store)
+        .await
+        .map_err(|_| "failed saving settings")
+        .unwrap();
+        let mut fs = PTFS {
+            store,
+            runtime_handle: Handle::current(),
+            tag_prefix: "a".to_string(),
+            untagged_name: String::new(),
+            vdirs: Default::default(),
+            settings: Default::default(),
+            cipher: None,
+            ttl: Default::default(),
+            invalidator: Default::default(),
+            blob_dir: Default::default(),
+        };
+        fs.settings = Settings::load(&fs.store)
+            .await
+            .map_err(|_| "failed loading settings")
+            .unwrap();
+        assert!(!fs.settings.encryption.as_ref().unwrap().dedup);
+
+        // encrypted names are hex, so they could start with these prefixes
+        assert!(fs.unlock(Cipher::from_key_file(b"key")).is_err());
+        fs.tag_prefix = "0".to_string();
+        assert!(fs.unlock(Cipher::from_key_file(b"key")).is_err());
+        fs.tag_prefix = "#".to_string();
+        assert!(fs.unlock(Cipher::from_key_file(b"other key")).is_err());
+        fs.unlock(cipher).map_err(|_| "failed unlocking").unwrap();
+
+        // equal pages aren't deduplicated, so they don't reveal being equal
+        query("INSERT INTO file_attrs (ino, size) VALUES (2, 0), (3, 0)")
+            .execute(&fs.store.pool)
+            .await
+            .unwrap();
+        for ino in [2, 3] {
+            fs.write_data(ino, 0, b"page")
+                .await
+                .map_err(|_| "failed writing")
+                .unwrap();
+        }
+        let chunks: u64 = query_scalar("SELECT COUNT(*) FROM chunks")
+            .fetch_one(&fs.store.pool)
+            .await
+            .unwrap();
+        assert_eq!(chunks, 2);
+        assert_eq!(
+            fs.read_data(3, 0, 4)
+                .await
+                .map_err(|_| "failed reading")
+                .unwrap(),
+            b"page"
+        );
+    }
+
+    #[test]
+    async fn chunk_size_test() {
+        let store = memory_store().await;
+
+        // databases created before the setting keep their page size
+        let page_size: u64 = query_scalar("PRAGMA page_size")
+            .fetch_one(&store.pool)
+            .await
+            .unwrap();
+        let settings = Settings::load(&store)
+            .await
+            .map_err(|_| "failed loading settings")
+            .unwrap();
+        assert_eq!(settings.chunk_size, page_size);
+
+        Settings {
+            chunk_size: 1 << 16,
+            ttl: Ttl {
+                negative: Duration::from_millis(500),
+                ..settings.ttl
+            },
+            ..settings
+        }
+        .save(&store)
+        .await
+        .map_err(|_| "failed saving settings")
+        .unwrap();
+        let settings = Settings::load(&store)
+            .await
+            .map_err(|_| "failed loading settings")
+            .unwrap();
+        assert_eq!(settings.chunk_size, 1 << 16);
+        assert_eq!(settings.ttl.attr, Duration::from_secs(1));
+        assert_eq!(settings.ttl.negative, Duration::from_millis(500));
+    }
+
+    #[test]
+    async fn untag_all_test() {
+        let fs = PTFS {
+            store: memory_store().await,
+            runtime_handle: Handle::current(),
+            tag_prefix: "#".to_string(),
+            untagged_name: String::new(),
+            vdirs: Default::default(),
+            settings: Default::default(),
+            cipher: None,
+            ttl: Default::default(),
+            invalidator: Default::default(),
+            blob_dir: Default::default(),
+        };
+        // #a/sub, and a file tagged #a
+        query(
+            "INSERT INTO file_attrs (ino, kind) VALUES (1, 3), (2, 3), (3, 3), (4, 4); INSERT INTO \
+             file_names (ino, name) VALUES (2, '#a'), (3, 'sub'), (4, 'file'); INSERT INTO \
+             dir_contents (dir_ino, cnt_ino) VALUES (1, 2), (2, 3); INSERT INTO tags (tid, name) \
+             VALUES (1, '#a'); INSERT INTO associated_tags (tid, ino) VALUES (1, 4)",
         )
+        .execute(&fs.store.pool)
+        .await
+        .unwrap();
+        let tags = || query_scalar::<_, u64>("SELECT COUNT(*) FROM tags").fetch_one(&fs.store.pool);
+
+        // like rmdir, non-empty tag directories are kept unless they're deleted recursively
+        assert!(fs.untag_all("#a", false).await.is_err());
+        assert_eq!(tags().await.unwrap(), 1);
+
+        fs.untag_all("#a", true)
+            .await
+            .map_err(|_| "failed removing tag")
+            .unwrap();
+        assert_eq!(tags().await.unwrap(), 0);
+        let lost: Vec<(String, u64)> = query_as(
+            "SELECT name, cnt_ino FROM dir_contents INNER JOIN file_names ON file_names.ino = \
+             dir_ino",
+        )
+        .fetch_all(&fs.store.pool)
+        .await
+        .unwrap();
+        assert_eq!(lost, vec![("lost+found".to_string(), 4)]);
+    }
+
+    #[test]
+    async fn membership_test() {
+        let store = memory_store().await;
+        query("INSERT INTO file_attrs (ino) VALUES (1), (2)")
+            .execute(&store.pool)
+            .await
+            .unwrap();
+        query("INSERT INTO file_names (ino, name) VALUES (2, 'f')")
+            .execute(&store.pool)
+            .await
+            .unwrap();
+        let tid = store
+            .create_tag("#a")
+            .await
+            .map_err(|_| "failed creating tag")
+            .unwrap();
+
+        // associating twice is the same as associating once
+        for _ in 0..2 {
+            store
+                .tag(tid, 2)
+                .await
+                .map_err(|_| "failed tagging")
+                .unwrap();
+            store
+                .add_to_dir(1, 2)
+                .await
+                .map_err(|_| "failed adding to directory")
+                .unwrap();
+        }
+        assert_eq!(
+            store
+                .count_tagged(tid)
+                .await
+                .map_err(|_| "failed counting")
+                .unwrap(),
+            1
+        );
+        assert_eq!(
+            store
+                .get_dir_contents(1)
+                .await
+                .map_err(|_| "failed listing")
+                .unwrap()
+                .len(),
+            1
+        );
+
+        // so a single untag orphans the tag
+        store
+            .untag(tid, 2)
+            .await
+            .map_err(|_| "failed untagging")
+            .unwrap();
+        assert_eq!(
+            store
+                .count_tagged(tid)
+                .await
+                .map_err(|_| "failed counting")
+                .unwrap(),
+            0
+        );
+    }
+
+    #[test]
+    async fn tag_views_test() {
+        let store = memory_store().await;
+        query(
+            "INSERT INTO file_attrs (ino) VALUES (1), (2), (3), (4), (5), (6), (7); INSERT INTO \
+             file_names (ino, name) VALUES (2, 'ab'), (3, 'a'), (4, '#a'), (5, '#b'), (6, 'dir'), \
+             (7, 'none'); INSERT INTO tags (tid, name) VALUES (1, '#a'), (2, '#b'); INSERT INTO \
+             associated_tags (tid, ino) VALUES (1, 2), (2, 2), (1, 3), (1, 4), (1, 5), (2, 5), (1, \
+             6)",
+        )
+        .execute(&store.pool)
+        .await
+        .unwrap();
+
+        // #a/#b only lists files with both tags, unprefixed directories list nothing by tags
+        let mut views = store
+            .get_tag_views(&[2, 3, 7], "#")
+            .await
+            .map_err(|_| "failed getting views")
+            .unwrap();
+        views.sort();
+        assert_eq!(views, vec![(2, 4), (2, 5), (3, 4)]);
+
+        let mut names = store
+            .get_names_and_tags(&[3, 2, 7])
+            .await
+            .map_err(|_| "failed getting names")
+            .unwrap();
+        names.sort();
+        assert_eq!(
+            names,
+            vec![
+                (2, "ab".to_string(), Some(1)),
+                (2, "ab".to_string(), Some(2)),
+                (3, "a".to_string(), Some(1)),
+                (7, "none".to_string(), None),
+            ]
+        );
+
+        // prefixes are matched case-sensitively, and without wildcards
+        for (prefix, inos) in [
+            ("a", vec![4, 5, 6]),
+            ("A", vec![2, 3, 4, 5, 6]),
+            ("?", vec![2, 3, 4, 5, 6]),
+        ] {
+            let mut tagged = store
+                .get_tagged(&[1], prefix)
+                .await
+                .map_err(|_| "failed getting tagged files")
+                .unwrap();
+            tagged.sort();
+            assert_eq!(tagged, inos);
+        }
+        // #a lists ab, a and dir
+        assert_eq!(
+            store
+                .count_tag_files(1, "#")
+                .await
+                .map_err(|_| "failed counting")
+                .unwrap(),
+            3
+        );
+    }
+
+    #[test]
+    async fn blob_test() {
+        let fs = PTFS {
+            store: memory_store().await,
+            runtime_handle: Handle::current(),
+            tag_prefix: String::new(),
+            untagged_name: String::new(),
+            vdirs: Default::default(),
+            settings: Settings {
+                blob_threshold: Some(1024),
+                ..Default::default()
+            },
+            cipher: None,
+            ttl: Default::default(),
+            invalidator: Default::default(),
+            blob_dir: std::env::temp_dir().join(format!("ptfs-blobs-{}", rand::random::<u64>())),
+        };
+        query("INSERT INTO file_attrs (ino, size) VALUES (2, 0)")
+            .execute(&fs.store.pool)
+            .await
+            .unwrap();
+
+        // only chunks above the threshold are blobs
+        let small = vec![1u8; 512];
+        let large: Vec<u8> = rand::random_iter().take(4096).collect();
+        fs.write_data(2, 0, &small)
+            .await
+            .map_err(|_| "failed writing")
+            .unwrap();
+        fs.write_data(2, 4096, &large)
+            .await
+            .map_err(|_| "failed writing")
+            .unwrap();
+        let blobs: Vec<bool> = query_scalar("SELECT blob FROM chunks ORDER BY len")
+            .fetch_all(&fs.store.pool)
+            .await
+            .unwrap();
+        assert_eq!(blobs, vec![false, true]);
+        assert_eq!(
+            fs.read_data(2, 4096, 4096)
+                .await
+                .map_err(|_| "failed reading")
+                .unwrap(),
+            large
+        );
+
+        let report = fs.fsck(false).await.map_err(|_| "failed fsck").unwrap();
+        assert!(report.missing_blobs.is_empty() && report.orphan_blobs.is_empty());
+
+        // nothing is repaired while mounted, and the lock file isn't an orphan
+        let lock = fs.lock_blobs(false).map_err(|_| "failed locking").unwrap();
+        assert!(lock.is_some());
+        let err = fs.fsck(true).await.err().unwrap();
+        assert_eq!(err.map_db_err().0, libc::EWOULDBLOCK);
+        let report = fs.fsck(false).await.map_err(|_| "failed fsck").unwrap();
+        assert!(report.orphan_blobs.is_empty());
+        drop(lock);
+
+        // orphans are found and removed
+        let blob_path = fs.blob_path(&blake3::hash(&large).as_bytes()[..]);
+        fs.write_blob(&[0; 32], b"orphan")
+            .map_err(|_| "failed writing blob")
+            .unwrap();
+        let report = fs.fsck(true).await.map_err(|_| "failed fsck").unwrap();
+        assert_eq!(report.orphan_blobs.len(), 1);
+        assert!(
+            fs.fsck(false)
+                .await
+                .map_err(|_| "failed fsck")
+                .unwrap()
+                .orphan_blobs
+                .is_empty()
+        );
+
+        // as are missing blobs
+        remove_file(&blob_path).unwrap();
+        let report = fs.fsck(false).await.map_err(|_| "failed fsck").unwrap();
+        assert_eq!(report.missing_blobs.len(), 1);
+
+        // blobs of collected chunks are removed
+        fs.write_blob(&blake3::hash(&large).as_bytes()[..], &large)
+            .map_err(|_| "failed writing blob")
+            .unwrap();
+        fs.change_file_size(2, 512)
+            .await
+            .map_err(|_| "failed truncating")
+            .unwrap();
+        assert!(!blob_path.exists());
+
+        remove_dir_all(&fs.blob_dir).unwrap();
+    }
+
+    #[test]
+    async fn legacy_pages_test() {
+        let fs = PTFS {
+            store: memory_store().await,
+            runtime_handle: Handle::current(),
+            tag_prefix: String::new(),
+            untagged_name: String::new(),
+            vdirs: Default::default(),
+            settings: Default::default(),
+            cipher: None,
+            ttl: Default::default(),
+            invalidator: Default::default(),
+            blob_dir: Default::default(),
+        };
+        // more pages than are converted at once, with three distinct contents
+        query(
+            "INSERT INTO file_attrs (ino, size) VALUES (2, 0); WITH RECURSIVE pages (page) AS \
+             (SELECT 0 UNION ALL SELECT page + 1 FROM pages WHERE page < 599) INSERT INTO \
+             file_contents (ino, page, bytes) SELECT 2, page, CAST(page % 3 AS BLOB) FROM pages",
+        )
+        .execute(&fs.store.pool)
+        .await
+        .unwrap();
+
+        fs.convert_legacy_pages()
+            .await
+            .map_err(|_| "failed converting")
+            .unwrap();
+        let legacy: i64 = query_scalar(
+            "SELECT COUNT(*) FROM file_contents WHERE hash IS NULL OR bytes IS NOT NULL",
+        )
+        .fetch_one(&fs.store.pool)
+        .await
+        .unwrap();
+        assert_eq!(legacy, 0);
+        let refs: Vec<i64> = query_scalar("SELECT refs FROM chunks")
+            .fetch_all(&fs.store.pool)
+            .await
+            .unwrap();
+        assert_eq!(refs, vec![200, 200, 200]);
+        assert_eq!(
+            fs.get_page(2, 599)
+                .await
+                .map_err(|_| "failed reading")
+                .unwrap(),
+            Some(b"2".to_vec())
+        );
+    }
+
+    /// PostgreSQL server in a temporary directory, listening on a socket there only, which is
+    /// stopped and removed on drop
+    #[cfg(feature = "postgres")]
+    struct PgServer {
+        dir: std::path::PathBuf,
+    }
+
+    #[cfg(feature = "postgres")]
+    impl PgServer {
+        /// Start a server with `initdb` and `pg_ctl`, which have to be on the `PATH`
+        fn start() -> PgServer {
+            use std::process::Command;
+
+            let dir = std::env::temp_dir().join(format!("ptfs-pg-{}", rand::random::<u64>()));
+            let status = Command::new("initdb")
+                .args(["-A", "trust", "-U", "ptfs", "-D"])
+                .arg(dir.join("data"))
+                .output()
+                .unwrap()
+                .status;
+            assert!(status.success(), "failed initdb");
+            let status = Command::new("pg_ctl")
+                .args(["-w", "-D"])
+                .arg(dir.join("data"))
+                .arg("-l")
+                .arg(dir.join("log"))
+                .arg("-o")
+                .arg(format!("-c listen_addresses='' -k {}", dir.display()))
+                .arg("start")
+                .status()
+                .unwrap();
+            assert!(status.success(), "failed starting server");
+            PgServer { dir }
+        }
+
+        async fn store(&self) -> crate::PgStore {
+            let url = format!(
+                "postgres://ptfs@localhost/postgres?host={}",
+                self.dir.display()
+            );
+            let store = crate::PgStore::new(sqlx::PgPool::connect(&url).await.unwrap());
+            store
+                .migrate()
+                .await
+                .map_err(|_| "failed migrating")
+                .unwrap();
+            store
+        }
+    }
+
+    #[cfg(feature = "postgres")]
+    impl Drop for PgServer {
+        fn drop(&mut self) {
+            std::process::Command::new("pg_ctl")
+                .args(["-m", "immediate", "-D"])
+                .arg(self.dir.join("data"))
+                .arg("stop")
+                .status()
+                .ok();
+            remove_dir_all(&self.dir).ok();
+        }
+    }
+
+    #[cfg(feature = "postgres")]
+    #[test]
+    async fn postgres_test() {
+        use crate::{
+            store::FileFilter,
+            vdirs::{RecentWindow, TimeField},
+        };
+        use fuser::{FileAttr, FileType};
+        use std::time::SystemTime;
+
+        let server = PgServer::start();
+        let fs = PTFS {
+            store: server.store().await,
+            runtime_handle: Handle::current(),
+            tag_prefix: "#".to_string(),
+            untagged_name: String::new(),
+            vdirs: Default::default(),
+            settings: Default::default(),
+            cipher: None,
+            ttl: Default::default(),
+            invalidator: Default::default(),
+            blob_dir: Default::default(),
+        };
+        // migrations are only applied once
+        fs.migrate().await.map_err(|_| "failed migrating").unwrap();
+
+        let now = SystemTime::now();
+        let attr = FileAttr {
+            ino: 1,
+            size: 0,
+            blocks: 0,
+            atime: now,
+            mtime: now,
+            ctime: now,
+            crtime: now,
+            kind: FileType::Directory,
+            perm: 0o755,
+            nlink: 2,
+            uid: 0,
+            gid: 0,
+            rdev: 0,
+            blksize: 0,
+            flags: 0,
+        };
+        fs.store
+            .insert_attr_if_missing(&attr)
+            .await
+            .map_err(|_| "failed inserting root")
+            .unwrap();
+        // new inodes don't collide with the root
+        let ino = fs
+            .store
+            .insert_attr(&FileAttr {
+                kind: FileType::RegularFile,
+                ..attr
+            })
+            .await
+            .map_err(|_| "failed inserting file")
+            .unwrap();
+        assert_eq!(ino, 2);
+        fs.store
+            .insert_name(ino, "a.pdf")
+            .await
+            .map_err(|_| "failed naming file")
+            .unwrap();
+
+        let tid = fs
+            .store
+            .create_tag("#work")
+            .await
+            .map_err(|_| "failed creating tag")
+            .unwrap();
+        fs.store
+            .tag(tid, ino)
+            .await
+            .map_err(|_| "failed tagging")
+            .unwrap();
+        let entry = fs
+            .store
+            .lookup_entry(1, &[tid], "a.pdf")
+            .await
+            .map_err(|_| "failed looking up")
+            .unwrap();
+        assert_eq!(entry.attr.ino, ino);
+        // the tag directory lists the file, unlike directories with other tags
+        let dir = fs
+            .store
+            .insert_attr(&attr)
+            .await
+            .map_err(|_| "failed inserting tag directory")
+            .unwrap();
+        fs.store
+            .insert_name(dir, "#work")
+            .await
+            .map_err(|_| "failed naming tag directory")
+            .unwrap();
+        fs.store
+            .tag(tid, dir)
+            .await
+            .map_err(|_| "failed tagging")
+            .unwrap();
+        assert_eq!(
+            fs.store
+                .get_tag_views(&[ino], "#")
+                .await
+                .map_err(|_| "failed getting views")
+                .unwrap(),
+            vec![(ino, dir)]
+        );
+        assert_eq!(
+            fs.store
+                .get_names_and_tags(&[ino])
+                .await
+                .map_err(|_| "failed getting names")
+                .unwrap(),
+            vec![(ino, "a.pdf".to_string(), Some(tid))]
+        );
+        // prefixes are matched case-sensitively, and without wildcards
+        for (prefix, inos) in [
+            ("#", vec![ino]),
+            ("#W", vec![ino, dir]),
+            ("_", vec![ino, dir]),
+        ] {
+            let mut tagged = fs
+                .store
+                .get_tagged(&[tid], prefix)
+                .await
+                .map_err(|_| "failed getting tagged files")
+                .unwrap();
+            tagged.sort();
+            assert_eq!(tagged, inos);
+        }
+        assert_eq!(
+            fs.store
+                .count_tag_files(tid, "#")
+                .await
+                .map_err(|_| "failed counting")
+                .unwrap(),
+            1
+        );
+        // without tags, only the directory's contents are listed
+        assert!(
+            fs.store
+                .list_entries(1, &[], "#", 0, 10)
+                .await
+                .map_err(|_| "failed listing")
+                .unwrap()
+                .is_empty()
+        );
+        let entries = fs
+            .store
+            .filter_entries(
+                &FileFilter::Glob {
+                    base_tags: Some(&[tid]),
+                    pattern: "*.pdf",
+                },
+                None,
+                0,
+                10,
+            )
+            .await
+            .map_err(|_| "failed filtering")
+            .unwrap();
+        assert_eq!(entries.len(), 1);
+        // listing resumes after the last listed inode
+        assert!(
+            fs.store
+                .filter_entries(
+                    &FileFilter::Glob {
+                        base_tags: Some(&[tid]),
+                        pattern: "*.pdf",
+                    },
+                    None,
+                    ino,
+                    10,
+                )
+                .await
+                .map_err(|_| "failed filtering")
+                .unwrap()
+                .is_empty()
+        );
+        // the file is listed under the year and month it was modified in, and as recent
+        let years = fs
+            .store
+            .list_time_parts(Some(&[tid]), TimeField::Mtime, None, 0, 10)
+            .await
+            .map_err(|_| "failed listing years")
+            .unwrap();
+        assert_eq!(years.len(), 1);
+        let months = fs
+            .store
+            .list_time_parts(Some(&[tid]), TimeField::Mtime, Some(years[0]), 0, 10)
+            .await
+            .map_err(|_| "failed listing months")
+            .unwrap();
+        assert_eq!(months.len(), 1);
+        for filter in [
+            FileFilter::Month {
+                base_tags: Some(&[tid]),
+                field: TimeField::Mtime,
+                year: years[0],
+                month: months[0],
+            },
+            FileFilter::Recent {
+                base_tags: None,
+                window: RecentWindow::Today,
+            },
+        ] {
+            let entries = fs
+                .store
+                .filter_entries(&filter, None, 0, 10)
+                .await
+                .map_err(|_| "failed filtering")
+                .unwrap();
+            assert_eq!(entries.len(), 1);
+        }
+
+        // chunks are shared and collected
+        let page = vec![7u8; fs.settings.chunk_size as usize];
+        fs.write_data(ino, 0, &page)
+            .await
+            .map_err(|_| "failed writing")
+            .unwrap();
+        fs.store
+            .copy_pages(ino, 0, ino, 1, 1)
+            .await
+            .map_err(|_| "failed copying")
+            .unwrap();
+        let chunks: i64 = query_scalar("SELECT COUNT(*) FROM chunks WHERE refs = 2")
+            .fetch_one(&fs.store.pool)
+            .await
+            .unwrap();
+        assert_eq!(chunks, 1);
+        assert_eq!(
+            fs.read_data(ino, 0, page.len() as u64)
+                .await
+                .map_err(|_| "failed reading")
+                .unwrap(),
+            page
+        );
+        fs.store
+            .delete_inode(ino)
+            .await
+            .map_err(|_| "failed deleting")
+            .unwrap();
+        fs.gc_chunks()
+            .await
+            .map_err(|_| "failed collecting")
+            .unwrap();
+        let chunks: i64 = query_scalar("SELECT COUNT(*) FROM chunks")
+            .fetch_one(&fs.store.pool)
+            .await
+            .unwrap();
+        assert_eq!(chunks, 0);
+
+        fs.store.close().await;
     }
 }
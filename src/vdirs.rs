@@ -0,0 +1,550 @@
+use crate::{
+    PTFS,
+    db_helpers::types::DBError,
+    store::{Entry, FileFilter, Store},
+};
+use fuser::{FileAttr, FileType};
+use std::{collections::HashMap, sync::Mutex};
+
+/// First inode handed out to virtual directories, above any inode a store can allocate
+pub const VIRTUAL_INO_START: u64 = 1 << 63;
+
+/// Name of the root's virtual directory listing all tags
+pub const TAGS_NAME: &str = ".tags";
+/// Name of the virtual directory listing recently modified files
+pub const RECENT_NAME: &str = ".recent";
+/// Name of the virtual directory listing files by modification year and month
+pub const BY_MTIME_NAME: &str = ".by-mtime";
+/// Name of the virtual directory listing files by creation year and month
+pub const BY_CRTIME_NAME: &str = ".by-crtime";
+/// Name of the virtual directory resolving glob patterns into files with matching names
+pub const SEARCH_NAME: &str = ".search";
+
+/// Read-only directory that isn't stored in the database, its contents are queried on demand
+///
+/// Directories with a `base` are found under the root or a tag directory, and only list files
+/// that are listed by the tag directory (or all files for the root).
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum VirtualDir {
+    /// Files with no tags and no parent directory
+    Untagged,
+    /// Every tag, as a [`VirtualDir::Tag`]
+    Tags,
+    /// Files associated with a tag
+    Tag(u64),
+    /// Every [`RecentWindow`], as a [`VirtualDir::RecentWindow`]
+    Recent { base: u64 },
+    /// Files modified within a window of time
+    RecentWindow { base: u64, window: RecentWindow },
+    /// Years, then months of the year, then files with `field` within the year and month
+    ByTime {
+        base: u64,
+        field: TimeField,
+        year: Option<u32>,
+        month: Option<u32>,
+    },
+    /// Nothing, then files with names matching the glob `pattern`
+    Search { base: u64, pattern: Option<String> },
+}
+
+/// Window of time, ending now
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RecentWindow {
+    Today,
+    Week,
+    Month,
+}
+
+impl RecentWindow {
+    const ALL: [RecentWindow; 3] = [RecentWindow::Today, RecentWindow::Week, RecentWindow::Month];
+
+    fn name(&self) -> &'static str {
+        match self {
+            RecentWindow::Today => "today",
+            RecentWindow::Week => "week",
+            RecentWindow::Month => "month",
+        }
+    }
+}
+
+/// `file_attrs` time column
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TimeField {
+    Mtime,
+    Crtime,
+}
+
+impl TimeField {
+    pub(crate) fn column(&self) -> &'static str {
+        match self {
+            TimeField::Mtime => "mtime",
+            TimeField::Crtime => "crtime",
+        }
+    }
+}
+
+/// Entry of a directory listing
+#[derive(Debug)]
+pub(crate) struct DirEntry {
+    /// Key the listing can resume after, the inode of files
+    pub key: u64,
+    pub ino: u64,
+    pub kind: FileType,
+    pub name: String,
+    /// Attributes of files, `None` for virtual directories, see [`PTFS::get_vdir_attr`]
+    pub attr: Option<FileAttr>,
+}
+
+/// Inode allocator for virtual directories
+///
+/// Inodes are reclaimed once the kernel forgets every lookup of them, as any name can be looked up
+/// in `.search` or `.by-mtime`, and reused for the next virtual directories. Inodes the kernel never
+/// looked up, e.g. of entries only listed by readdir, are reclaimed by [`VirtualDirs::reclaim`].
+#[derive(Debug, Default)]
+pub struct VirtualDirs(Mutex<VirtualDirsInner>);
+
+#[derive(Debug, Default)]
+struct VirtualDirsInner {
+    inos: HashMap<VirtualDir, u64>,
+    /// Virtual directory of each inode from [`VIRTUAL_INO_START`] on, `None` once reclaimed
+    dirs: Vec<Option<VirtualDir>>,
+    /// Lookups of allocated inodes the kernel didn't forget yet
+    lookups: HashMap<u64, u64>,
+    /// Inodes allocated since the last [`VirtualDirs::reclaim`]
+    allocated: Vec<u64>,
+    /// Reclaimed inodes
+    free: Vec<u64>,
+}
+
+impl VirtualDirs {
+    /// Get the inode of `vdir`, allocating one if it doesn't have one yet
+    pub fn ino(&self, vdir: VirtualDir) -> u64 {
+        let mut inner = self.0.lock().unwrap();
+        if let Some(ino) = inner.inos.get(&vdir) {
+            return *ino;
+        }
+        let ino = match inner.free.pop() {
+            Some(ino) => {
+                inner.dirs[(ino - VIRTUAL_INO_START) as usize] = Some(vdir.clone());
+                ino
+            }
+            None => {
+                inner.dirs.push(Some(vdir.clone()));
+                VIRTUAL_INO_START + inner.dirs.len() as u64 - 1
+            }
+        };
+        inner.inos.insert(vdir, ino);
+        inner.lookups.insert(ino, 0);
+        inner.allocated.push(ino);
+        ino
+    }
+
+    /// Get the inode of `vdir` if it has one
+    pub fn find(&self, vdir: &VirtualDir) -> Option<u64> {
+        self.0.lock().unwrap().inos.get(vdir).copied()
+    }
+
+    /// Get the virtual directories that have inodes, along with them
+    pub fn all(&self) -> Vec<(u64, VirtualDir)> {
+        (VIRTUAL_INO_START..)
+            .zip(self.0.lock().unwrap().dirs.iter().cloned())
+            .filter_map(|(ino, vdir)| Some((ino, vdir?)))
+            .collect()
+    }
+
+    pub fn get(&self, ino: u64) -> Option<VirtualDir> {
+        let index = usize::try_from(ino.checked_sub(VIRTUAL_INO_START)?).ok()?;
+        self.0.lock().unwrap().dirs.get(index).cloned().flatten()
+    }
+
+    /// Count a lookup of `ino` replied to the kernel, if it's a virtual directory
+    pub fn looked_up(&self, ino: u64) {
+        if let Some(lookups) = self.0.lock().unwrap().lookups.get_mut(&ino) {
+            *lookups += 1;
+        }
+    }
+
+    /// Drop `nlookup` lookups of `ino` the kernel forgot, reclaiming its inode if it was the last
+    pub fn forget(&self, ino: u64, nlookup: u64) {
+        let mut inner = self.0.lock().unwrap();
+        let Some(lookups) = inner.lookups.get_mut(&ino) else {
+            return;
+        };
+        *lookups = lookups.saturating_sub(nlookup);
+        if *lookups == 0 {
+            inner.release(ino);
+        }
+    }
+
+    /// Reclaim the inodes allocated since the last call that the kernel didn't look up
+    ///
+    /// Called once a request is replied to, as inodes are only allocated to reply with them.
+    pub fn reclaim(&self) {
+        let mut inner = self.0.lock().unwrap();
+        for ino in std::mem::take(&mut inner.allocated) {
+            if inner.lookups.get(&ino) == Some(&0) {
+                inner.release(ino);
+            }
+        }
+    }
+}
+
+impl VirtualDirsInner {
+    fn release(&mut self, ino: u64) {
+        self.lookups.remove(&ino);
+        if let Some(vdir) = self.dirs[(ino - VIRTUAL_INO_START) as usize].take() {
+            self.inos.remove(&vdir);
+            self.free.push(ino);
+        }
+    }
+}
+
+impl<S: Store> PTFS<S> {
+    /// Get the inode of the virtual directory `name` under `parent`, if it names one
+    pub(crate) async fn lookup_vdir(
+        &self,
+        parent: u64,
+        name: &str,
+    ) -> Result<Option<u64>, DBError> {
+        let vdir = self.vdir_named(parent, name).await?;
+        Ok(vdir.map(|vdir| self.vdirs.ino(vdir)))
+    }
+
+    /// Get the virtual directory `name` names under `parent`, if any, which is found before real
+    /// entries so they can't be named like it
+    pub(crate) async fn vdir_named(
+        &self,
+        parent: u64,
+        name: &str,
+    ) -> Result<Option<VirtualDir>, DBError> {
+        Ok(Some(match name {
+            _ if parent == 1 && name == self.untagged_name => VirtualDir::Untagged,
+            TAGS_NAME if parent == 1 => VirtualDir::Tags,
+            // names of encrypted databases can't be matched against globs
+            SEARCH_NAME if self.cipher.is_some() => return Ok(None),
+            RECENT_NAME | BY_MTIME_NAME | BY_CRTIME_NAME | SEARCH_NAME => {
+                if parent != 1 && !self.is_prefixed(&self.get_ino_name(parent).await?) {
+                    return Ok(None);
+                }
+                match name {
+                    RECENT_NAME => VirtualDir::Recent { base: parent },
+                    SEARCH_NAME => VirtualDir::Search {
+                        base: parent,
+                        pattern: None,
+                    },
+                    _ => VirtualDir::ByTime {
+                        base: parent,
+                        field: match name {
+                            BY_MTIME_NAME => TimeField::Mtime,
+                            _ => TimeField::Crtime,
+                        },
+                        year: None,
+                        month: None,
+                    },
+                }
+            }
+            _ => return Ok(None),
+        }))
+    }
+
+    /// Get attributes of a virtual directory, which are owned by the root's owner
+    ///
+    /// [`VirtualDir::Tag`]s report the amount of files associated with the tag as their size and
+    /// link count.
+    pub(crate) async fn get_vdir_attr(&self, ino: u64) -> Result<FileAttr, DBError> {
+        let mut attr = self.store.get_attr(1).await?;
+        attr.ino = ino;
+        attr.kind = FileType::Directory;
+        attr.perm = 0o555;
+        attr.nlink = 1;
+        attr.size = 0;
+
+        if let Some(VirtualDir::Tag(tid)) = self.vdirs.get(ino) {
+            let files: u32 = self
+                .store
+                .count_tag_files(tid, &self.tag_prefix)
+                .await?
+                .try_into()?;
+            attr.nlink = files;
+            attr.size = files.into();
+        }
+
+        Ok(attr)
+    }
+
+    /// Get the tags of the files listed by `base`, `None` for the root which lists all files
+    async fn base_tags(&self, base: u64) -> Result<Option<Vec<u64>>, DBError> {
+        Ok(match base {
+            1 => None,
+            _ => Some(self.store.get_tags(base).await?),
+        })
+    }
+
+    /// Decode a stored file listing into a [`DirEntry`], keyed by inode
+    pub(crate) fn dir_entry(&self, entry: Entry) -> Result<DirEntry, DBError> {
+        Ok(DirEntry {
+            key: entry.attr.ino,
+            ino: entry.attr.ino,
+            kind: entry.attr.kind,
+            name: self.dec_name(entry.name)?,
+            attr: Some(entry.attr),
+        })
+    }
+
+    /// Get up to `limit` files in `vdir` matching `name` if it's set, with inodes above `after`
+    ///
+    /// Virtual directories that only contain virtual directories have no files.
+    async fn get_vdir_files(
+        &self,
+        vdir: &VirtualDir,
+        name: Option<&str>,
+        after: u64,
+        limit: u64,
+    ) -> Result<Vec<Entry>, DBError> {
+        let base_tags = match vdir {
+            VirtualDir::RecentWindow { base, .. }
+            | VirtualDir::ByTime { base, .. }
+            | VirtualDir::Search { base, .. } => self.base_tags(*base).await?,
+            _ => None,
+        };
+        let base_tags = base_tags.as_deref();
+        let filter = match vdir {
+            VirtualDir::Untagged => FileFilter::Untagged,
+            VirtualDir::Tag(tid) => FileFilter::Tag {
+                tid: *tid,
+                tag_prefix: &self.tag_prefix,
+            },
+            VirtualDir::RecentWindow { window, .. } => FileFilter::Recent {
+                base_tags,
+                window: *window,
+            },
+            VirtualDir::ByTime {
+                field,
+                year: Some(year),
+                month: Some(month),
+                ..
+            } => FileFilter::Month {
+                base_tags,
+                field: *field,
+                year: *year,
+                month: *month,
+            },
+            VirtualDir::Search {
+                pattern: Some(pattern),
+                ..
+            } => FileFilter::Glob { base_tags, pattern },
+            VirtualDir::Tags
+            | VirtualDir::Recent { .. }
+            | VirtualDir::ByTime { .. }
+            | VirtualDir::Search { .. } => return Ok(vec![]),
+        };
+        let name = name.map(|name| self.enc_name(name));
+        self.store
+            .filter_entries(&filter, name.as_deref(), after, limit)
+            .await
+    }
+
+    /// Get attributes of the entry called `name` in `vdir`
+    pub(crate) async fn lookup_in_vdir(
+        &self,
+        vdir: &VirtualDir,
+        name: &str,
+    ) -> Result<FileAttr, DBError> {
+        let not_found = || Err(sqlx::Error::RowNotFound.into());
+        let child = match vdir {
+            VirtualDir::Tags => Some(VirtualDir::Tag(self.get_tid(name).await?)),
+            VirtualDir::Recent { base } => {
+                let Some(window) = RecentWindow::ALL.into_iter().find(|w| w.name() == name) else {
+                    return not_found();
+                };
+                Some(VirtualDir::RecentWindow {
+                    base: *base,
+                    window,
+                })
+            }
+            VirtualDir::ByTime {
+                base,
+                field,
+                year,
+                month: None,
+            } => {
+                let Ok(n) = name.parse::<u32>() else {
+                    return not_found();
+                };
+                Some(match year {
+                    None => VirtualDir::ByTime {
+                        base: *base,
+                        field: *field,
+                        year: Some(n),
+                        month: None,
+                    },
+                    Some(_) if !(1..=12).contains(&n) => return not_found(),
+                    Some(_) => VirtualDir::ByTime {
+                        base: *base,
+                        field: *field,
+                        year: *year,
+                        month: Some(n),
+                    },
+                })
+            }
+            VirtualDir::Search {
+                base,
+                pattern: None,
+            } => Some(VirtualDir::Search {
+                base: *base,
+                pattern: Some(name.to_string()),
+            }),
+            _ => None,
+        };
+        if let Some(child) = child {
+            return self.get_vdir_attr(self.vdirs.ino(child)).await;
+        }
+
+        match self
+            .get_vdir_files(vdir, Some(name), 0, 1)
+            .await?
+            .into_iter()
+            .next()
+        {
+            Some(entry) => Ok(entry.attr),
+            None => not_found(),
+        }
+    }
+
+    /// Get the inode of the directory `vdir` is listed in
+    pub(crate) fn vdir_parent(&self, vdir: &VirtualDir) -> u64 {
+        let parent = match vdir {
+            VirtualDir::Untagged | VirtualDir::Tags => return 1,
+            VirtualDir::Tag(_) => VirtualDir::Tags,
+            VirtualDir::Recent { base }
+            | VirtualDir::ByTime {
+                base, year: None, ..
+            }
+            | VirtualDir::Search {
+                base,
+                pattern: None,
+            } => return *base,
+            VirtualDir::RecentWindow { base, .. } => VirtualDir::Recent { base: *base },
+            VirtualDir::ByTime {
+                base,
+                field,
+                year: Some(_),
+                month: None,
+            } => VirtualDir::ByTime {
+                base: *base,
+                field: *field,
+                year: None,
+                month: None,
+            },
+            VirtualDir::ByTime {
+                base, field, year, ..
+            } => VirtualDir::ByTime {
+                base: *base,
+                field: *field,
+                year: *year,
+                month: None,
+            },
+            VirtualDir::Search { base, .. } => VirtualDir::Search {
+                base: *base,
+                pattern: None,
+            },
+        };
+        self.vdirs.ino(parent)
+    }
+
+    /// Get up to `limit` entries in `vdir` with keys above `after`
+    ///
+    /// Keys are the tag, year or month of virtual directories and the inode of files, so that
+    /// listing can resume after the last listed entry.
+    pub(crate) async fn get_vdir_entries(
+        &self,
+        vdir: &VirtualDir,
+        after: u64,
+        limit: u64,
+    ) -> Result<Vec<DirEntry>, DBError> {
+        let children: Option<Vec<(u64, VirtualDir, String)>> = match vdir {
+            VirtualDir::Tags => {
+                let tags = self.store.list_tags(after, limit).await?;
+                Some(
+                    tags.into_iter()
+                        .map(|(tid, name)| Ok((tid, VirtualDir::Tag(tid), self.dec_name(name)?)))
+                        .collect::<Result<_, DBError>>()?,
+                )
+            }
+            VirtualDir::Recent { base } => Some(
+                (1..)
+                    .zip(RecentWindow::ALL)
+                    .filter(|(key, _)| *key > after)
+                    .take(limit.try_into()?)
+                    .map(|(key, window)| {
+                        (
+                            key,
+                            VirtualDir::RecentWindow {
+                                base: *base,
+                                window,
+                            },
+                            window.name().to_string(),
+                        )
+                    })
+                    .collect(),
+            ),
+            VirtualDir::ByTime {
+                base,
+                field,
+                year,
+                month: None,
+            } => {
+                let base_tags = self.base_tags(*base).await?;
+                let ns = self
+                    .store
+                    .list_time_parts(base_tags.as_deref(), *field, *year, after, limit)
+                    .await?;
+                Some(
+                    ns.into_iter()
+                        .map(|n| match year {
+                            None => (
+                                n.into(),
+                                VirtualDir::ByTime {
+                                    base: *base,
+                                    field: *field,
+                                    year: Some(n),
+                                    month: None,
+                                },
+                                format!("{n:04}"),
+                            ),
+                            Some(_) => (
+                                n.into(),
+                                VirtualDir::ByTime {
+                                    base: *base,
+                                    field: *field,
+                                    year: *year,
+                                    month: Some(n),
+                                },
+                                format!("{n:02}"),
+                            ),
+                        })
+                        .collect(),
+                )
+            }
+            _ => None,
+        };
+        if let Some(children) = children {
+            return Ok(children
+                .into_iter()
+                .map(|(key, child, name)| DirEntry {
+                    key,
+                    ino: self.vdirs.ino(child),
+                    kind: FileType::Directory,
+                    name,
+                    attr: None,
+                })
+                .collect());
+        }
+
+        self.get_vdir_files(vdir, None, after, limit)
+            .await?
+            .into_iter()
+            .map(|entry| self.dir_entry(entry))
+            .collect()
+    }
+}
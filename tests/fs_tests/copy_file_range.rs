@@ -0,0 +1,68 @@
+load_prelude!();
+
+#[test]
+fn aligned_copy() {
+    let Test { bg_sess, rt, pool, mp, .. } = Test::new();
+
+    let bytes: Vec<u8> = rand::random_iter().take(PAGE_SIZE * 2 + 512).collect();
+    create_file(path!(mp; "src"))
+        .unwrap()
+        .write_all(&bytes)
+        .unwrap();
+
+    copy(
+        &mut File::open(path!(mp; "src")).unwrap(),
+        &mut create_file(path!(mp; "dst")).unwrap(),
+    )
+    .unwrap();
+
+    let db_bytes: Vec<u8> = rt
+        .block_on(read_file_query!().bind(3).fetch_one(&pool))
+        .unwrap();
+    assert!(bytes == db_bytes);
+    assert_eq!(
+        metadata(path!(mp; "dst")).unwrap().len(),
+        bytes.len() as u64
+    );
+
+    // pages are shared
+    let refs: Vec<i64> = rt
+        .block_on(query_scalar("SELECT refs FROM chunks").fetch_all(&pool))
+        .unwrap();
+    assert_eq!(refs, vec![2, 2, 2]);
+
+    Test::cleanup(bg_sess, mp);
+}
+
+#[test]
+fn unaligned_copy() {
+    let Test { bg_sess, rt, pool, mp, .. } = Test::new();
+
+    let bytes: Vec<u8> = rand::random_iter().take(PAGE_SIZE * 2).collect();
+    let src = create_file(path!(mp; "src")).unwrap();
+    src.write_all_at(&bytes, 0).unwrap();
+    let dst = create_file(path!(mp; "dst")).unwrap();
+
+    let mut off_in: i64 = 100;
+    let mut off_out: i64 = 1000;
+    let copied = unsafe {
+        libc::copy_file_range(
+            src.as_raw_fd(),
+            &mut off_in,
+            dst.as_raw_fd(),
+            &mut off_out,
+            PAGE_SIZE,
+            0,
+        )
+    };
+    assert_eq!(copied, PAGE_SIZE as isize);
+
+    let db_bytes: Vec<u8> = rt
+        .block_on(read_file_query!().bind(3).fetch_one(&pool))
+        .unwrap();
+    let mut expected = vec![0u8; 1000];
+    expected.extend_from_slice(&bytes[100..100 + PAGE_SIZE]);
+    assert!(expected == db_bytes);
+
+    Test::cleanup(bg_sess, mp);
+}
@@ -0,0 +1,94 @@
+load_prelude!();
+
+fn fallocate(file: &File, mode: i32, offset: usize, len: usize) -> i32 {
+    unsafe {
+        libc::fallocate(
+            file.as_raw_fd(),
+            mode,
+            offset.try_into().unwrap(),
+            len.try_into().unwrap(),
+        )
+    }
+}
+
+#[test]
+fn allocate() {
+    let Test { bg_sess, rt, pool, mp, .. } = Test::new();
+
+    let file = create_file(path!(mp; "file")).unwrap();
+    assert_eq!(fallocate(&file, 0, 0, PAGE_SIZE * 3), 0);
+    assert_eq!(file.metadata().unwrap().len(), (PAGE_SIZE * 3) as u64);
+
+    assert_eq!(
+        fallocate(&file, libc::FALLOC_FL_KEEP_SIZE, 0, PAGE_SIZE * 8),
+        0
+    );
+    assert_eq!(file.metadata().unwrap().len(), (PAGE_SIZE * 3) as u64);
+
+    // allocated ranges are holes
+    let pages: u64 = rt
+        .block_on(query_scalar("SELECT COUNT(*) FROM file_contents WHERE ino = 2").fetch_one(&pool))
+        .unwrap();
+    assert_eq!(pages, 0);
+
+    assert_eq!(
+        fallocate(&file, libc::FALLOC_FL_COLLAPSE_RANGE, 0, PAGE_SIZE),
+        -1
+    );
+    assert_eq!(
+        std::io::Error::last_os_error().raw_os_error(),
+        Some(libc::EOPNOTSUPP)
+    );
+
+    Test::cleanup(bg_sess, mp);
+}
+
+#[test]
+fn punch_hole() {
+    let Test { bg_sess, rt, pool, mp, .. } = Test::new();
+
+    let mut bytes: Vec<u8> = rand::random_iter().take(PAGE_SIZE * 3).collect();
+    let mut file = create_file(path!(mp; "file")).unwrap();
+    file.write_all(&bytes).unwrap();
+
+    let (offset, len) = (PAGE_SIZE / 2, PAGE_SIZE * 2);
+    assert_eq!(
+        fallocate(
+            &file,
+            libc::FALLOC_FL_PUNCH_HOLE | libc::FALLOC_FL_KEEP_SIZE,
+            offset,
+            len
+        ),
+        0
+    );
+    bytes[offset..offset + len].fill(0);
+
+    // the middle page is dropped
+    let pages: Vec<i64> = rt
+        .block_on(query_scalar("SELECT page FROM file_contents WHERE ino = 2").fetch_all(&pool))
+        .unwrap();
+    assert_eq!(pages, vec![0, 2]);
+    assert!(read(path!(mp; "file")).unwrap() == bytes);
+
+    Test::cleanup(bg_sess, mp);
+}
+
+#[test]
+fn zero_range() {
+    let Test { bg_sess, rt: _rt, mp, .. } = Test::new();
+
+    let mut bytes: Vec<u8> = rand::random_iter().take(PAGE_SIZE).collect();
+    let mut file = create_file(path!(mp; "file")).unwrap();
+    file.write_all(&bytes).unwrap();
+
+    // zeroing past the end grows the file
+    assert_eq!(
+        fallocate(&file, libc::FALLOC_FL_ZERO_RANGE, 512, PAGE_SIZE * 2),
+        0
+    );
+    bytes.truncate(512);
+    bytes.resize(PAGE_SIZE * 2 + 512, 0);
+    assert!(read(path!(mp; "file")).unwrap() == bytes);
+
+    Test::cleanup(bg_sess, mp);
+}
@@ -0,0 +1,52 @@
+load_prelude!();
+
+#[test]
+fn lookup_negative() {
+    let Test { bg_sess, rt: _rt, mp, invalidator, .. } = Test::with_ttl(LONG_TTL);
+
+    // names that weren't found are created in place
+    let file_path = path!(mp; "file");
+    assert!(!file_path.exists());
+    create_file(&file_path).unwrap();
+    assert!(file_path.exists());
+
+    // and appear in other directories once their tags are created
+    let tag_path = path!(mp; ".tags", "#new");
+    assert!(!tag_path.exists());
+    create_dir(path!(mp; "#new")).unwrap();
+    invalidator.flush();
+    assert!(tag_path.exists());
+
+    Test::cleanup(bg_sess, mp);
+}
+
+// files created or removed in a tag directory, seen from others
+#[test]
+fn lookup_tag_views() {
+    let Test { bg_sess, rt: _rt, mp, invalidator, .. } = Test::with_ttl(LONG_TTL);
+
+    let a_path = path!(mp; "#a");
+    create_dir(&a_path).unwrap();
+    let ba_path = path!(mp; "#b", "#a");
+    create_dir(path!(mp; "#b")).unwrap();
+    create_dir(&ba_path).unwrap();
+
+    let views = [
+        path!(mp; ".tags", "#a", "file"),
+        path!(mp; "#b", "file"),
+        path!(mp; ".recent", "today", "file"),
+    ];
+    assert!(views.iter().all(|path| !path.exists()));
+
+    create_file(path!(&ba_path; "file")).unwrap();
+    invalidator.flush();
+    assert!(views.iter().all(|path| path.exists()));
+    assert!(path!(&a_path; "file").exists());
+
+    remove_file(path!(&a_path; "file")).unwrap();
+    invalidator.flush();
+    assert!(views.iter().all(|path| !path.exists()));
+    assert!(!path!(&ba_path; "file").exists());
+
+    Test::cleanup(bg_sess, mp);
+}
@@ -0,0 +1,30 @@
+load_prelude!();
+
+#[test]
+fn seek_data_hole() {
+    let Test { bg_sess, rt: _rt, mp, .. } = Test::new();
+
+    // |-----|#####|#####|-----|--
+    let size = PAGE_SIZE * 4 + 512;
+    let bytes: Vec<u8> = rand::random_iter().take(PAGE_SIZE * 2).collect();
+    let file = create_file(path!(mp; "file")).unwrap();
+    file.set_len(size.try_into().unwrap()).unwrap();
+    file.write_all_at(&bytes, PAGE_SIZE.try_into().unwrap())
+        .unwrap();
+
+    let seek = |offset: usize, whence| unsafe {
+        libc::lseek(file.as_raw_fd(), offset.try_into().unwrap(), whence)
+    };
+    let page = PAGE_SIZE as i64;
+    assert_eq!(seek(0, libc::SEEK_DATA), page);
+    assert_eq!(seek(PAGE_SIZE + 10, libc::SEEK_DATA), page + 10);
+    assert_eq!(seek(0, libc::SEEK_HOLE), 0);
+    assert_eq!(seek(PAGE_SIZE, libc::SEEK_HOLE), page * 3);
+    // no data after the last stored page
+    assert_eq!(seek(PAGE_SIZE * 3, libc::SEEK_DATA), -1);
+    // the end of the file is a hole
+    assert_eq!(seek(PAGE_SIZE * 4, libc::SEEK_HOLE), page * 4);
+    assert_eq!(seek(size, libc::SEEK_HOLE), -1);
+
+    Test::cleanup(bg_sess, mp);
+}
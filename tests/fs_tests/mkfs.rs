@@ -0,0 +1,120 @@
+load_prelude!();
+
+// names and contents of an encrypted database only reach it encrypted
+#[test]
+fn encrypted() {
+    let Test {
+        rt, pool, bg_sess, mp, invalidator, ..
+    } = Test::mount(Setup {
+        mkfs: Some(&[]),
+        key: Some(b"key"),
+        ..Default::default()
+    });
+
+    let bytes = "plaintext contents ".repeat(PAGE_SIZE / 8).into_bytes();
+    create_dir(path!(mp; "#secret-tag")).unwrap();
+    create_file(path!(mp; "secret-name"))
+        .unwrap()
+        .write_all(&bytes)
+        .unwrap();
+    assert_eq!(metadata(path!(mp; "secret-name")).unwrap().len(), bytes.len() as u64);
+
+    rename(path!(mp; "secret-name"), path!(mp; "#secret-tag", "secret-renamed")).unwrap();
+    assert!(!path!(mp; "secret-name").exists());
+    invalidator.flush();
+
+    let tags: Vec<String> = read_dir(path!(mp; ".tags"))
+        .unwrap()
+        .map(|e| e.unwrap().file_name().into_string().unwrap())
+        .collect();
+    assert_eq!(tags, vec!["#secret-tag"]);
+    let tagged_path = path!(mp; ".tags", "#secret-tag", "secret-renamed");
+    assert_eq!(read(&tagged_path).unwrap(), bytes);
+
+    // pages written through a view are read back from the file
+    File::options()
+        .write(true)
+        .open(&tagged_path)
+        .unwrap()
+        .write_all_at(b"secret bytes", PAGE_SIZE as u64)
+        .unwrap();
+    let read_back = read(path!(mp; "#secret-tag", "secret-renamed")).unwrap();
+    assert_eq!(&read_back[PAGE_SIZE..PAGE_SIZE + 12], b"secret bytes");
+
+    // tags keep their prefix to be told apart from other names
+    let tag: String = rt
+        .block_on(query_scalar("SELECT name FROM tags").fetch_one(&pool))
+        .unwrap();
+    assert!(tag.starts_with('#') && tag != "#secret-tag");
+
+    let dir = mp.parent().unwrap().to_path_buf();
+    for file in ["db.sqlite", "db.sqlite-wal"] {
+        let Ok(db_bytes) = read(path!(dir; file)) else {
+            continue;
+        };
+        for needle in ["secret", "plaintext contents"] {
+            assert!(
+                !db_bytes
+                    .windows(needle.len())
+                    .any(|w| w == needle.as_bytes()),
+                "{needle} found in {file}"
+            );
+        }
+    }
+
+    Test::cleanup(bg_sess, mp);
+}
+
+/// Write compressible and incompressible files to a database created with `mkfs`, checking that
+/// only the first are stored with `codec` while both keep their logical size and blocks
+fn compressed(mkfs: &'static [&'static str], codec: u8) {
+    let Test { bg_sess, rt, pool, mp, .. } = Test::mount(Setup {
+        mkfs: Some(mkfs),
+        ..Default::default()
+    });
+
+    let text: Vec<u8> = b"compressible ".iter().copied().cycle().take(PAGE_SIZE * 2).collect();
+    let random: Vec<u8> = rand::random_iter().take(PAGE_SIZE * 2).collect();
+    for (name, bytes) in [("text", &text), ("random", &random)] {
+        create_file(path!(mp; name))
+            .unwrap()
+            .write_all(bytes)
+            .unwrap();
+        assert_eq!(read(path!(mp; name)).unwrap(), *bytes);
+
+        let meta = metadata(path!(mp; name)).unwrap();
+        assert_eq!(meta.len(), bytes.len() as u64);
+        assert_eq!(meta.blocks(), (bytes.len() / 512) as u64);
+    }
+
+    let chunks = |ino: u64| {
+        rt.block_on(
+            query_as(
+                "SELECT codec, LENGTH(chunks.bytes), len FROM file_contents INNER JOIN chunks ON \
+                 chunks.hash = file_contents.hash WHERE ino = ? ORDER BY page",
+            )
+            .bind(ino as i64)
+            .fetch_all(&pool),
+        )
+        .unwrap()
+    };
+    let text_chunks: Vec<(u8, u64, u64)> = chunks(2);
+    assert_eq!(text_chunks.len(), 2);
+    assert!(text_chunks.iter().all(|&(c, stored, len)| c == codec && stored < len));
+    // incompressible pages are stored raw
+    let random_chunks: Vec<(u8, u64, u64)> = chunks(3);
+    assert_eq!(random_chunks.len(), 2);
+    assert!(random_chunks.iter().all(|&(c, stored, len)| c == 0 && stored == len));
+
+    Test::cleanup(bg_sess, mp);
+}
+
+#[test]
+fn compressed_zstd() {
+    compressed(&["--compression", "zstd"], 1);
+}
+
+#[test]
+fn compressed_lz4() {
+    compressed(&["--compression", "lz4"], 2);
+}
@@ -3,9 +3,10 @@ mod prelude;
 reg_method!(rename);
 reg_method!(write);
 reg_method!(setattr);
-
-pub fn test_fs() {
-    test_rename();
-    test_write();
-    test_setattr();
-}
+reg_method!(rmdir);
+reg_method!(readdir);
+reg_method!(copy_file_range);
+reg_method!(lseek);
+reg_method!(fallocate);
+reg_method!(lookup);
+reg_method!(mkfs);
@@ -10,50 +10,57 @@ macro_rules! path {
         }};
     }
 
-macro_rules! init_pool {
-    () => {
-        SqlitePool::connect_with(
-            SqliteConnectOptions::from_str(format!("sqlite:{}", DB_PATH).as_str())
-                .unwrap()
-                .locking_mode(sqlx::sqlite::SqliteLockingMode::Normal)
-                .journal_mode(sqlx::sqlite::SqliteJournalMode::Wal),
-        )
-    };
-}
-
 macro_rules! init_sess {
-    ($rt:expr, $pool:expr) => {
-        spawn_mount2(
-            PTFS {
-                runtime_handle: $rt.handle().clone(),
-                tag_prefix: "#".to_string(),
-                pool: $pool.clone(),
-            },
-            MP_PATH,
-            &[],
-        )
-        .unwrap()
-    };
+    (
+        $rt:expr,
+        $store:expr,
+        $blob_dir:expr,
+        $ttl:expr,
+        $invalidator:expr,
+        $cipher:expr,
+        $mp:expr
+    ) => {{
+        let mut fs = PTFS {
+            runtime_handle: $rt.handle().clone(),
+            tag_prefix: "#".to_string(),
+            untagged_name: UNTAGGED_NAME.to_string(),
+            vdirs: Default::default(),
+            settings: Default::default(),
+            cipher: None,
+            blob_dir: $blob_dir,
+            ttl: $ttl,
+            invalidator: $invalidator.clone(),
+            store: $store,
+        };
+        if let Some(cipher) = $cipher {
+            fs.settings = $rt
+                .block_on(Settings::load(&fs.store))
+                .map_err(|_| "failed loading settings")
+                .unwrap();
+            fs.unlock(cipher).map_err(|_| "failed unlocking").unwrap();
+        }
+        let bg_sess = spawn_mount2(fs, $mp, &[]).unwrap();
+        $invalidator.start(bg_sess.notifier());
+        bg_sess
+    }};
 }
 
 macro_rules! init_paths {
-    () => {
-        create_dir(MP_PATH).unwrap();
-        File::create_new(DB_PATH).unwrap();
+    ($mp:expr) => {
+        create_dir_all($mp).unwrap();
     };
 }
 
 macro_rules! cleanup_paths {
-    () => {
-        remove_dir(MP_PATH).unwrap();
-        remove_file(DB_PATH).unwrap();
+    ($mp:expr) => {
+        remove_dir(&$mp).unwrap();
+        remove_dir_all($mp.parent().unwrap()).unwrap();
     };
 }
 
 macro_rules! reg_method {
     ($m:ident) => {
         mod $m;
-        use $m::*;
     };
 }
 
@@ -66,8 +73,8 @@ macro_rules! load_prelude {
 macro_rules! read_file_query {
     () => {
         query_scalar(
-            "SELECT GROUP_CONCAT(bytes, '') FROM (SELECT bytes FROM file_contents WHERE ino = ? \
-             ORDER BY page)",
+            "SELECT GROUP_CONCAT(bytes, '') FROM (SELECT chunks.bytes FROM file_contents INNER \
+             JOIN chunks ON chunks.hash = file_contents.hash WHERE ino = ? ORDER BY page)",
         )
     };
 }
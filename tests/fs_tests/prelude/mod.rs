@@ -2,17 +2,29 @@
 mod macros;
 
 pub use std::{
-    fs::{File, create_dir, remove_dir, remove_file, rename},
-    io::{ErrorKind as IoErrorKind, Result as IoResult, Write},
-    os::unix::fs::FileExt,
+    fs::{
+        File, create_dir, create_dir_all, metadata, read, read_dir, remove_dir, remove_dir_all,
+        remove_file, rename,
+    },
+    io::{ErrorKind as IoErrorKind, Result as IoResult, Write, copy},
+    os::{
+        fd::AsRawFd,
+        unix::fs::{FileExt, MetadataExt},
+    },
     path::{Path, PathBuf},
+    process::Command,
     str::FromStr,
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Duration,
 };
 
 pub use fuser::{BackgroundSession, spawn_mount2};
-pub use ptfs::PTFS;
+pub use ptfs::{Cipher, Invalidator, PTFS, Settings, SqliteStore, Ttl};
 pub use rand::prelude::*;
-pub use sqlx::{SqlitePool, query, query_scalar, sqlite::SqliteConnectOptions};
+pub use sqlx::{
+    SqlitePool, query, query_as, query_scalar,
+    sqlite::{SqliteConnectOptions, SqliteJournalMode},
+};
 pub use tokio::runtime::Runtime;
 
 /// Alias to `File::create_new`
@@ -24,26 +36,105 @@ pub struct Test {
     pub rt: Runtime,
     pub pool: SqlitePool,
     pub bg_sess: BackgroundSession,
+    /// Mountpoint, in a temporary directory of the test's own
+    pub mp: PathBuf,
+    /// Flushed before checking other directories than the ones changed, see
+    /// [`Invalidator::flush`]
+    pub invalidator: Invalidator,
+}
+
+/// How [`Test::mount`] creates the file system
+#[derive(Default)]
+pub struct Setup {
+    /// How long the kernel caches replies
+    pub ttl: Ttl,
+    /// Arguments of `ptfs mkfs` creating a database file to mount, a new in-memory database is
+    /// mounted if it's `None`
+    pub mkfs: Option<&'static [&'static str]>,
+    /// Key file the database is encrypted and unlocked with, passed to mkfs with `--encrypt`
+    pub key: Option<&'static [u8]>,
 }
 
 impl Test {
     pub fn new() -> Test {
+        Test::mount(Default::default())
+    }
+
+    /// Mount with the kernel caching replies for `ttl`
+    pub fn with_ttl(ttl: Ttl) -> Test {
+        Test::mount(Setup {
+            ttl,
+            ..Default::default()
+        })
+    }
+
+    pub fn mount(setup: Setup) -> Test {
         tracing_subscriber::fmt::try_init().ok();
-        init_paths!();
+        // tests run in parallel, each in a directory of its own
+        static TESTS: AtomicUsize = AtomicUsize::new(0);
+        let dir = std::env::temp_dir().join(format!(
+            "ptfs-test-{}-{}",
+            std::process::id(),
+            TESTS.fetch_add(1, Ordering::Relaxed)
+        ));
+        let mp = path!(dir; "mnt");
+        init_paths!(&mp);
+
         let rt = Runtime::new().unwrap();
-        let pool = rt.block_on(init_pool!()).unwrap();
-        let bg_sess = init_sess!(rt, pool);
-        Test { rt, pool, bg_sess }
+        let (store, blob_dir) = match setup.mkfs {
+            Some(args) => {
+                let database = path!(dir; "db.sqlite");
+                let mut mkfs = Command::new(env!("CARGO_BIN_EXE_ptfs"));
+                mkfs.arg("mkfs").args(args);
+                if let Some(key) = setup.key {
+                    let key_file = path!(dir; "key");
+                    std::fs::write(&key_file, key).unwrap();
+                    mkfs.arg("--encrypt").arg("--key-file").arg(key_file);
+                }
+                let status = mkfs.arg(&database).status().unwrap();
+                assert!(status.success(), "mkfs failed");
+
+                let options = SqliteConnectOptions::from_str(database.to_str().unwrap())
+                    .unwrap()
+                    .journal_mode(SqliteJournalMode::Wal);
+                let pool = rt.block_on(SqlitePool::connect_with(options)).unwrap();
+                (SqliteStore::new(pool), path!(dir; "db.sqlite.blobs"))
+            }
+            // every test gets a new in-memory database, `pool` shares its connection for queries
+            None => {
+                let store = rt
+                    .block_on(SqliteStore::memory())
+                    .map_err(|_| "failed creating database")
+                    .unwrap();
+                // in-memory databases have no blob threshold
+                (store, PathBuf::new())
+            }
+        };
+        let pool = store.pool.clone();
+        let invalidator = Invalidator::default();
+        let cipher = setup.key.map(Cipher::from_key_file);
+        let bg_sess = init_sess!(rt, store, blob_dir, setup.ttl, invalidator, cipher, &mp);
+        Test {
+            rt,
+            pool,
+            bg_sess,
+            mp,
+            invalidator,
+        }
     }
 
-    pub fn cleanup(bg_sess: BackgroundSession) {
-        // INFO: dropping instead of joining leaves behind sqlite shm and wal files, however
-        // joining hangs when creating files (e.g., `let file = create_file("file").unwrap()`)
+    pub fn cleanup(bg_sess: BackgroundSession, mp: PathBuf) {
+        // INFO: joining hangs when creating files (e.g., `let file = create_file("file").unwrap()`)
         drop(bg_sess);
-        cleanup_paths!();
+        cleanup_paths!(mp);
     }
 }
 
-pub const DB_PATH: &str = "test-db.sqlite";
-pub const MP_PATH: &str = "test-mountpoint";
 pub const PAGE_SIZE: usize = 4096;
+pub const UNTAGGED_NAME: &str = ".untagged";
+/// TTLs long enough for the kernel to cache replies for the rest of a test
+pub const LONG_TTL: Ttl = Ttl {
+    attr: Duration::from_secs(60),
+    entry: Duration::from_secs(60),
+    negative: Duration::from_secs(60),
+};
@@ -0,0 +1,304 @@
+load_prelude!();
+
+/// Names and inodes of every entry of `path`, including `.` and `..`
+fn list_with_dots(path: &Path) -> Vec<(String, u64)> {
+    use std::{ffi::CStr, ffi::CString, os::unix::ffi::OsStrExt};
+
+    let c_path = CString::new(path.as_os_str().as_bytes()).unwrap();
+    let mut entries = vec![];
+    unsafe {
+        let dir = libc::opendir(c_path.as_ptr());
+        assert!(!dir.is_null());
+        loop {
+            let entry = libc::readdir(dir);
+            if entry.is_null() {
+                break;
+            }
+            let name = CStr::from_ptr((*entry).d_name.as_ptr());
+            entries.push((name.to_str().unwrap().to_string(), (*entry).d_ino));
+        }
+        libc::closedir(dir);
+    }
+    entries
+}
+
+#[test]
+fn readdir_untagged() {
+    let Test { rt, pool, bg_sess, mp, .. } = Test::new();
+
+    let tag_path = path!(mp; "#tag");
+    create_dir(&tag_path).unwrap();
+    create_file(path!(&tag_path; "file")).unwrap();
+
+    // leave file without tags
+    rt.block_on(query("DELETE FROM associated_tags WHERE ino = 3").execute(&pool))
+        .unwrap();
+
+    let names: Vec<String> = read_dir(path!(mp; UNTAGGED_NAME))
+        .unwrap()
+        .map(|e| e.unwrap().file_name().into_string().unwrap())
+        .collect();
+    assert_eq!(names, vec!["file"]);
+
+    Test::cleanup(bg_sess, mp);
+}
+
+#[test]
+fn readdir_tags() {
+    let Test { bg_sess, rt: _rt, mp, .. } = Test::new();
+
+    let a_path = path!(mp; "#a");
+    create_dir(&a_path).unwrap();
+    create_file(path!(&a_path; "file1")).unwrap();
+    create_file(path!(&a_path; "file2")).unwrap();
+    create_dir(path!(mp; "#b")).unwrap();
+
+    let tags_path = path!(mp; ".tags");
+    let names: Vec<String> = read_dir(&tags_path)
+        .unwrap()
+        .map(|e| e.unwrap().file_name().into_string().unwrap())
+        .collect();
+    assert_eq!(names, vec!["#a", "#b"]);
+
+    // assert counts
+    let a_meta = metadata(path!(&tags_path; "#a")).unwrap();
+    assert_eq!(a_meta.len(), 2);
+    assert_eq!(a_meta.nlink(), 2);
+    assert_eq!(metadata(path!(&tags_path; "#b")).unwrap().len(), 0);
+
+    // assert tag listing
+    let mut a_names: Vec<String> = read_dir(path!(&tags_path; "#a"))
+        .unwrap()
+        .map(|e| e.unwrap().file_name().into_string().unwrap())
+        .collect();
+    a_names.sort();
+    assert_eq!(a_names, vec!["file1", "file2"]);
+
+    Test::cleanup(bg_sess, mp);
+}
+
+#[test]
+fn readdir_time_windows() {
+    let Test { rt, pool, bg_sess, mp, .. } = Test::new();
+
+    let a_path = path!(mp; "#a");
+    create_dir(&a_path).unwrap();
+    create_file(path!(&a_path; "new")).unwrap();
+    create_file(path!(mp; "old")).unwrap();
+
+    // 1971-02-05
+    rt.block_on(query("UPDATE file_attrs SET mtime = 34560000 WHERE ino = 4").execute(&pool))
+        .unwrap();
+
+    let list = |path: PathBuf| -> Vec<String> {
+        read_dir(path)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().into_string().unwrap())
+            .collect()
+    };
+
+    assert_eq!(list(path!(mp; ".recent")), vec!["today", "week", "month"]);
+    assert_eq!(list(path!(mp; ".recent", "today")), vec!["new"]);
+    assert_eq!(list(path!(&a_path; ".recent", "week")), vec!["new"]);
+
+    let years = list(path!(mp; ".by-mtime"));
+    assert_eq!(years.len(), 2);
+    assert_eq!(years[0], "1971");
+    assert_eq!(list(path!(mp; ".by-mtime", "1971")), vec!["02"]);
+    assert_eq!(list(path!(mp; ".by-mtime", "1971", "02")), vec!["old"]);
+
+    // old isn't tagged with #a
+    assert_eq!(list(path!(&a_path; ".by-mtime")), vec![years[1].clone()]);
+
+    Test::cleanup(bg_sess, mp);
+}
+
+#[test]
+fn readdir_search() {
+    let Test { bg_sess, rt: _rt, mp, .. } = Test::new();
+
+    let work_path = path!(mp; "#work");
+    create_dir(&work_path).unwrap();
+    create_file(path!(&work_path; "a.pdf")).unwrap();
+    create_file(path!(&work_path; "b.txt")).unwrap();
+    create_file(path!(&work_path; "100%.pdf")).unwrap();
+    create_file(path!(mp; "c.pdf")).unwrap();
+    create_file(path!(mp; "D.PDF")).unwrap();
+    create_file(path!(mp; "[x].txt")).unwrap();
+
+    let search = |path: PathBuf| -> Vec<String> {
+        let mut names: Vec<String> = read_dir(path)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().into_string().unwrap())
+            .collect();
+        names.sort();
+        names
+    };
+
+    assert_eq!(
+        search(path!(mp; ".search", "*.pdf")),
+        vec!["100%.pdf", "a.pdf", "c.pdf"]
+    );
+    assert_eq!(
+        search(path!(&work_path; ".search", "*.pdf")),
+        vec!["100%.pdf", "a.pdf"]
+    );
+    assert_eq!(search(path!(mp; ".search", "?.txt")), vec!["b.txt"]);
+    assert_eq!(search(path!(mp; ".search", "100%*")), vec!["100%.pdf"]);
+    assert_eq!(search(path!(mp; ".search", "1_0*")), Vec::<String>::new());
+    // matching is case-sensitive, and only * and ? are wildcards
+    assert_eq!(search(path!(mp; ".search", "*.PDF")), vec!["D.PDF"]);
+    assert_eq!(search(path!(mp; ".search", "[x]*")), vec!["[x].txt"]);
+
+    Test::cleanup(bg_sess, mp);
+}
+
+#[test]
+fn readdir_dots() {
+    let Test { bg_sess, rt: _rt, mp, .. } = Test::new();
+
+    let dir_path = path!(mp; "dir");
+    create_dir(&dir_path).unwrap();
+    create_dir(path!(&dir_path; "sub")).unwrap();
+    create_file(path!(&dir_path; "sub", "file")).unwrap();
+
+    let ino = |path: &Path| metadata(path).unwrap().ino();
+    let sub_path = path!(&dir_path; "sub");
+    assert_eq!(
+        list_with_dots(&sub_path),
+        vec![
+            (".".to_string(), ino(&sub_path)),
+            ("..".to_string(), ino(&dir_path)),
+            ("file".to_string(), ino(&path!(&sub_path; "file"))),
+        ]
+    );
+
+    // the root is its own parent
+    let root_entries = list_with_dots(&mp);
+    assert_eq!(root_entries[0], (".".to_string(), 1));
+    assert_eq!(root_entries[1], ("..".to_string(), 1));
+
+    // virtual directories are listed in the directory they're found in
+    let today_path = path!(mp; ".recent", "today");
+    let today_entries = list_with_dots(&today_path);
+    assert_eq!(today_entries[0], (".".to_string(), ino(&today_path)));
+    assert_eq!(
+        today_entries[1],
+        ("..".to_string(), ino(&path!(mp; ".recent")))
+    );
+
+    Test::cleanup(bg_sess, mp);
+}
+
+#[test]
+fn readdir_resume() {
+    let Test { bg_sess, rt: _rt, mp, .. } = Test::new();
+
+    let dir_path = path!(mp; "dir");
+    create_dir(&dir_path).unwrap();
+    let files = 2000;
+    for i in 0..files {
+        create_file(path!(&dir_path; format!("file-{i}"))).unwrap();
+    }
+
+    // removing listed files while listing doesn't skip or repeat the rest
+    let mut names = vec![];
+    for entry in read_dir(&dir_path).unwrap() {
+        let name = entry.unwrap().file_name().into_string().unwrap();
+        names.push(name);
+        if names.len() == 500 {
+            for name in &names {
+                remove_file(path!(&dir_path; name)).unwrap();
+            }
+        }
+    }
+    assert_eq!(names.len(), files);
+    names.sort();
+    names.dedup();
+    assert_eq!(names.len(), files);
+
+    Test::cleanup(bg_sess, mp);
+}
+
+#[test]
+fn readdir_attrs() {
+    let Test { bg_sess, rt: _rt, mp, .. } = Test::new();
+
+    let a_path = path!(mp; "#a");
+    create_dir(&a_path).unwrap();
+    for i in 0..100 {
+        create_file(path!(&a_path; format!("file-{i}")))
+            .unwrap()
+            .write_all(&vec![0; i])
+            .unwrap();
+    }
+
+    // like `ls -l`, with attributes listed along with the entries
+    let mut sizes: Vec<(String, u64)> = read_dir(&a_path)
+        .unwrap()
+        .map(|e| {
+            let e = e.unwrap();
+            (
+                e.file_name().into_string().unwrap(),
+                e.metadata().unwrap().len(),
+            )
+        })
+        .collect();
+    sizes.sort_by_key(|(_, size)| *size);
+    assert_eq!(
+        sizes,
+        (0..100)
+            .map(|i| (format!("file-{i}"), i))
+            .collect::<Vec<_>>()
+    );
+
+    let tags: Vec<(String, u64)> = read_dir(path!(mp; ".tags"))
+        .unwrap()
+        .map(|e| {
+            let e = e.unwrap();
+            (
+                e.file_name().into_string().unwrap(),
+                e.metadata().unwrap().len(),
+            )
+        })
+        .collect();
+    assert_eq!(tags, vec![("#a".to_string(), 100)]);
+
+    Test::cleanup(bg_sess, mp);
+}
+
+// attributes listed along with the entries are cached, so the kernel doesn't look them up again
+#[test]
+fn readdir_plus() {
+    let Test { bg_sess, rt, pool, mp, .. } = Test::with_ttl(LONG_TTL);
+
+    create_dir(path!(mp; "#a")).unwrap();
+    // a file the kernel hasn't looked up yet, copied from one it has
+    create_file(path!(mp; "#a", "file")).unwrap();
+    rt.block_on(
+        query(
+            "INSERT INTO file_attrs (size, blocks, atime, mtime, ctime, crtime, kind, perm, \
+             nlink, uid, gid, rdev, blksize, flags) SELECT size, blocks, atime, mtime, ctime, \
+             crtime, kind, perm, nlink, uid, gid, rdev, blksize, flags FROM file_attrs WHERE ino \
+             = 3; INSERT INTO file_names (ino, name) VALUES (4, 'copy'); INSERT INTO \
+             associated_tags (tid, ino) SELECT tid, 4 FROM tags WHERE name = '#a'",
+        )
+        .execute(&pool),
+    )
+    .unwrap();
+
+    let tagged_path = path!(mp; ".tags", "#a");
+    let mut names: Vec<String> = read_dir(&tagged_path)
+        .unwrap()
+        .map(|e| e.unwrap().file_name().into_string().unwrap())
+        .collect();
+    names.sort();
+    assert_eq!(names, vec!["copy", "file"]);
+
+    // a lookup would find the new size
+    rt.block_on(query("UPDATE file_attrs SET size = 42 WHERE ino = 4").execute(&pool))
+        .unwrap();
+    assert_eq!(metadata(path!(&tagged_path; "copy")).unwrap().len(), 0);
+
+    Test::cleanup(bg_sess, mp);
+}
@@ -1,42 +1,91 @@
 load_prelude!();
 
-pub fn test_rename() {
-    // TODO: test:
-    // - premissions
-    // - atime, ctime, mtime
-    rename_p2u();
-    rename_u_p2p();
-    rename_u_p2u();
-    rename_u_u2p();
-    rename_u_u2u();
-    rename_p_p2p(); // prefixed directory, from prefixed parent to another prefixed parent
-    rename_p_p2u();
-    rename_p_u2p();
-    rename_p_u2u();
-}
+// TODO: test:
+// - premissions
+// - atime, ctime, mtime
 
+#[test]
 fn rename_p2u() {
-    let t = Test::new();
-    let Test { bg_sess, .. } = t;
+    let Test { rt, pool, bg_sess, mp, .. } = Test::new();
 
-    let dir_path = path!(MP_PATH, "#dir");
+    let dir_path = path!(mp; "#dir");
     create_dir(&dir_path).unwrap();
 
-    let new_dir_path = path!(MP_PATH, "dir");
-    let mv_op = rename(&dir_path, &new_dir_path);
+    create_file(path!(&dir_path; "file")).unwrap();
+
+    let new_dir_path = path!(mp; "dir");
+    rename(&dir_path, &new_dir_path).unwrap();
+
+    // assert tagged file moved into the directory's contents
+    rt.block_on(
+        query("SELECT 1 FROM dir_contents WHERE dir_ino = 2 AND cnt_ino = 3").fetch_one(&pool),
+    )
+    .unwrap();
+
+    // assert no associations left
+    let tids: Vec<u64> = rt
+        .block_on(query_scalar("SELECT tid FROM associated_tags").fetch_all(&pool))
+        .unwrap();
+    assert!(tids.is_empty());
+
+    // assert no dangling tags
+    assert!(
+        rt.block_on(query("SELECT 1 FROM tags WHERE name = '#dir'").fetch_optional(&pool))
+            .unwrap()
+            .is_none()
+    );
+
+    Test::cleanup(bg_sess, mp);
+}
+
+#[test]
+fn rename_u2p() {
+    let Test { rt, pool, bg_sess, mp, .. } = Test::new();
+
+    let dir_path = path!(mp; "dir");
+    create_dir(&dir_path).unwrap();
+
+    create_file(path!(&dir_path; "file")).unwrap();
+
+    let new_dir_path = path!(mp; "#dir");
+    rename(&dir_path, &new_dir_path).unwrap();
+
+    // assert directory and its contents are associated with the new tag
+    let tid: u64 = rt
+        .block_on(query_scalar("SELECT tid FROM tags WHERE name = '#dir'").fetch_one(&pool))
+        .unwrap();
+    for ino in [2, 3] {
+        let tids: Vec<u64> = rt
+            .block_on(
+                query_scalar("SELECT tid FROM associated_tags WHERE ino = ?")
+                    .bind(ino)
+                    .fetch_all(&pool),
+            )
+            .unwrap();
+        assert_eq!(tids, vec![tid]);
+    }
+
+    // assert file is only listed through its tag
+    assert!(
+        rt.block_on(query("SELECT 1 FROM dir_contents WHERE cnt_ino = 3").fetch_optional(&pool))
+            .unwrap()
+            .is_none()
+    );
 
-    assert!(mv_op.is_err_and(|e| e.kind() == IoErrorKind::InvalidInput));
+    // assert file is still reachable
+    assert!(path!(&new_dir_path; "file").exists());
 
-    Test::cleanup(bg_sess);
+    Test::cleanup(bg_sess, mp);
 }
 
+#[test]
 fn rename_u_p2p() {
-    let Test { rt, pool, bg_sess } = Test::new();
+    let Test { rt, pool, bg_sess, mp, .. } = Test::new();
 
-    let parent_path = path!(MP_PATH, "#parent");
+    let parent_path = path!(mp; "#parent");
     create_dir(&parent_path).unwrap();
 
-    let new_parent_path = path!(MP_PATH, "#new-parent");
+    let new_parent_path = path!(mp; "#new-parent");
     create_dir(&new_parent_path).unwrap();
 
     let child_path = path!(&parent_path; "child");
@@ -58,16 +107,17 @@ fn rename_u_p2p() {
     )
     .unwrap();
 
-    Test::cleanup(bg_sess);
+    Test::cleanup(bg_sess, mp);
 }
 
+#[test]
 fn rename_u_p2u() {
-    let Test { rt, pool, bg_sess } = Test::new();
+    let Test { rt, pool, bg_sess, mp, .. } = Test::new();
 
-    let parent_path = path!(MP_PATH, "#parent");
+    let parent_path = path!(mp; "#parent");
     create_dir(&parent_path).unwrap();
 
-    let new_parent_path = path!(MP_PATH, "new-parent");
+    let new_parent_path = path!(mp; "new-parent");
     create_dir(&new_parent_path).unwrap();
 
     let child_path = path!(&parent_path; "child");
@@ -88,16 +138,17 @@ fn rename_u_p2u() {
     )
     .unwrap();
 
-    Test::cleanup(bg_sess);
+    Test::cleanup(bg_sess, mp);
 }
 
+#[test]
 fn rename_u_u2p() {
-    let Test { rt, pool, bg_sess } = Test::new();
+    let Test { rt, pool, bg_sess, mp, .. } = Test::new();
 
-    let parent_path = path!(MP_PATH, "parent");
+    let parent_path = path!(mp; "parent");
     create_dir(&parent_path).unwrap();
 
-    let new_parent_path = path!(MP_PATH, "#new-parent");
+    let new_parent_path = path!(mp; "#new-parent");
     create_dir(&new_parent_path).unwrap();
 
     let child_path = path!(&parent_path; "child");
@@ -119,16 +170,17 @@ fn rename_u_u2p() {
     )
     .unwrap();
 
-    Test::cleanup(bg_sess);
+    Test::cleanup(bg_sess, mp);
 }
 
+#[test]
 fn rename_u_u2u() {
-    let Test { rt, pool, bg_sess } = Test::new();
+    let Test { rt, pool, bg_sess, mp, .. } = Test::new();
 
-    let parent_path = path!(MP_PATH, "parent");
+    let parent_path = path!(mp; "parent");
     create_dir(&parent_path).unwrap();
 
-    let new_parent_path = path!(MP_PATH, "new-parent");
+    let new_parent_path = path!(mp; "new-parent");
     create_dir(&new_parent_path).unwrap();
 
     let child_path = path!(&parent_path; "child");
@@ -149,16 +201,18 @@ fn rename_u_u2u() {
     )
     .unwrap();
 
-    Test::cleanup(bg_sess);
+    Test::cleanup(bg_sess, mp);
 }
 
+// prefixed directory, from prefixed parent to another prefixed parent
+#[test]
 fn rename_p_p2p() {
-    let Test { rt, pool, bg_sess } = Test::new();
+    let Test { rt, pool, bg_sess, mp, .. } = Test::new();
 
-    let parent_path = path!(MP_PATH, "#parent");
+    let parent_path = path!(mp; "#parent");
     create_dir(&parent_path).unwrap();
 
-    let new_parent_path = path!(MP_PATH, "#new-parent");
+    let new_parent_path = path!(mp; "#new-parent");
     create_dir(&new_parent_path).unwrap();
 
     let child_path = path!(&parent_path; "#child");
@@ -188,16 +242,17 @@ fn rename_p_p2p() {
             .is_none()
     );
 
-    Test::cleanup(bg_sess);
+    Test::cleanup(bg_sess, mp);
 }
 
+#[test]
 fn rename_p_p2u() {
-    let Test { rt, pool, bg_sess } = Test::new();
+    let Test { rt, pool, bg_sess, mp, .. } = Test::new();
 
-    let parent_path = path!(MP_PATH, "#parent");
+    let parent_path = path!(mp; "#parent");
     create_dir(&parent_path).unwrap();
 
-    let new_parent_path = path!(MP_PATH, "new-parent");
+    let new_parent_path = path!(mp; "new-parent");
     create_dir(&new_parent_path).unwrap();
 
     let child_path = path!(&parent_path; "#child");
@@ -232,16 +287,17 @@ fn rename_p_p2u() {
             .is_none()
     );
 
-    Test::cleanup(bg_sess);
+    Test::cleanup(bg_sess, mp);
 }
 
+#[test]
 fn rename_p_u2p() {
-    let Test { rt, pool, bg_sess } = Test::new();
+    let Test { rt, pool, bg_sess, mp, .. } = Test::new();
 
-    let parent_path = path!(MP_PATH, "parent");
+    let parent_path = path!(mp; "parent");
     create_dir(&parent_path).unwrap();
 
-    let new_parent_path = path!(MP_PATH, "#new-parent");
+    let new_parent_path = path!(mp; "#new-parent");
     create_dir(&new_parent_path).unwrap();
 
     let child_path = path!(&parent_path; "#child");
@@ -281,16 +337,17 @@ fn rename_p_u2p() {
             .is_none()
     );
 
-    Test::cleanup(bg_sess);
+    Test::cleanup(bg_sess, mp);
 }
 
+#[test]
 fn rename_p_u2u() {
-    let Test { rt, pool, bg_sess } = Test::new();
+    let Test { rt, pool, bg_sess, mp, .. } = Test::new();
 
-    let parent_path = path!(MP_PATH, "parent");
+    let parent_path = path!(mp; "parent");
     create_dir(&parent_path).unwrap();
 
-    let new_parent_path = path!(MP_PATH, "new-parent");
+    let new_parent_path = path!(mp; "new-parent");
     create_dir(&new_parent_path).unwrap();
 
     let child_path = path!(&parent_path; "#child");
@@ -335,5 +392,116 @@ fn rename_p_u2u() {
             .is_none()
     );
 
-    Test::cleanup(bg_sess);
+    Test::cleanup(bg_sess, mp);
+}
+
+#[test]
+fn rename_from_untagged() {
+    let Test { rt, pool, bg_sess, mp, .. } = Test::new();
+
+    let tag_path = path!(mp; "#tag");
+    create_dir(&tag_path).unwrap();
+    create_file(path!(&tag_path; "file")).unwrap();
+
+    // leave file without tags
+    rt.block_on(query("DELETE FROM associated_tags WHERE ino = 3").execute(&pool))
+        .unwrap();
+
+    rename(path!(mp; UNTAGGED_NAME, "file"), path!(mp; "new-file")).unwrap();
+
+    // assert file is re-homed into the root
+    rt.block_on(
+        query("SELECT 1 FROM dir_contents WHERE dir_ino = 1 AND cnt_ino = 3").fetch_one(&pool),
+    )
+    .unwrap();
+
+    Test::cleanup(bg_sess, mp);
+}
+
+// tags created and deleted by renaming directories appear in and leave .tags
+#[test]
+fn rename_tag_entries() {
+    let Test { bg_sess, rt: _rt, mp, invalidator, .. } = Test::with_ttl(LONG_TTL);
+
+    let tags_path = path!(mp; ".tags");
+    create_dir(path!(mp; "dir")).unwrap();
+    assert!(!path!(&tags_path; "#new").exists());
+    assert!(!path!(&tags_path; "#renamed").exists());
+
+    // promote
+    rename(path!(mp; "dir"), path!(mp; "#new")).unwrap();
+    invalidator.flush();
+    assert!(path!(&tags_path; "#new").exists());
+
+    // rename the tag
+    rename(path!(mp; "#new"), path!(mp; "#renamed")).unwrap();
+    invalidator.flush();
+    assert!(!path!(&tags_path; "#new").exists());
+    assert!(path!(&tags_path; "#renamed").exists());
+
+    // demote
+    rename(path!(mp; "#renamed"), path!(mp; "dir")).unwrap();
+    invalidator.flush();
+    assert!(!path!(&tags_path; "#renamed").exists());
+
+    Test::cleanup(bg_sess, mp);
+}
+
+// other directories listing the file through its tags see it move
+#[test]
+fn rename_tag_views() {
+    let Test { bg_sess, rt: _rt, mp, invalidator, .. } = Test::with_ttl(LONG_TTL);
+
+    let a_path = path!(mp; "#a");
+    create_dir(&a_path).unwrap();
+    let b_path = path!(mp; "#b");
+    create_dir(&b_path).unwrap();
+    let ab_path = path!(&b_path; "#a");
+    create_dir(&ab_path).unwrap();
+    create_file(path!(&a_path; "file")).unwrap();
+
+    // cache the file in the views of #a and its absence from those of #b
+    let old_views = [path!(mp; ".tags", "#a", "file"), path!(mp; "#a", "file")];
+    let new_views = [path!(mp; ".tags", "#b", "file"), path!(&ab_path; "renamed")];
+    assert!(old_views.iter().all(|path| path.exists()));
+    assert!(new_views.iter().all(|path| !path.exists()));
+
+    rename(path!(&a_path; "file"), path!(&ab_path; "renamed")).unwrap();
+    invalidator.flush();
+
+    assert!(!path!(mp; ".tags", "#a", "file").exists());
+    assert!(path!(mp; ".tags", "#a", "renamed").exists());
+    assert!(path!(mp; ".tags", "#b", "renamed").exists());
+    assert!(path!(&b_path; "renamed").exists());
+    assert!(!path!(&a_path; "file").exists());
+    assert!(path!(&a_path; "renamed").exists());
+
+    Test::cleanup(bg_sess, mp);
+}
+
+// virtual directories are found before real entries, which can't take their names
+#[test]
+fn rename_vdir_names() {
+    let Test { bg_sess, rt: _rt, mp, .. } = Test::new();
+
+    let dir_path = path!(mp; "dir");
+    create_dir(&dir_path).unwrap();
+    create_dir(path!(mp; "#a")).unwrap();
+    for vdir_path in [
+        path!(mp; ".tags"),
+        path!(mp; UNTAGGED_NAME),
+        path!(mp; ".recent"),
+        path!(mp; "#a", ".search"),
+    ] {
+        let e = rename(&dir_path, &vdir_path).unwrap_err();
+        assert_eq!(e.kind(), IoErrorKind::AlreadyExists);
+    }
+
+    // plain directories have no virtual directories, until they become tag directories
+    create_file(path!(&dir_path; ".by-mtime")).unwrap();
+    let e = rename(&dir_path, path!(mp; "#dir")).unwrap_err();
+    assert_eq!(e.kind(), IoErrorKind::AlreadyExists);
+    assert!(path!(&dir_path; ".by-mtime").is_file());
+
+    Test::cleanup(bg_sess, mp);
 }
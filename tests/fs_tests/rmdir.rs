@@ -0,0 +1,105 @@
+load_prelude!();
+
+// tag directory at the root, untagged files are moved into lost+found
+#[test]
+fn rmdir_tag() {
+    let Test { rt, pool, bg_sess, mp, .. } = Test::new();
+
+    let tag_path = path!(mp; "#tag");
+    create_dir(&tag_path).unwrap();
+    create_file(path!(&tag_path; "file")).unwrap();
+
+    remove_dir(&tag_path).unwrap();
+
+    // assert tag is deleted
+    assert!(
+        rt.block_on(query("SELECT 1 FROM tags WHERE name = '#tag'").fetch_optional(&pool))
+            .unwrap()
+            .is_none()
+    );
+
+    // assert file is moved into lost+found
+    let lost_found_ino: u64 = rt
+        .block_on(
+            query_scalar("SELECT ino FROM file_names WHERE name = 'lost+found'").fetch_one(&pool),
+        )
+        .unwrap();
+    rt.block_on(
+        query("SELECT 1 FROM dir_contents WHERE dir_ino = ? AND cnt_ino = 3")
+            .bind(i64::try_from(lost_found_ino).unwrap())
+            .fetch_one(&pool),
+    )
+    .unwrap();
+    assert!(path!(mp; "lost+found", "file").exists());
+
+    Test::cleanup(bg_sess, mp);
+}
+
+// files stay tagged with the parent's tags
+#[test]
+fn rmdir_nested_tag() {
+    let Test { rt, pool, bg_sess, mp, .. } = Test::new();
+
+    let parent_path = path!(mp; "#parent");
+    create_dir(&parent_path).unwrap();
+    let tag_path = path!(&parent_path; "#tag");
+    create_dir(&tag_path).unwrap();
+    create_file(path!(&tag_path; "file")).unwrap();
+
+    remove_dir(&tag_path).unwrap();
+
+    // assert file only keeps the parent's tag
+    let tids: Vec<u64> = rt
+        .block_on(query_scalar("SELECT tid FROM associated_tags WHERE ino = 4").fetch_all(&pool))
+        .unwrap();
+    assert_eq!(tids, vec![1]);
+
+    // assert file isn't moved into lost+found
+    assert!(
+        rt.block_on(query("SELECT 1 FROM dir_contents WHERE cnt_ino = 4").fetch_optional(&pool))
+            .unwrap()
+            .is_none()
+    );
+    assert!(path!(&parent_path; "file").exists());
+
+    Test::cleanup(bg_sess, mp);
+}
+
+#[test]
+fn rmdir_tag_nested_nonempty() {
+    let Test { bg_sess, rt: _rt, mp, .. } = Test::new();
+
+    let tag_path = path!(mp; "#tag");
+    create_dir(&tag_path).unwrap();
+    create_dir(path!(&tag_path; "#nested")).unwrap();
+
+    let rm_op = remove_dir(&tag_path);
+    assert!(rm_op.is_err_and(|e| e.kind() == IoErrorKind::DirectoryNotEmpty));
+
+    Test::cleanup(bg_sess, mp);
+}
+
+// untagged files leave the kernel's cache of other directories
+#[test]
+fn rmdir_tag_views() {
+    let Test { bg_sess, rt: _rt, mp, invalidator, .. } = Test::with_ttl(LONG_TTL);
+
+    let a_path = path!(mp; "#a");
+    create_dir(&a_path).unwrap();
+    let b_path = path!(&a_path; "#b");
+    create_dir(&b_path).unwrap();
+    create_file(path!(&b_path; "file")).unwrap();
+
+    let tagged_path = path!(mp; ".tags", "#b", "file");
+    assert!(tagged_path.exists());
+    assert_eq!(metadata(path!(mp; ".tags", "#a")).unwrap().len(), 1);
+
+    remove_dir(&b_path).unwrap();
+    invalidator.flush();
+
+    assert!(!tagged_path.exists());
+    assert!(!path!(mp; ".tags", "#b").exists());
+    assert!(path!(&a_path; "file").exists());
+
+    Test::cleanup(bg_sess, mp);
+}
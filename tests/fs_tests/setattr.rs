@@ -10,46 +10,68 @@ load_prelude!();
 // 4.   |###)#|####-|
 // 5.   |---)-|####-|
 
-pub fn test_setattr() {
-    resize_empty_file(); // 1
-    truncate_file(); // 2
-    extend_file(); // 3
-    truncate_file2(); // 4
-    truncate_file3(); // 5
+#[test]
+fn sparse_file() {
+    let Test { bg_sess, rt, pool, mp, .. } = Test::new();
+
+    let size = PAGE_SIZE * 1024;
+    let file = create_file(path!(mp; "file")).unwrap();
+    file.set_len(size.try_into().unwrap()).unwrap();
+    assert_eq!(metadata(path!(mp; "file")).unwrap().blocks(), 0);
+
+    // only the written page is stored
+    let bytes: Vec<u8> = rand::random_iter().take(512).collect();
+    let offset = size - PAGE_SIZE;
+    file.write_all_at(&bytes, offset.try_into().unwrap())
+        .unwrap();
+    let pages: u64 = rt
+        .block_on(query_scalar("SELECT COUNT(*) FROM file_contents WHERE ino = 2").fetch_one(&pool))
+        .unwrap();
+    assert_eq!(pages, 1);
+
+    let meta = metadata(path!(mp; "file")).unwrap();
+    assert_eq!(meta.len(), size as u64);
+    assert_eq!(meta.blocks(), (PAGE_SIZE / 512) as u64);
+
+    let read_bytes = read(path!(mp; "file")).unwrap();
+    assert!(read_bytes[..offset].iter().all(|b| *b == 0));
+    assert!(read_bytes[offset..offset + 512] == bytes);
+    assert!(read_bytes[offset + 512..].iter().all(|b| *b == 0));
+
+    Test::cleanup(bg_sess, mp);
 }
 
+// case 5
+#[test]
 fn truncate_file3() {
-    let Test { bg_sess, rt, pool } = Test::new();
+    let Test { bg_sess, rt, pool, mp, .. } = Test::new();
 
     let size = PAGE_SIZE - 512 * 2;
     let offset = PAGE_SIZE + 512;
     let bytes: Vec<u8> = rand::random_iter().take(size).collect();
-    let file = create_file(path!(MP_PATH, "file")).unwrap();
+    let file = create_file(path!(mp; "file")).unwrap();
     file.write_all_at(&bytes, offset.try_into().unwrap())
         .unwrap();
     file.set_len(size.try_into().unwrap()).unwrap();
 
+    // only the hole is left
     let pages: u64 = rt
-        .block_on(
-            query_scalar("SELECT LENGTH(page) FROM file_contents WHERE ino = 2").fetch_one(&pool),
-        )
+        .block_on(query_scalar("SELECT COUNT(*) FROM file_contents WHERE ino = 2").fetch_one(&pool))
         .unwrap();
-    assert_eq!(pages, 1);
+    assert_eq!(pages, 0);
+    assert_eq!(read(path!(mp; "file")).unwrap(), vec![0u8; size]);
 
-    let db_bytes: Vec<u8> = rt
-        .block_on(read_file_query!().bind(2).fetch_one(&pool))
-        .unwrap();
-    assert_eq!(db_bytes, vec![0u8; size]);
-
-    Test::cleanup(bg_sess);
+    Test::cleanup(bg_sess, mp);
 }
 
+// case 4
+#[test]
 fn truncate_file2() {
-    let Test { bg_sess, rt, pool } = Test::new();
+    let Test { bg_sess, rt, pool, mp, .. } = Test::new();
 
     let size = PAGE_SIZE + 512;
     let bytes: Vec<u8> = rand::random_iter().take(size).collect();
-    let mut file = create_file(path!(MP_PATH, "file")).unwrap();
+    let mut file = create_file(path!(mp; "file")).unwrap();
     file.write_all(&bytes).unwrap();
 
     let new_size = PAGE_SIZE - 512;
@@ -67,35 +89,42 @@ fn truncate_file2() {
         .unwrap();
     assert_eq!(pages, 1);
 
-    Test::cleanup(bg_sess);
+    Test::cleanup(bg_sess, mp);
 }
 
+// case 3
+#[test]
 fn extend_file() {
-    let Test { bg_sess, rt, pool } = Test::new();
+    let Test { bg_sess, rt, pool, mp, .. } = Test::new();
 
     let size = PAGE_SIZE - 512;
     let mut bytes: Vec<u8> = rand::random_iter().take(size).collect();
-    let mut file = create_file(path!(MP_PATH, "file")).unwrap();
+    let mut file = create_file(path!(mp; "file")).unwrap();
     file.write_all(&bytes).unwrap();
 
     let new_size = PAGE_SIZE * 2 - 512;
     file.set_len(new_size.try_into().unwrap()).unwrap();
 
+    // the extension is a hole
     let db_bytes: Vec<u8> = rt
         .block_on(read_file_query!().bind(2).fetch_one(&pool))
         .unwrap();
-    bytes.resize(new_size, 0);
     assert!(bytes == db_bytes);
 
-    Test::cleanup(bg_sess);
+    bytes.resize(new_size, 0);
+    assert!(bytes == read(path!(mp; "file")).unwrap());
+
+    Test::cleanup(bg_sess, mp);
 }
 
+// case 2
+#[test]
 fn truncate_file() {
-    let Test { bg_sess, rt, pool } = Test::new();
+    let Test { bg_sess, rt, pool, mp, .. } = Test::new();
 
     let size = PAGE_SIZE;
     let bytes: Vec<u8> = rand::random_iter().take(size).collect();
-    let mut file = create_file(path!(MP_PATH, "file")).unwrap();
+    let mut file = create_file(path!(mp; "file")).unwrap();
     file.write_all(&bytes).unwrap();
 
     let new_size = size - 512;
@@ -106,21 +135,24 @@ fn truncate_file() {
         .unwrap();
     assert!(bytes[..new_size] == db_bytes);
 
-    Test::cleanup(bg_sess);
+    Test::cleanup(bg_sess, mp);
 }
 
+// case 1
+#[test]
 fn resize_empty_file() {
-    let Test { bg_sess, rt, pool } = Test::new();
+    let Test { bg_sess, rt, pool, mp, .. } = Test::new();
 
     let size = PAGE_SIZE - 512;
     let bytes = vec![0u8; size];
-    let file = create_file(path!(MP_PATH, "file")).unwrap();
+    let file = create_file(path!(mp; "file")).unwrap();
     file.set_len(size.try_into().unwrap()).unwrap();
 
-    let db_bytes: Vec<u8> = rt
-        .block_on(read_file_query!().bind(2).fetch_one(&pool))
+    let pages: u64 = rt
+        .block_on(query_scalar("SELECT COUNT(*) FROM file_contents WHERE ino = 2").fetch_one(&pool))
         .unwrap();
-    assert!(bytes == db_bytes);
+    assert_eq!(pages, 0);
+    assert!(bytes == read(path!(mp; "file")).unwrap());
 
-    Test::cleanup(bg_sess);
+    Test::cleanup(bg_sess, mp);
 }
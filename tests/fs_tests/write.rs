@@ -1,39 +1,33 @@
 load_prelude!();
 
-pub fn test_write() {
-    // = CASES
-    //
-    // Legend:
-    // |    : page delimiter
-    // [    : start of data
-    // ]    : end of data
-    // #    : data
-    // -    : empty data
-    // )    : resize
-    //
-    // == Aligned
-    // 1.   |[---]|
-    // 2.   |[----|----]|
-    //
-    // == Unaligned
-    // 3.   |[-]--|
-    // 4.   |[----|-]---|
-    // 5.   |-----|-[-]-|
-
-    aligned(); // 1
-    aligned_span(); // 2
-    unaligned_end(); // 3
-    unaligned_end_span(); // 4
-    unaligned_start_end_span(); // 5
-}
-
+// = CASES
+//
+// Legend:
+// |    : page delimiter
+// [    : start of data
+// ]    : end of data
+// #    : data
+// -    : empty data
+// )    : resize
+//
+// == Aligned
+// 1.   |[---]|
+// 2.   |[----|----]|
+//
+// == Unaligned
+// 3.   |[-]--|
+// 4.   |[----|-]---|
+// 5.   |-----|-[-]-|
+
+// case 5
+#[test]
 fn unaligned_start_end_span() {
-    let Test { bg_sess, rt, pool } = Test::new();
+    let Test { bg_sess, rt, pool, mp, .. } = Test::new();
 
     let size = PAGE_SIZE - 512 * 2;
     let offset = PAGE_SIZE + 512;
     let bytes: Vec<u8> = rand::random_iter().take(size).collect();
-    let file = create_file(path!(MP_PATH, "file")).unwrap();
+    let file = create_file(path!(mp; "file")).unwrap();
     file.write_all_at(&bytes, offset.try_into().unwrap())
         .unwrap();
 
@@ -46,23 +40,28 @@ fn unaligned_start_end_span() {
 
     let db_bytes: Vec<u8> = rt
         .block_on(
-            query_scalar("SELECT bytes FROM file_contents WHERE ino = 2 AND page = 1")
-                .fetch_one(&pool),
+            query_scalar(
+                "SELECT chunks.bytes FROM file_contents INNER JOIN chunks ON chunks.hash = \
+                 file_contents.hash WHERE ino = 2 AND page = 1",
+            )
+            .fetch_one(&pool),
         )
         .unwrap();
     let mut new_bytes = vec![0u8; 512];
     new_bytes.extend_from_slice(&bytes);
     assert_eq!(new_bytes, db_bytes);
 
-    Test::cleanup(bg_sess);
+    Test::cleanup(bg_sess, mp);
 }
 
+// case 1
+#[test]
 fn aligned() {
-    let Test { bg_sess, rt, pool } = Test::new();
+    let Test { bg_sess, rt, pool, mp, .. } = Test::new();
 
     let size = PAGE_SIZE;
     let bytes: Vec<u8> = rand::random_iter().take(size).collect();
-    let mut file = create_file(path!(MP_PATH, "file")).unwrap();
+    let mut file = create_file(path!(mp; "file")).unwrap();
     file.write_all(&bytes).unwrap();
 
     let db_bytes: Vec<u8> = rt
@@ -70,15 +69,17 @@ fn aligned() {
         .unwrap();
     assert!(bytes == db_bytes);
 
-    Test::cleanup(bg_sess);
+    Test::cleanup(bg_sess, mp);
 }
 
+// case 2
+#[test]
 fn aligned_span() {
-    let Test { bg_sess, rt, pool } = Test::new();
+    let Test { bg_sess, rt, pool, mp, .. } = Test::new();
 
     let size = PAGE_SIZE * 2;
     let bytes: Vec<u8> = rand::random_iter().take(size).collect();
-    let mut file = create_file(path!(MP_PATH, "file")).unwrap();
+    let mut file = create_file(path!(mp; "file")).unwrap();
     file.write_all(&bytes).unwrap();
 
     let db_bytes: Vec<u8> = rt
@@ -86,15 +87,17 @@ fn aligned_span() {
         .unwrap();
     assert!(bytes == db_bytes);
 
-    Test::cleanup(bg_sess);
+    Test::cleanup(bg_sess, mp);
 }
 
+// case 3
+#[test]
 fn unaligned_end() {
-    let Test { bg_sess, rt, pool } = Test::new();
+    let Test { bg_sess, rt, pool, mp, .. } = Test::new();
 
     let size = PAGE_SIZE - 512;
     let bytes: Vec<u8> = rand::random_iter().take(size).collect();
-    let mut file = create_file(path!(MP_PATH, "file")).unwrap();
+    let mut file = create_file(path!(mp; "file")).unwrap();
     file.write_all(&bytes).unwrap();
 
     let db_bytes: Vec<u8> = rt
@@ -102,15 +105,17 @@ fn unaligned_end() {
         .unwrap();
     assert!(bytes == db_bytes);
 
-    Test::cleanup(bg_sess);
+    Test::cleanup(bg_sess, mp);
 }
 
+// case 4
+#[test]
 fn unaligned_end_span() {
-    let Test { bg_sess, rt, pool } = Test::new();
+    let Test { bg_sess, rt, pool, mp, .. } = Test::new();
 
     let size = PAGE_SIZE + 512;
     let bytes: Vec<u8> = rand::random_iter().take(size).collect();
-    let mut file = create_file(path!(MP_PATH, "file")).unwrap();
+    let mut file = create_file(path!(mp; "file")).unwrap();
     file.write_all(&bytes).unwrap();
 
     let db_bytes: Vec<u8> = rt
@@ -118,5 +123,55 @@ fn unaligned_end_span() {
         .unwrap();
     assert_eq!(bytes, db_bytes);
 
-    Test::cleanup(bg_sess);
+    Test::cleanup(bg_sess, mp);
+}
+
+#[test]
+fn dedup() {
+    let Test { bg_sess, rt, pool, mp, .. } = Test::new();
+
+    let bytes: Vec<u8> = rand::random_iter().take(PAGE_SIZE * 2).collect();
+    for name in ["file1", "file2"] {
+        create_file(path!(mp; name))
+            .unwrap()
+            .write_all(&bytes)
+            .unwrap();
+    }
+
+    let chunk_refs = || -> Vec<i64> {
+        rt.block_on(query_scalar("SELECT refs FROM chunks").fetch_all(&pool))
+            .unwrap()
+    };
+    assert_eq!(chunk_refs(), vec![2, 2]);
+
+    remove_file(path!(mp; "file1")).unwrap();
+    assert_eq!(chunk_refs(), vec![1, 1]);
+
+    remove_file(path!(mp; "file2")).unwrap();
+    assert_eq!(chunk_refs(), Vec::<i64>::new());
+
+    Test::cleanup(bg_sess, mp);
+}
+
+// database file created by `ptfs mkfs`, written through another connection than the test's
+#[test]
+fn file_database() {
+    let Test { bg_sess, rt, pool, mp, .. } = Test::mount(Setup {
+        mkfs: Some(&[]),
+        ..Default::default()
+    });
+
+    let bytes: Vec<u8> = rand::random_iter().take(PAGE_SIZE * 3 + 512).collect();
+    create_file(path!(mp; "file"))
+        .unwrap()
+        .write_all(&bytes)
+        .unwrap();
+    assert_eq!(read(path!(mp; "file")).unwrap(), bytes);
+
+    let db_bytes: Vec<u8> = rt
+        .block_on(read_file_query!().bind(2).fetch_one(&pool))
+        .unwrap();
+    assert_eq!(bytes, db_bytes);
+
+    Test::cleanup(bg_sess, mp);
 }